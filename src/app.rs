@@ -1,6 +1,7 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
-use crate::state::State;
+use crate::state::{State, TITLE};
+use wgpu_raymarcher::{hdr::HdrImage, Particle, Scene, Shaders};
 use winit::{
 	application::ApplicationHandler,
 	dpi::PhysicalSize,
@@ -9,9 +10,36 @@ use winit::{
 	window::{Window, WindowId},
 };
 
-#[derive(Default)]
+/// Startup settings of the interactive viewer.
+pub struct WindowOptions {
+	pub size: (u32, u32),
+	pub present_mode: wgpu::PresentMode,
+	pub scene: Scene,
+	pub shaders: Shaders,
+	/// Where the scene is written when asked to save it.
+	pub save_path: Option<PathBuf>,
+}
+
 pub struct App {
 	state: Option<State>,
+	particles: Vec<Particle>,
+	environment: Option<HdrImage>,
+	options: WindowOptions,
+}
+
+impl App {
+	pub fn new(
+		particles: Vec<Particle>,
+		environment: Option<HdrImage>,
+		options: WindowOptions,
+	) -> Self {
+		Self {
+			state: None,
+			particles,
+			environment,
+			options,
+		}
+	}
 }
 
 impl ApplicationHandler for App {
@@ -19,13 +47,29 @@ impl ApplicationHandler for App {
 		let window = Arc::new(
 			event_loop
 				.create_window(
-					Window::default_attributes().with_inner_size(PhysicalSize::new(600, 600)),
+					Window::default_attributes()
+						.with_title(TITLE)
+						.with_inner_size(PhysicalSize::new(
+							self.options.size.0,
+							self.options.size.1,
+						)),
 				)
 				.unwrap(),
 		);
 
-		let state = pollster::block_on(State::new(window.clone()));
-		self.state = Some(state);
+		let state = pollster::block_on(State::new(
+			window.clone(),
+			&self.particles,
+			self.environment.as_ref(),
+			&self.options,
+		));
+		match state {
+			Ok(state) => self.state = Some(state),
+			Err(err) => {
+				eprintln!("Invalid shader: {err}");
+				std::process::exit(1);
+			}
+		}
 
 		window.request_redraw();
 	}
@@ -38,7 +82,7 @@ impl ApplicationHandler for App {
 	) {
 		let state = self.state.as_mut().unwrap();
 		match event {
-			winit::event::DeviceEvent::MouseMotion { delta } => state.mouse(delta.into()),
+			winit::event::DeviceEvent::MouseMotion { delta } => state.mouse(delta),
 			winit::event::DeviceEvent::Key(raw_key_event) => state.keyboard(raw_key_event),
 			_ => {}
 		}
@@ -57,20 +101,18 @@ impl ApplicationHandler for App {
 			WindowEvent::Resized(size) => {
 				appstate.resize(size);
 			}
-			WindowEvent::MouseInput { state, button, .. } => {
-				if state.is_pressed() && button == MouseButton::Left {
-					appstate.lock();
-				}
+			WindowEvent::MouseInput { state, button, .. }
+				if state.is_pressed() && button == MouseButton::Left =>
+			{
+				appstate.lock();
 			}
 			WindowEvent::KeyboardInput { event, .. } => {
-				match (event.physical_key, event.state.is_pressed()) {
-					(
-						winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::Escape),
-						true,
-					) => {
-						appstate.unlock();
-					}
-					_ => {}
+				if let (
+					winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::Escape),
+					true,
+				) = (event.physical_key, event.state.is_pressed())
+				{
+					appstate.unlock();
 				}
 			}
 			_ => (),
@@ -0,0 +1,284 @@
+use glam::UVec3;
+use wgpu::util::DeviceExt;
+
+use crate::readback::{Readback, Warning};
+
+// Must be the same as the ones in compute.wgsl
+pub const TILE_SIZE: UVec3 = UVec3::new(8, 8, 16);
+pub const MAX_PER_TILE: u32 = 1024;
+pub const WORKGROUP_SIZE: u32 = 64;
+
+/// How the froxel SDF is built from the particle buffer.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum SdfMethod {
+	/// Every voxel evaluates every particle, a bundle at a time through workgroup memory.
+	Bundled,
+	/// Particles are binned into froxel tiles first, every voxel only evaluates its tile.
+	#[default]
+	Binned,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BinningSettings {
+	/// Distance past a particle's radius and blend radius it is still binned within.
+	/// Voxels further than this from every particle store the margin instead of their distance.
+	pub margin: f32,
+}
+
+impl Default for BinningSettings {
+	fn default() -> Self {
+		Self { margin: 0.25 }
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BinningUniform {
+	margin: f32,
+	count: u32,
+}
+
+impl BinningUniform {
+	pub fn bytes(&self) -> &[u8] {
+		bytemuck::bytes_of(self)
+	}
+}
+
+/// Number of tiles along each axis of a froxel grid.
+pub fn tile_grid(width: u32, height: u32, depth: u32) -> UVec3 {
+	(UVec3::new(width, height, depth) + TILE_SIZE - 1) / TILE_SIZE
+}
+
+/// Size in bytes of the tile entry buffer of a `froxels` grid.
+pub fn tile_entry_bytes(froxels: UVec3) -> u64 {
+	let grid = tile_grid(froxels.x, froxels.y, froxels.z);
+	grid.as_u64vec3().element_product() * MAX_PER_TILE as u64 * std::mem::size_of::<u32>() as u64
+}
+
+/// Per froxel tile particle lists, bound as group 1 of every SDF compute pipeline.
+///
+/// The lists are only allocated once [`SdfMethod::Binned`] is used, until then the group
+/// binds a single tile so the other pipelines can still reach the particles through it.
+pub struct Binning {
+	pub settings: BinningSettings,
+	layout: wgpu::BindGroupLayout,
+	group: wgpu::BindGroup,
+	uniform_buffer: wgpu::Buffer,
+	overflow_buffer: wgpu::Buffer,
+	overflow: Readback,
+	overflow_warning: Warning,
+	grid: UVec3,
+	allocated: bool,
+	tiles: u32,
+	count: u32,
+}
+
+impl Binning {
+	pub fn new(device: &wgpu::Device, particles: &wgpu::Buffer, count: u32, grid: UVec3) -> Self {
+		let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility: wgpu::ShaderStages::COMPUTE,
+			ty: wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Storage { read_only },
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		};
+
+		let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Binning Layout Group"),
+			entries: &[
+				storage(0, true),
+				storage(1, false),
+				storage(2, false),
+				wgpu::BindGroupLayoutEntry {
+					binding: 3,
+					visibility: wgpu::ShaderStages::COMPUTE,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				storage(4, false),
+			],
+		});
+
+		let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("Binning Buffer"),
+			contents: bytemuck::bytes_of(&BinningUniform::default()),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		});
+
+		let overflow_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Tile Overflow Buffer"),
+			size: std::mem::size_of::<u32>() as u64,
+			usage: wgpu::BufferUsages::STORAGE
+				| wgpu::BufferUsages::COPY_SRC
+				| wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let (group, tiles) = create_group(
+			device,
+			&layout,
+			particles,
+			&uniform_buffer,
+			&overflow_buffer,
+			UVec3::ONE,
+		);
+
+		Self {
+			settings: Default::default(),
+			layout,
+			group,
+			uniform_buffer,
+			overflow_buffer,
+			overflow: Readback::new(device, "Tile Overflow Readback Buffer"),
+			overflow_warning: Warning::default(),
+			grid,
+			allocated: false,
+			tiles,
+			count,
+		}
+	}
+
+	/// Switches to a new tile grid, keeping the settings.
+	/// The lists are freed and only allocated again by the next [`Binning::allocate`].
+	pub fn resize(&mut self, device: &wgpu::Device, particles: &wgpu::Buffer, grid: UVec3) {
+		self.grid = grid;
+		if self.allocated {
+			(self.group, self.tiles) = create_group(
+				device,
+				&self.layout,
+				particles,
+				&self.uniform_buffer,
+				&self.overflow_buffer,
+				UVec3::ONE,
+			);
+			self.allocated = false;
+		}
+	}
+
+	/// Allocates the tile lists for the current grid unless they already are, before binning.
+	pub fn allocate(&mut self, device: &wgpu::Device, particles: &wgpu::Buffer) {
+		if !self.allocated {
+			(self.group, self.tiles) = create_group(
+				device,
+				&self.layout,
+				particles,
+				&self.uniform_buffer,
+				&self.overflow_buffer,
+				self.grid,
+			);
+			self.allocated = true;
+		}
+	}
+
+	/// Warns when tiles held more particles than they fit in a recent frame,
+	/// voxels of those tiles then miss some particles.
+	pub fn check_overflow(&mut self) {
+		if let Some(largest) = self.overflow.read() {
+			self.overflow_warning.update(largest > 0, || {
+				format!(
+					"a froxel tile holds {largest} particles but only {MAX_PER_TILE} are used, \
+					use finer froxels or the bundled SDF method"
+				)
+			});
+		}
+	}
+
+	/// Resets the overflow counter, before binning.
+	pub fn clear_overflow(&self, encoder: &mut wgpu::CommandEncoder) {
+		encoder.clear_buffer(&self.overflow_buffer, 0, None);
+	}
+
+	/// Reads the overflow counter back, after binning.
+	pub fn copy_overflow(&self, encoder: &mut wgpu::CommandEncoder) {
+		self.overflow.copy(encoder, &self.overflow_buffer, 0);
+	}
+
+	pub fn layout(&self) -> &wgpu::BindGroupLayout {
+		&self.layout
+	}
+
+	pub fn group(&self) -> &wgpu::BindGroup {
+		&self.group
+	}
+
+	pub fn uniform(&self) -> BinningUniform {
+		BinningUniform {
+			margin: self.settings.margin,
+			count: self.count,
+		}
+	}
+
+	pub fn write_uniform(&self, queue: &wgpu::Queue) {
+		queue.write_buffer(&self.uniform_buffer, 0, self.uniform().bytes());
+	}
+
+	/// Workgroups needed to clear every tile.
+	pub fn tile_workgroups(&self) -> u32 {
+		self.tiles.div_ceil(WORKGROUP_SIZE)
+	}
+
+	/// Workgroups needed to bin every particle.
+	pub fn particle_workgroups(&self) -> u32 {
+		self.count.div_ceil(WORKGROUP_SIZE)
+	}
+}
+
+/// Allocates the tile lists for `grid` and binds them, returning the group and number of tiles.
+fn create_group(
+	device: &wgpu::Device,
+	layout: &wgpu::BindGroupLayout,
+	particles: &wgpu::Buffer,
+	uniform_buffer: &wgpu::Buffer,
+	overflow_buffer: &wgpu::Buffer,
+	grid: UVec3,
+) -> (wgpu::BindGroup, u32) {
+	let tiles = grid.element_product();
+	let tile_counts = device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Tile Count Buffer"),
+		size: tiles as u64 * std::mem::size_of::<u32>() as u64,
+		usage: wgpu::BufferUsages::STORAGE,
+		mapped_at_creation: false,
+	});
+	let tile_entries = device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Tile Entry Buffer"),
+		size: tiles as u64 * MAX_PER_TILE as u64 * std::mem::size_of::<u32>() as u64,
+		usage: wgpu::BufferUsages::STORAGE,
+		mapped_at_creation: false,
+	});
+
+	let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Binning Group"),
+		layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: particles.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: tile_counts.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: tile_entries.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: uniform_buffer.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 4,
+				resource: overflow_buffer.as_entire_binding(),
+			},
+		],
+	});
+
+	(group, tiles)
+}
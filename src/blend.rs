@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+/// How the distances of neighbouring particles are combined into one field.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+	/// Hard union, every particle keeps its own silhouette.
+	#[default]
+	Min,
+	/// Quadratic polynomial smooth minimum.
+	Polynomial,
+	/// Exponential smooth minimum, blends with every particle in range.
+	Exponential,
+	/// Cubic polynomial smooth minimum, C2 continuous.
+	Cubic,
+}
+
+impl BlendMode {
+	// Must match the BLEND_* constants in compute.wgsl
+	fn id(self) -> u32 {
+		match self {
+			BlendMode::Min => 0,
+			BlendMode::Polynomial => 1,
+			BlendMode::Exponential => 2,
+			BlendMode::Cubic => 3,
+		}
+	}
+
+	pub fn next(self) -> Self {
+		match self {
+			BlendMode::Min => BlendMode::Polynomial,
+			BlendMode::Polynomial => BlendMode::Exponential,
+			BlendMode::Exponential => BlendMode::Cubic,
+			BlendMode::Cubic => BlendMode::Min,
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Blend {
+	pub mode: BlendMode,
+	pub radius: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BlendUniform {
+	mode: u32,
+	radius: f32,
+}
+
+impl Default for Blend {
+	fn default() -> Self {
+		Self {
+			mode: BlendMode::Min,
+			radius: 0.1,
+		}
+	}
+}
+
+impl Blend {
+	pub fn uniform(&self) -> BlendUniform {
+		BlendUniform {
+			mode: self.mode.id(),
+			radius: self.radius,
+		}
+	}
+
+	/// CPU reference of `blend` in `compute.wgsl`.
+	pub fn apply(&self, a: f32, b: f32) -> f32 {
+		let k = self.radius;
+		if k <= 0.0 {
+			return a.min(b);
+		}
+		match self.mode {
+			BlendMode::Min => a.min(b),
+			BlendMode::Polynomial => {
+				let h = (k - (a - b).abs()).max(0.0) / k;
+				a.min(b) - h * h * k * 0.25
+			}
+			BlendMode::Exponential => {
+				let m = a.min(b);
+				let r = (-(a - m) / k).exp2() + (-(b - m) / k).exp2();
+				m - k * r.log2()
+			}
+			BlendMode::Cubic => {
+				let h = (k - (a - b).abs()).max(0.0) / k;
+				a.min(b) - h * h * h * k / 6.0
+			}
+		}
+	}
+}
+
+impl BlendUniform {
+	pub fn bytes(&self) -> &[u8] {
+		bytemuck::bytes_of(self)
+	}
+}
+
+pub fn create_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+	device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		label: Some("Blend Buffer"),
+		contents: bytemuck::bytes_of(&BlendUniform::default()),
+		usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MODES: [BlendMode; 4] = [
+		BlendMode::Min,
+		BlendMode::Polynomial,
+		BlendMode::Exponential,
+		BlendMode::Cubic,
+	];
+	const PAIRS: [(f32, f32); 5] = [
+		(0.0, 0.0),
+		(0.3, 0.35),
+		(-0.2, 0.1),
+		(1.0, -1.0),
+		(0.5, 2.0),
+	];
+
+	#[test]
+	fn reduces_to_min_as_radius_vanishes() {
+		for mode in MODES {
+			for radius in [1e-3, 1e-5, 0.0] {
+				let blend = Blend { mode, radius };
+				for (a, b) in PAIRS {
+					let blended = blend.apply(a, b);
+					// The exponential one still subtracts up to `radius` from equal distances
+					assert!(
+						(blended - a.min(b)).abs() <= radius,
+						"{mode:?} with radius {radius} blends {a} and {b} to {blended}"
+					);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn is_symmetric() {
+		for mode in MODES {
+			let blend = Blend { mode, radius: 0.2 };
+			for (a, b) in PAIRS {
+				assert_eq!(
+					blend.apply(a, b),
+					blend.apply(b, a),
+					"{mode:?} of {a} and {b}"
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn never_exceeds_min() {
+		for mode in MODES {
+			for radius in [0.05, 0.2, 1.0] {
+				let blend = Blend { mode, radius };
+				for (a, b) in PAIRS {
+					assert!(
+						blend.apply(a, b) <= a.min(b),
+						"{mode:?} with radius {radius} of {a} and {b}"
+					);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn leaves_distant_distances_alone() {
+		// Only the exponential minimum reaches past the blend radius
+		for mode in [BlendMode::Polynomial, BlendMode::Cubic] {
+			let blend = Blend { mode, radius: 0.2 };
+			assert_eq!(blend.apply(0.5, 2.0), 0.5);
+		}
+	}
+}
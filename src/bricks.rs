@@ -0,0 +1,332 @@
+use glam::UVec3;
+use wgpu::util::DeviceExt;
+
+use crate::{
+	readback::{Readback, Warning},
+	sdf::WorldVolume,
+};
+
+// Must be the same as the one in compute.wgsl and shader.wgsl
+/// Samples along each axis of a brick. Neighbouring bricks share their boundary samples,
+/// so a brick can be filtered on its own without bleeding into its neighbours in the atlas.
+pub const BRICK_SIZE: u32 = 8;
+/// Most bricks an atlas can hold, 4 KiB each.
+pub const MAX_BRICKS: u32 = 65536;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BricksUniform {
+	cells: [u32; 3],
+	capacity: u32,
+	atlas: [u32; 3],
+	_pad: u32,
+}
+
+/// Number of bricks along each axis of the world volume.
+pub fn cell_grid(resolution: UVec3) -> UVec3 {
+	(resolution + BRICK_SIZE - 1) / BRICK_SIZE
+}
+
+/// Number of bricks along each axis of an atlas holding `capacity` bricks, close to a cube.
+pub fn atlas_grid(capacity: u32) -> UVec3 {
+	let side = (capacity as f64).cbrt().ceil() as u32;
+	UVec3::new(side, side, capacity.div_ceil(side * side))
+}
+
+/// Sparse storage of the world volume: every brick is either empty, with only the distance at
+/// its center kept to skip over it, or allocated a block of samples in an atlas texture.
+/// The atlas is the SDF texture of the renderer, the indirection grid lives here.
+pub struct Bricks {
+	compute_layout: wgpu::BindGroupLayout,
+	render_layout: wgpu::BindGroupLayout,
+	compute_group: wgpu::BindGroup,
+	render_group: wgpu::BindGroup,
+	args_buffer: wgpu::Buffer,
+	count_buffer: wgpu::Buffer,
+	count: Readback,
+	capacity_warning: Warning,
+	cells: UVec3,
+	capacity: u32,
+}
+
+impl Bricks {
+	pub fn new(device: &wgpu::Device, world: &WorldVolume) -> Self {
+		let entry = |binding, visibility, ty| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility,
+			ty: wgpu::BindingType::Buffer {
+				ty,
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		};
+		let storage = |binding| {
+			entry(
+				binding,
+				wgpu::ShaderStages::COMPUTE,
+				wgpu::BufferBindingType::Storage { read_only: false },
+			)
+		};
+
+		let compute_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Bricks Compute Layout Group"),
+			entries: &[
+				storage(0),
+				storage(1),
+				storage(2),
+				storage(3),
+				storage(4),
+				entry(
+					5,
+					wgpu::ShaderStages::COMPUTE,
+					wgpu::BufferBindingType::Uniform,
+				),
+			],
+		});
+
+		let render_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Bricks Render Layout Group"),
+			entries: &[
+				entry(
+					0,
+					wgpu::ShaderStages::FRAGMENT,
+					wgpu::BufferBindingType::Storage { read_only: true },
+				),
+				entry(
+					1,
+					wgpu::ShaderStages::FRAGMENT,
+					wgpu::BufferBindingType::Storage { read_only: true },
+				),
+				entry(
+					2,
+					wgpu::ShaderStages::FRAGMENT,
+					wgpu::BufferBindingType::Uniform,
+				),
+			],
+		});
+
+		let groups = create_groups(device, &compute_layout, &render_layout, world);
+
+		Self {
+			compute_layout,
+			render_layout,
+			compute_group: groups.compute,
+			render_group: groups.render,
+			args_buffer: groups.args,
+			count_buffer: groups.count,
+			count: Readback::new(device, "Brick Count Readback Buffer"),
+			capacity_warning: Warning::default(),
+			cells: cell_grid(world.resolution),
+			capacity: world.brick_capacity,
+		}
+	}
+
+	/// Reallocates the indirection grid and brick lists for a new volume.
+	pub fn resize(&mut self, device: &wgpu::Device, world: &WorldVolume) {
+		let groups = create_groups(device, &self.compute_layout, &self.render_layout, world);
+		self.compute_group = groups.compute;
+		self.render_group = groups.render;
+		self.args_buffer = groups.args;
+		self.count_buffer = groups.count;
+		self.cells = cell_grid(world.resolution);
+		self.capacity = world.brick_capacity;
+	}
+
+	pub fn compute_layout(&self) -> &wgpu::BindGroupLayout {
+		&self.compute_layout
+	}
+
+	pub fn render_layout(&self) -> &wgpu::BindGroupLayout {
+		&self.render_layout
+	}
+
+	pub fn compute_group(&self) -> &wgpu::BindGroup {
+		&self.compute_group
+	}
+
+	pub fn render_group(&self) -> &wgpu::BindGroup {
+		&self.render_group
+	}
+
+	/// Indirect dispatch filling every allocated brick, written by `cs_bricks_args`.
+	pub fn args(&self) -> &wgpu::Buffer {
+		&self.args_buffer
+	}
+
+	/// Warns when a recent bake needed more bricks than the atlas holds,
+	/// the ones past its capacity are then skipped over as if empty.
+	pub fn check_capacity(&mut self) {
+		if let Some(needed) = self.count.read() {
+			let capacity = self.capacity;
+			self.capacity_warning.update(needed > capacity, || {
+				format!(
+					"the world volume needs {needed} bricks but only {capacity} fit, \
+					raise `render.brick_capacity`"
+				)
+			});
+		}
+	}
+
+	/// Reads the number of bricks the last bake needed back, after marking them.
+	pub fn copy_count(&self, encoder: &mut wgpu::CommandEncoder) {
+		self.count.copy(encoder, &self.count_buffer, 0);
+	}
+
+	/// Size in voxels of the atlas texture.
+	pub fn atlas_size(&self) -> UVec3 {
+		atlas_grid(self.capacity) * BRICK_SIZE
+	}
+
+	/// Workgroups needed to classify every brick.
+	pub fn cell_workgroups(&self) -> (u32, u32, u32) {
+		(
+			self.cells.x.div_ceil(8),
+			self.cells.y.div_ceil(4),
+			self.cells.z.div_ceil(4),
+		)
+	}
+}
+
+struct Groups {
+	compute: wgpu::BindGroup,
+	render: wgpu::BindGroup,
+	args: wgpu::Buffer,
+	count: wgpu::Buffer,
+}
+
+fn create_groups(
+	device: &wgpu::Device,
+	compute_layout: &wgpu::BindGroupLayout,
+	render_layout: &wgpu::BindGroupLayout,
+	world: &WorldVolume,
+) -> Groups {
+	let cells = cell_grid(world.resolution);
+	let cell_count = cells.element_product() as u64;
+	let capacity = world.brick_capacity;
+	let word = std::mem::size_of::<u32>() as u64;
+
+	let storage = |label, size, usage| {
+		device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some(label),
+			size,
+			usage: wgpu::BufferUsages::STORAGE | usage,
+			mapped_at_creation: false,
+		})
+	};
+	let slots = storage(
+		"Brick Slot Buffer",
+		cell_count * word,
+		wgpu::BufferUsages::empty(),
+	);
+	let distances = storage(
+		"Brick Distance Buffer",
+		cell_count * word,
+		wgpu::BufferUsages::empty(),
+	);
+	let count = storage("Brick Count Buffer", word, wgpu::BufferUsages::COPY_SRC);
+	let args = storage("Brick Args Buffer", 3 * word, wgpu::BufferUsages::INDIRECT);
+	let owners = storage(
+		"Brick Owner Buffer",
+		capacity as u64 * word,
+		wgpu::BufferUsages::empty(),
+	);
+	let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		label: Some("Bricks Buffer"),
+		contents: bytemuck::bytes_of(&BricksUniform {
+			cells: cells.to_array(),
+			capacity,
+			atlas: atlas_grid(capacity).to_array(),
+			_pad: 0,
+		}),
+		usage: wgpu::BufferUsages::UNIFORM,
+	});
+
+	let compute = device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Bricks Compute Group"),
+		layout: compute_layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: slots.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: distances.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: count.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: args.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 4,
+				resource: owners.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 5,
+				resource: uniform_buffer.as_entire_binding(),
+			},
+		],
+	});
+
+	let render = device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Bricks Render Group"),
+		layout: render_layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: slots.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: distances.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: uniform_buffer.as_entire_binding(),
+			},
+		],
+	});
+
+	Groups {
+		compute,
+		render,
+		args,
+		count,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cell_grid_covers_resolution() {
+		assert_eq!(cell_grid(UVec3::new(128, 1, 9)), UVec3::new(16, 1, 2));
+		assert_eq!(cell_grid(UVec3::splat(BRICK_SIZE)), UVec3::ONE);
+		assert_eq!(cell_grid(UVec3::splat(BRICK_SIZE + 1)), UVec3::splat(2));
+	}
+
+	#[test]
+	fn atlas_grid_holds_capacity() {
+		for capacity in [1, 2, 7, 8, 9, 26, 27, 28, 1000, 4096, 4097, MAX_BRICKS] {
+			let grid = atlas_grid(capacity);
+			assert!(grid.element_product() >= capacity, "{capacity}: {grid}");
+			// Close to a cube, at most one layer of bricks short of it
+			assert_eq!(grid.x, grid.y, "{capacity}: {grid}");
+			assert!(grid.z <= grid.x, "{capacity}: {grid}");
+			assert!(
+				(grid.z - 1) * grid.x * grid.y < capacity,
+				"{capacity}: {grid} has an empty layer"
+			);
+		}
+		assert_eq!(atlas_grid(1), UVec3::ONE);
+		assert_eq!(atlas_grid(4096), UVec3::splat(16));
+		// The largest atlas still fits the guaranteed 3D texture size
+		assert!(atlas_grid(MAX_BRICKS).max_element() * BRICK_SIZE <= 2048);
+	}
+}
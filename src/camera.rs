@@ -6,7 +6,15 @@ use std::f32::{
 use glam::{Mat4, Vec2, Vec3};
 use wgpu::util::DeviceExt;
 
-#[derive(Debug, Default)]
+/// Distance of the near clipping plane, where the froxel grid starts.
+pub const NEAR: f32 = 0.05;
+/// Distance of the far clipping plane, where the froxel grid ends.
+pub const FAR: f32 = 20.0;
+
+// Logarithmic slicing divides by the near distance
+const _: () = assert!(NEAR > 0.0 && NEAR < FAR);
+
+#[derive(Debug, Default, Copy, Clone)]
 pub struct Camera {
 	pub aspect: f32,
 	pub fov: f32,
@@ -29,6 +37,9 @@ pub struct CameraUniform {
 	view: [f32; 16],
 	inv_proj: [f32; 16],
 	inv_view: [f32; 16],
+	near: f32,
+	far: f32,
+	_pad: [f32; 2],
 }
 
 impl Camera {
@@ -66,7 +77,7 @@ impl Camera {
 		Mat4::look_to_lh(self.position, forward, up)
 	}
 	pub fn projection_matrix(&self) -> Mat4 {
-		Mat4::perspective_lh(self.fov, self.aspect, 0.05, 20.0)
+		Mat4::perspective_lh(self.fov, self.aspect, NEAR, FAR)
 	}
 	pub fn uniform(&self) -> CameraUniform {
 		let forward = self.look_dir();
@@ -86,6 +97,9 @@ impl Camera {
 			view: view.to_cols_array(),
 			inv_proj: proj.inverse().to_cols_array(),
 			inv_view: view.inverse().to_cols_array(),
+			near: NEAR,
+			far: FAR,
+			_pad: [0.0; 2],
 		}
 	}
 
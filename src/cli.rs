@@ -0,0 +1,266 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use glam::{UVec3, Vec3};
+
+use wgpu_raymarcher::{
+	scene::{self, ParticleSource, Scene},
+	shaders::Shaders,
+};
+
+/// Raymarches a set of particles through a frustum-aligned SDF grid.
+///
+/// Options left out fall back to the scene file, if any, and then to the built-in defaults.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+	/// Particle file (.xyz, .csv, .txt or .ply), replacing the particles of the scene.
+	pub particles: Option<PathBuf>,
+
+	/// Scene file (.toml) to start from.
+	#[arg(long, value_name = "TOML")]
+	pub scene: Option<PathBuf>,
+
+	/// Equirectangular Radiance HDR (.hdr) lighting the scene in place of the sky gradient.
+	#[arg(long, value_name = "HDR")]
+	pub environment: Option<PathBuf>,
+
+	/// Writes the resolved scene to this file. In the viewer, `O` writes it again.
+	#[arg(long, value_name = "TOML")]
+	pub save_scene: Option<PathBuf>,
+
+	/// Window or image size in pixels.
+	#[arg(
+		long,
+		value_name = "WxH",
+		default_value = "600x600",
+		value_parser = parse_size,
+	)]
+	pub size: (u32, u32),
+
+	/// Resolution of the froxel grid the SDF is baked into [default: 64x64x256].
+	#[arg(long, value_name = "WxHxD", value_parser = parse_froxels)]
+	pub froxels: Option<UVec3>,
+
+	/// Size the froxel grid to the window instead, one froxel every N pixels; keeps the depth of --froxels.
+	#[arg(long, value_name = "N", value_parser = parse_froxel_pixels)]
+	pub froxel_pixels: Option<u32>,
+
+	/// How frames are presented to the window.
+	#[arg(long, value_enum, default_value_t = PresentMode::Immediate)]
+	pub present_mode: PresentMode,
+
+	/// Starting camera position [default: 0,0,-5].
+	#[arg(
+		long,
+		value_name = "X,Y,Z",
+		value_parser = parse_vec3,
+		allow_hyphen_values = true,
+	)]
+	pub camera: Option<Vec3>,
+
+	/// Starting camera yaw in degrees [default: 0].
+	#[arg(
+		long,
+		value_name = "DEGREES",
+		value_parser = parse_yaw,
+		allow_hyphen_values = true,
+	)]
+	pub yaw: Option<f32>,
+
+	/// Starting camera pitch in degrees, within [-90, 90] [default: 0].
+	#[arg(
+		long,
+		value_name = "DEGREES",
+		value_parser = parse_pitch,
+		allow_hyphen_values = true,
+	)]
+	pub pitch: Option<f32>,
+
+	/// Vertical field of view in degrees, within (0, 180) [default: 60].
+	#[arg(long, value_name = "DEGREES", value_parser = parse_fov)]
+	pub fov: Option<f32>,
+
+	/// Reads the WGSL shaders from this directory instead of the copies built
+	/// into the binary, e.g. `src` while working on them. The viewer reloads
+	/// `compute.wgsl` and `shader.wgsl` whenever they are saved.
+	#[arg(long, value_name = "DIR")]
+	pub shader_dir: Option<PathBuf>,
+
+	/// Render offscreen without opening a window and write PNGs instead.
+	#[arg(long)]
+	pub headless: bool,
+
+	/// Number of frames to render in headless mode, stepped at a fixed rate.
+	#[arg(
+		long,
+		default_value_t = 1,
+		requires = "headless",
+		value_parser = clap::value_parser!(u32).range(1..),
+	)]
+	pub frames: u32,
+
+	/// Image written in headless mode. With more than one frame, the frame
+	/// number is appended to the file name.
+	#[arg(
+		long,
+		value_name = "PNG",
+		default_value = "out.png",
+		requires = "headless"
+	)]
+	pub output: PathBuf,
+}
+
+impl Cli {
+	pub fn shaders(&self) -> Shaders {
+		match &self.shader_dir {
+			Some(dir) => Shaders::from_dir(dir.clone()),
+			None => Shaders::default(),
+		}
+	}
+
+	/// Overrides the parts of `scene` given on the command line.
+	pub fn apply(&self, scene: &mut Scene) {
+		if let Some(path) = &self.particles {
+			scene.particles = vec![ParticleSource::File { path: path.clone() }];
+		}
+		if let Some(path) = &self.environment {
+			scene.background.environment = Some(path.clone());
+		}
+		if let Some(froxels) = self.froxels {
+			scene.render.froxels = froxels;
+		}
+		if let Some(pixels) = self.froxel_pixels {
+			scene.render.froxel_pixels = Some(pixels);
+		}
+		if let Some(position) = self.camera {
+			scene.camera.position = position;
+		}
+		if let Some(yaw) = self.yaw {
+			scene.camera.yaw = yaw;
+		}
+		if let Some(pitch) = self.pitch {
+			scene.camera.pitch = pitch;
+		}
+		if let Some(fov) = self.fov {
+			scene.camera.fov = fov;
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+pub enum PresentMode {
+	AutoVsync,
+	AutoNoVsync,
+	Fifo,
+	FifoRelaxed,
+	Immediate,
+	Mailbox,
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+	fn from(mode: PresentMode) -> Self {
+		match mode {
+			PresentMode::AutoVsync => wgpu::PresentMode::AutoVsync,
+			PresentMode::AutoNoVsync => wgpu::PresentMode::AutoNoVsync,
+			PresentMode::Fifo => wgpu::PresentMode::Fifo,
+			PresentMode::FifoRelaxed => wgpu::PresentMode::FifoRelaxed,
+			PresentMode::Immediate => wgpu::PresentMode::Immediate,
+			PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+		}
+	}
+}
+
+/// Parses `N` values separated by `separator`, each of which must be non-zero.
+fn parse_dims<const N: usize>(value: &str, separator: char) -> Result<[u32; N], String> {
+	let dims: Vec<u32> = value
+		.split(separator)
+		.map(|dim| {
+			dim.trim()
+				.parse::<u32>()
+				.map_err(|err| format!("`{dim}`: {err}"))
+		})
+		.collect::<Result<_, _>>()?;
+	let dims: [u32; N] = dims
+		.try_into()
+		.map_err(|dims: Vec<u32>| format!("expected {N} dimensions, found {}", dims.len()))?;
+	if dims.contains(&0) {
+		return Err("dimensions must be greater than zero".into());
+	}
+	Ok(dims)
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+	let [width, height] = parse_dims(value, 'x')?;
+	Ok((width, height))
+}
+
+fn parse_froxels(value: &str) -> Result<UVec3, String> {
+	let froxels = UVec3::from_array(parse_dims(value, 'x')?);
+	scene::check_froxels(froxels)?;
+	Ok(froxels)
+}
+
+fn parse_froxel_pixels(value: &str) -> Result<u32, String> {
+	let pixels = value.parse().map_err(|err| format!("`{value}`: {err}"))?;
+	scene::check_froxel_pixels(pixels)?;
+	Ok(pixels)
+}
+
+fn parse_vec3(value: &str) -> Result<Vec3, String> {
+	let values: Vec<f32> = value
+		.split(',')
+		.map(|v| {
+			v.trim()
+				.parse::<f32>()
+				.map_err(|err| format!("`{v}`: {err}"))
+		})
+		.collect::<Result<_, _>>()?;
+	match values[..] {
+		[x, y, z] => Ok(Vec3::new(x, y, z)),
+		_ => Err(format!("expected 3 values, found {}", values.len())),
+	}
+}
+
+fn parse_yaw(value: &str) -> Result<f32, String> {
+	let yaw: f32 = value.parse().map_err(|err| format!("{err}"))?;
+	scene::check_yaw(yaw)?;
+	Ok(yaw)
+}
+
+fn parse_pitch(value: &str) -> Result<f32, String> {
+	let pitch: f32 = value.parse().map_err(|err| format!("{err}"))?;
+	scene::check_pitch(pitch)?;
+	Ok(pitch)
+}
+
+fn parse_fov(value: &str) -> Result<f32, String> {
+	let fov: f32 = value.parse().map_err(|err| format!("{err}"))?;
+	scene::check_fov(fov)?;
+	Ok(fov)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn froxels_past_the_tile_lists_of_some_devices() {
+		// The renderer reduces grids whose tile lists don't fit the device it runs on
+		let cli = Cli::try_parse_from(["raymarcher", "--froxels", "512x512x256"]).unwrap();
+		assert_eq!(cli.froxels, Some(UVec3::new(512, 512, 256)));
+	}
+
+	#[test]
+	fn froxels_past_the_largest_grid() {
+		let err = Cli::try_parse_from(["raymarcher", "--froxels", "4096x64x64"]).unwrap_err();
+		assert!(err.to_string().contains("at most"), "{err}");
+	}
+
+	#[test]
+	fn yaw_must_be_finite() {
+		assert!(Cli::try_parse_from(["raymarcher", "--yaw", "inf"]).is_err());
+		let cli = Cli::try_parse_from(["raymarcher", "--yaw", "-90"]).unwrap();
+		assert_eq!(cli.yaw, Some(-90.0));
+	}
+}
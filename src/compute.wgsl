@@ -10,56 +10,304 @@ struct Camera {
     view: mat4x4<f32>,
     inv_proj: mat4x4<f32>,
     inv_view: mat4x4<f32>,
+    near: f32,
+    far: f32,
+};
+
+struct Slicing {
+    mode: u32,
+};
+
+struct Volume {
+    min: vec3<f32>,
+    space: u32,
+    max: vec3<f32>,
+};
+
+struct Grid {
+    min: vec3<f32>,
+    count: u32,
+    max: vec3<f32>,
+    margin: f32,
+    cells: vec3<u32>,
+};
+
+struct Bricks {
+    cells: vec3<u32>,
+    capacity: u32,
+    atlas: vec3<u32>,
+};
+
+struct Blend {
+    mode: u32,
+    radius: f32,
+};
+
+struct Binning {
+    margin: f32,
+    count: u32,
 };
 
 struct Particle {
     position: vec3<f32>,
-    radius: f32
+    radius: f32,
+    color: vec3<f32>,
+    material: u32,
 }
 
 // Must be the same as the one in particle.rs
-const BUNDLE_SIZE = 32;
+// The last bundle is padded with far away sentinel particles.
+const BUNDLE_SIZE = 32u;
+
+// Must match BlendMode::id in blend.rs
+const BLEND_MIN = 0u;
+const BLEND_POLYNOMIAL = 1u;
+const BLEND_EXPONENTIAL = 2u;
+const BLEND_CUBIC = 3u;
+
+// Must be the same as the ones in binning.rs
+const TILE_SIZE = vec3(8u, 8u, 16u);
+const MAX_PER_TILE = 1024u;
+const WORKGROUP_SIZE = 64;
+
+// Must be the same as the one in grid.rs
+const MAX_PER_CELL = 256u;
+
+// Must be the same as the one in bricks.rs
+const BRICK_SIZE = 8u;
+// Voxels around the surface within which bricks are still allocated,
+// so that normals sampled next to the surface stay within allocated bricks.
+const BRICK_MARGIN = 2.0;
+
+// Value of voxels that no particle has been merged into yet.
+// Kept well within the range of a 16-bit float.
+const FAR = 1000.0;
 
 @group(0) @binding(0)
-var<storage> particles: array<Particle, BUNDLE_SIZE>;
+var sdf_tex_write: texture_storage_3d<rgba16float, write>;
+
+@group(0) @binding(5)
+var material_tex_write: texture_storage_3d<r32uint, write>;
 
 @group(0) @binding(1)
-var sdf_tex_write: texture_storage_3d<rgba16float, write>;
+var<uniform> u_camera: Camera;
 
 @group(0) @binding(2)
-var sdf_tex_read: texture_3d<f32>;
+var<uniform> u_blend: Blend;
 
 @group(0) @binding(3)
-var<uniform> u_camera: Camera;
+var<uniform> u_slicing: Slicing;
+
+@group(0) @binding(4)
+var<uniform> u_volume: Volume;
+
+@group(1) @binding(0)
+var<storage> all_particles: array<Particle>;
+
+@group(1) @binding(1)
+var<storage, read_write> tile_counts: array<atomic<u32>>;
+
+@group(1) @binding(2)
+var<storage, read_write> tile_entries: array<u32>;
+
+@group(1) @binding(3)
+var<uniform> u_binning: Binning;
+
+// Largest number of particles binned into a tile that didn't fit, 0 if all of them did
+@group(1) @binding(4)
+var<storage, read_write> tile_overflow: atomic<u32>;
+
+// Slot of every brick of the volume in the atlas plus one, 0 when empty
+@group(2) @binding(0)
+var<storage, read_write> brick_slots: array<u32>;
+
+// Distance at the center of every brick of the volume
+@group(2) @binding(1)
+var<storage, read_write> brick_distances: array<f32>;
+
+@group(2) @binding(2)
+var<storage, read_write> brick_count: atomic<u32>;
+
+// Indirect dispatch of `cs_bricks_fill`
+@group(2) @binding(3)
+var<storage, read_write> brick_args: array<u32, 3>;
+
+// Brick of the volume every slot of the atlas was allocated to
+@group(2) @binding(4)
+var<storage, read_write> brick_owners: array<u32>;
+
+@group(2) @binding(5)
+var<uniform> u_bricks: Bricks;
+
+@group(3) @binding(0)
+var<storage, read_write> cell_counts: array<atomic<u32>>;
 
-fn smin(d1: f32, d2: f32) -> f32 {
-    let k = 0.1;
-    let h = clamp(0.5 + 0.5 * (d2 - d1) / k, 0.0, 1.0);
-    return mix(d2, d1, h) - k * h * (1.0 - h);
+@group(3) @binding(1)
+var<storage, read_write> cell_entries: array<u32>;
+
+@group(3) @binding(2)
+var<uniform> u_grid: Grid;
+
+// Largest number of particles binned into a cell that didn't fit, 0 if all of them did
+@group(3) @binding(3)
+var<storage, read_write> cell_overflow: atomic<u32>;
+
+fn smin_polynomial(a: f32, b: f32, k: f32) -> f32 {
+    let h = max(k - abs(a - b), 0.0) / k;
+    return min(a, b) - h * h * k * 0.25;
+}
+
+fn smin_exponential(a: f32, b: f32, k: f32) -> f32 {
+    // Shifted by the minimum so far away voxels don't underflow to log2(0)
+    let m = min(a, b);
+    let r = exp2(-(a - m) / k) + exp2(-(b - m) / k);
+    return m - k * log2(r);
+}
+
+fn smin_cubic(a: f32, b: f32, k: f32) -> f32 {
+    let h = max(k - abs(a - b), 0.0) / k;
+    return min(a, b) - h * h * h * k * (1.0 / 6.0);
+}
+
+fn blend(a: f32, b: f32) -> f32 {
+    let k = u_blend.radius;
+    if k <= 0.0 {
+        return min(a, b);
+    }
+    switch u_blend.mode {
+        case BLEND_POLYNOMIAL: {
+            return smin_polynomial(a, b, k);
+        }
+        case BLEND_EXPONENTIAL: {
+            return smin_exponential(a, b, k);
+        }
+        case BLEND_CUBIC: {
+            return smin_cubic(a, b, k);
+        }
+        default: {
+            return min(a, b);
+        }
+    }
+}
+
+// How much of the colour of a particle at distance `b` is blended into a value at distance `a`.
+fn blend_weight(a: f32, b: f32) -> f32 {
+    let k = u_blend.radius;
+    if k <= 0.0 || u_blend.mode == BLEND_MIN {
+        return select(0.0, 1.0, b < a);
+    }
+    if u_blend.mode == BLEND_EXPONENTIAL {
+        let m = min(a, b);
+        let wa = exp2(-(a - m) / k);
+        let wb = exp2(-(b - m) / k);
+        return wb / (wa + wb);
+    }
+    return saturate(0.5 + 0.5 * (a - b) / k);
 }
 
 fn sdf_particle(p: vec3<f32>, particle: Particle) -> f32 {
-    return length(p - particle.position) - 0.1;
+    return length(p - particle.position) - particle.radius;
 }
 
-fn sdf(p: vec3<f32>) -> f32 {
-    var curr = sdf_particle(p, particles[0]);
-    for (var i = 1; i < BUNDLE_SIZE; i++) {
-        curr = min(curr, sdf_particle(p, particles[i]));
+// What a voxel stores: the blended distance and colour, and the material of the nearest particle.
+struct SdfSample {
+    distance: f32,
+    color: vec3<f32>,
+    material: u32,
+};
+
+fn empty_sample() -> SdfSample {
+    return SdfSample(FAR, vec3(0.0), 0u);
+}
+
+fn merge(sample: SdfSample, p: vec3<f32>, particle: Particle) -> SdfSample {
+    let d = sdf_particle(p, particle);
+    return SdfSample(
+        blend(sample.distance, d),
+        mix(sample.color, particle.color, blend_weight(sample.distance, d)),
+        select(sample.material, particle.material, d < sample.distance),
+    );
+}
+
+fn store(id: vec3<u32>, sample: SdfSample) {
+    textureStore(sdf_tex_write, vec3<i32>(id), vec4<f32>(sample.distance, sample.color));
+    textureStore(material_tex_write, vec3<i32>(id), vec4<u32>(sample.material, 0u, 0u, 0u));
+}
+
+// The bundle of particles currently being evaluated by the workgroup
+var<workgroup> bundle: array<Particle, BUNDLE_SIZE>;
+
+// Folds every particle of the current bundle into `sample`.
+fn sdf(p: vec3<f32>, sample: SdfSample) -> SdfSample {
+    var curr = sample;
+    for (var i = 0u; i < BUNDLE_SIZE; i++) {
+        curr = merge(curr, p, bundle[i]);
     }
     return curr;
 }
 
+// Must match Slicing::id in sdf.rs. The slicing functions below must be the same as the ones
+// in shader.wgsl, and match the CPU references of Slicing
+const SLICING_NDC_LINEAR = 0u;
+const SLICING_VIEW_LINEAR = 1u;
+const SLICING_LOGARITHMIC = 2u;
+
+// Normalized device depth of a view space depth, as projected by `perspective_lh`.
+fn view_to_ndc_depth(z: f32) -> f32 {
+    return u_camera.far / (u_camera.far - u_camera.near) * (1.0 - u_camera.near / z);
+}
+
+fn ndc_to_view_depth(d: f32) -> f32 {
+    return u_camera.near / (1.0 - d * (u_camera.far - u_camera.near) / u_camera.far);
+}
+
+// View space depth at a fraction `s` of the way through the slices of the froxel grid.
+fn slice_to_view_depth(s: f32) -> f32 {
+    switch u_slicing.mode {
+        case SLICING_VIEW_LINEAR: {
+            return mix(u_camera.near, u_camera.far, s);
+        }
+        case SLICING_LOGARITHMIC: {
+            return u_camera.near * pow(u_camera.far / u_camera.near, s);
+        }
+        default: {
+            return ndc_to_view_depth(s);
+        }
+    }
+}
+
+// Inverse of `slice_to_view_depth`, 0 at the near plane and 1 at the far one.
+fn view_depth_to_slice(z: f32) -> f32 {
+    switch u_slicing.mode {
+        case SLICING_VIEW_LINEAR: {
+            return (z - u_camera.near) / (u_camera.far - u_camera.near);
+        }
+        case SLICING_LOGARITHMIC: {
+            return log(z / u_camera.near) / log(u_camera.far / u_camera.near);
+        }
+        default: {
+            return view_to_ndc_depth(z);
+        }
+    }
+}
+
+fn slice_to_ndc_depth(s: f32) -> f32 {
+    if u_slicing.mode == SLICING_NDC_LINEAR {
+        return s;
+    }
+    return view_to_ndc_depth(slice_to_view_depth(s));
+}
+
 // Converts a position in normalized screen space to world space.
 // In normalized screen space, (0,0,0) is (left,bottom,near)
-// and (1,1,1) is (right,top,far).
+// and (1,1,1) is (right,top,far), with z spread according to `u_slicing`.
 fn screen_to_world(pos: vec3<f32>) -> vec3<f32> {
 
     // Converts normalized screen space to normalized device space
     let ndc = vec3(
         pos.x * 2.0 - 1.0,
         pos.y * 2.0 - 1.0,
-        pos.z
+        slice_to_ndc_depth(pos.z)
     );
     
     // Reverts the projection matrix
@@ -73,30 +321,271 @@ fn screen_to_world(pos: vec3<f32>) -> vec3<f32> {
     return world_pos;
 }
 
+// Evaluates every particle at `p`, a bundle at a time. Every invocation of
+// the workgroup must call it, as they take turns loading the bundles.
+fn sdf_bundled(p: vec3<f32>, local: u32) -> SdfSample {
+    // Every bundle is folded into the same value, so the result doesn't
+    // depend on how the particles are split into bundles.
+    var value = empty_sample();
+    let bundles = arrayLength(&all_particles) / BUNDLE_SIZE;
+    for (var b = 0u; b < bundles; b++) {
+        if local < BUNDLE_SIZE {
+            bundle[local] = all_particles[b * BUNDLE_SIZE + local];
+        }
+        workgroupBarrier();
+        value = sdf(p, value);
+        workgroupBarrier();
+    }
+    return value;
+}
+
 @compute @workgroup_size(8,4,4)
-fn cs_clear(@builtin(global_invocation_id) id: vec3<u32>) {
-    textureStore(
-        sdf_tex_write,
-        vec3<i32>(id),
-        vec4<f32>(100000.0, 0.0, 0.0, 0.0)
-    );
+fn cs_sdf(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32
+) {
+    let dims = textureDimensions(sdf_tex_write);
+    let coord = vec3<f32>(id) + vec3<f32>(0.5, 0.5, 0.5); // center of voxel
+    let p = screen_to_world(coord / vec3<f32>(dims));
+    let value = sdf_bundled(p, local);
+
+    if all(id < dims) {
+        store(id, value);
+    }
 }
 
+// Bakes the axis-aligned world space box of `u_volume` instead of the frustum.
 @compute @workgroup_size(8,4,4)
-fn cs_sdf(@builtin(global_invocation_id) id: vec3<u32>) {
-    var value = textureLoad(
-        sdf_tex_read,
-        vec3<i32>(id),
-        0
-    );
+fn cs_sdf_world(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32
+) {
+    let dims = textureDimensions(sdf_tex_write);
+    let coord = vec3<f32>(id) + vec3<f32>(0.5, 0.5, 0.5); // center of voxel
+    let p = mix(u_volume.min, u_volume.max, coord / vec3<f32>(dims));
+    let value = sdf_bundled(p, local);
+
+    if all(id < dims) {
+        store(id, value);
+    }
+}
+
+// Distance past a particle's radius within which it must be binned into a tile.
+fn bin_reach() -> f32 {
+    return u_blend.radius + u_binning.margin;
+}
+
+fn tile_grid() -> vec3<u32> {
+    return (textureDimensions(sdf_tex_write) + TILE_SIZE - 1u) / TILE_SIZE;
+}
+
+fn tile_index(tile: vec3<u32>) -> u32 {
+    let grid = tile_grid();
+    return tile.x + grid.x * (tile.y + grid.y * tile.z);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_clear_tiles(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= arrayLength(&tile_counts) {
+        return;
+    }
+    atomicStore(&tile_counts[id.x], 0u);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_bin_particles(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= u_binning.count {
+        return;
+    }
+    let particle = all_particles[i];
+    let reach = particle.radius + bin_reach();
+
+    // Normalized screen space bounds of the corners of the particle's bounding box
+    var lo = vec3(1.0);
+    var hi = vec3(0.0);
+    var behind = 0;
+    for (var c = 0u; c < 8u; c++) {
+        let corner_dir = vec3<f32>(vec3(c & 1u, (c >> 1u) & 1u, (c >> 2u) & 1u)) * 2.0 - 1.0;
+        let corner = particle.position + reach * corner_dir;
+        let clip = u_camera.proj * u_camera.view * vec4(corner, 1.0);
+        if clip.w <= 0.0 {
+            behind++;
+            continue;
+        }
+        let ndc = clip.xyz / clip.w;
+        // The w of a perspective_lh projection is the view space depth
+        let screen = vec3(ndc.xy * 0.5 + 0.5, view_depth_to_slice(clip.w));
+        lo = min(lo, screen);
+        hi = max(hi, screen);
+    }
+    if behind == 8 {
+        return;
+    }
+    if behind > 0 {
+        // The box straddles the camera plane, its projection is unbounded
+        lo = vec3(0.0);
+        hi = vec3(1.0, 1.0, hi.z);
+    }
+    if any(hi < vec3(0.0)) || any(lo > vec3(1.0)) {
+        return;
+    }
+
     let size = vec3<f32>(textureDimensions(sdf_tex_write));
+    let grid = tile_grid();
+    let tile_lo = vec3<u32>(saturate(lo) * size) / TILE_SIZE;
+    let tile_hi = min(vec3<u32>(saturate(hi) * size) / TILE_SIZE, grid - 1u);
+    for (var z = tile_lo.z; z <= tile_hi.z; z++) {
+        for (var y = tile_lo.y; y <= tile_hi.y; y++) {
+            for (var x = tile_lo.x; x <= tile_hi.x; x++) {
+                let tile = tile_index(vec3(x, y, z));
+                let slot = atomicAdd(&tile_counts[tile], 1u);
+                // Particles past the capacity of a tile are dropped from it, the renderer warns
+                if slot < MAX_PER_TILE {
+                    tile_entries[tile * MAX_PER_TILE + slot] = i;
+                } else {
+                    atomicMax(&tile_overflow, slot + 1u);
+                }
+            }
+        }
+    }
+}
+
+@compute @workgroup_size(8,4,4)
+fn cs_sdf_binned(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dims = textureDimensions(sdf_tex_write);
+    if any(id >= dims) {
+        return;
+    }
+    let tile = tile_index(id / TILE_SIZE);
     let coord = vec3<f32>(id) + vec3<f32>(0.5, 0.5, 0.5); // center of voxel
-    let norm = coord / size;
-    value.r = min(value.r, sdf(screen_to_world(norm)));
-    textureStore(
-        sdf_tex_write,
-        vec3<i32>(id),
-        value
-    );
+    let p = screen_to_world(coord / vec3<f32>(dims));
+
+    var value = empty_sample();
+    let count = min(atomicLoad(&tile_counts[tile]), MAX_PER_TILE);
+    for (var k = 0u; k < count; k++) {
+        let particle = all_particles[tile_entries[tile * MAX_PER_TILE + k]];
+        value = merge(value, p, particle);
+    }
+    // Every particle left out of this tile is further away than its reach
+    value.distance = min(value.distance, bin_reach());
+    store(id, value);
+}
+
+fn brick_size() -> vec3<f32> {
+    return (u_volume.max - u_volume.min) / vec3<f32>(u_bricks.cells);
+}
+
+fn brick_index(cell: vec3<u32>) -> u32 {
+    return cell.x + u_bricks.cells.x * (cell.y + u_bricks.cells.y * cell.z);
+}
+
+@compute @workgroup_size(1)
+fn cs_bricks_clear() {
+    atomicStore(&brick_count, 0u);
+}
+
+// Allocates a slot of the atlas to every brick close enough to the surface.
+@compute @workgroup_size(8,4,4)
+fn cs_bricks_mark(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32
+) {
+    let size = brick_size();
+    let center = u_volume.min + (vec3<f32>(id) + 0.5) * size;
+    let value = sdf_bundled(center, local).distance;
+    if any(id >= u_bricks.cells) {
+        return;
+    }
+
+    let i = brick_index(id);
+    let voxel = size / f32(BRICK_SIZE - 1u);
+    let reach = 0.5 * length(size) + BRICK_MARGIN * max(voxel.x, max(voxel.y, voxel.z));
+    var slot = 0u;
+    if abs(value) < reach {
+        let index = atomicAdd(&brick_count, 1u);
+        // Bricks past the capacity of the atlas are dropped and skipped over as if empty
+        if index < u_bricks.capacity {
+            brick_owners[index] = i;
+            slot = index + 1u;
+        }
+    }
+    brick_slots[i] = slot;
+    brick_distances[i] = value;
+}
+
+@compute @workgroup_size(1)
+fn cs_bricks_args() {
+    let bricks = min(atomicLoad(&brick_count), u_bricks.capacity);
+    // One workgroup of `cs_bricks_fill` covers 8x4x4 samples
+    brick_args[0] = BRICK_SIZE / 8u;
+    brick_args[1] = BRICK_SIZE / 4u;
+    brick_args[2] = bricks * BRICK_SIZE / 4u;
 }
 
+// Bakes the samples of every allocated brick into its slot of the atlas.
+// Bricks are stacked along z, so a workgroup never straddles two of them.
+@compute @workgroup_size(8,4,4)
+fn cs_bricks_fill(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32
+) {
+    let slot = id.z / BRICK_SIZE;
+    let sample = vec3(id.x, id.y, id.z % BRICK_SIZE);
+
+    let cells = u_bricks.cells;
+    let i = brick_owners[slot];
+    let cell = vec3(i % cells.x, (i / cells.x) % cells.y, i / (cells.x * cells.y));
+    let t = vec3<f32>(sample) / f32(BRICK_SIZE - 1u);
+    let p = u_volume.min + (vec3<f32>(cell) + t) * brick_size();
+    let value = sdf_bundled(p, local);
+
+    let atlas = u_bricks.atlas;
+    let origin = vec3(slot % atlas.x, (slot / atlas.x) % atlas.y, slot / (atlas.x * atlas.y));
+    store(origin * BRICK_SIZE + sample, value);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_clear_cells(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= arrayLength(&cell_counts) {
+        return;
+    }
+    atomicStore(&cell_counts[id.x], 0u);
+}
+
+// Adds every particle to the cells of the world grid its reach overlaps.
+// Particles out of reach of the grid are left out, the exact SDF is clipped to the world volume.
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_bin_cells(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= u_grid.count {
+        return;
+    }
+    let particle = all_particles[i];
+    let reach = particle.radius + u_blend.radius + u_grid.margin;
+
+    let cells = vec3<f32>(u_grid.cells);
+    let cell_size = (u_grid.max - u_grid.min) / cells;
+    let lo = (particle.position - reach - u_grid.min) / cell_size;
+    let hi = (particle.position + reach - u_grid.min) / cell_size;
+    if any(hi < vec3(0.0)) || any(lo >= cells) {
+        return;
+    }
+
+    let cell_lo = vec3<u32>(max(lo, vec3(0.0)));
+    let cell_hi = min(vec3<u32>(max(hi, vec3(0.0))), u_grid.cells - 1u);
+    for (var z = cell_lo.z; z <= cell_hi.z; z++) {
+        for (var y = cell_lo.y; y <= cell_hi.y; y++) {
+            for (var x = cell_lo.x; x <= cell_hi.x; x++) {
+                let cell = x + u_grid.cells.x * (y + u_grid.cells.y * z);
+                let slot = atomicAdd(&cell_counts[cell], 1u);
+                // Particles past the capacity of a cell are dropped from it, the renderer warns
+                if slot < MAX_PER_CELL {
+                    cell_entries[cell * MAX_PER_CELL + slot] = i;
+                } else {
+                    atomicMax(&cell_overflow, slot + 1u);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,565 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+	hdr::HdrImage,
+	shaders::{self, ShaderError, Shaders},
+};
+
+/// Widest the radiance of an environment is kept at, larger images are downsampled.
+pub const MAX_RADIANCE_WIDTH: u32 = 2048;
+/// Width of the top level of the reflections, one level per roughness step.
+pub const SPECULAR_WIDTH: u32 = 256;
+/// Levels of the reflections, from a mirror at the top to fully rough at the bottom.
+pub const SPECULAR_MIPS: u32 = 6;
+/// Width of the irradiance, which is smooth enough to store tiny.
+pub const IRRADIANCE_WIDTH: u32 = 32;
+/// Width of the radiance level the irradiance is integrated from.
+const IRRADIANCE_SOURCE_WIDTH: u32 = 64;
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct EnvironmentUniform {
+	enabled: u32,
+	intensity: f32,
+	specular_lod: f32,
+	_pad: u32,
+}
+
+impl EnvironmentUniform {
+	pub fn bytes(&self) -> &[u8] {
+		bytemuck::bytes_of(self)
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PrefilterUniform {
+	roughness: f32,
+	_pad: [u32; 3],
+}
+
+/// Equirectangular environment lighting the scene in place of the sky gradient once loaded,
+/// prefiltered on the GPU into the radiance shown in the background, the irradiance lighting
+/// diffuse surfaces and the reflections of increasingly rough ones. Bound as group 3 of the
+/// render pipeline.
+pub struct Environment {
+	/// Scale of the radiance of the environment.
+	pub intensity: f32,
+	render_layout: wgpu::BindGroupLayout,
+	render_group: wgpu::BindGroup,
+	downsample_layout: wgpu::BindGroupLayout,
+	prefilter_layout: wgpu::BindGroupLayout,
+	downsample_pipeline: wgpu::ComputePipeline,
+	irradiance_pipeline: wgpu::ComputePipeline,
+	specular_pipeline: wgpu::ComputePipeline,
+	sampler: wgpu::Sampler,
+	uniform_buffer: wgpu::Buffer,
+	/// Radiance, specular and irradiance textures bound for rendering.
+	textures: [wgpu::Texture; 3],
+	loaded: bool,
+}
+
+impl Environment {
+	/// Starts out without an environment, lit by the sky gradient.
+	pub fn new(device: &wgpu::Device, shaders: &Shaders) -> Result<Self, ShaderError> {
+		let shader = shaders.create_module(device, shaders::ENVIRONMENT)?;
+
+		let texture = |binding, visibility, filterable| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility,
+			ty: wgpu::BindingType::Texture {
+				sample_type: wgpu::TextureSampleType::Float { filterable },
+				view_dimension: wgpu::TextureViewDimension::D2,
+				multisampled: false,
+			},
+			count: None,
+		};
+		let sampler = |binding, visibility| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility,
+			ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+			count: None,
+		};
+		let uniform = |binding, visibility| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility,
+			ty: wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Uniform,
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		};
+		let storage = wgpu::BindGroupLayoutEntry {
+			binding: 1,
+			visibility: wgpu::ShaderStages::COMPUTE,
+			ty: wgpu::BindingType::StorageTexture {
+				access: wgpu::StorageTextureAccess::WriteOnly,
+				format: FORMAT,
+				view_dimension: wgpu::TextureViewDimension::D2,
+			},
+			count: None,
+		};
+		let compute = wgpu::ShaderStages::COMPUTE;
+		let fragment = wgpu::ShaderStages::FRAGMENT;
+
+		let render_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Environment Render Layout Group"),
+			entries: &[
+				sampler(0, fragment),
+				texture(1, fragment, true),
+				texture(2, fragment, true),
+				texture(3, fragment, true),
+				uniform(4, fragment),
+			],
+		});
+		let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Environment Downsample Layout Group"),
+			entries: &[texture(0, compute, false), storage],
+		});
+		let prefilter_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Environment Prefilter Layout Group"),
+			entries: &[
+				storage,
+				sampler(2, compute),
+				texture(3, compute, true),
+				uniform(4, compute),
+			],
+		});
+
+		let pipeline = |label, layout: &wgpu::BindGroupLayout, entry_point| {
+			let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+				label: Some(label),
+				bind_group_layouts: &[layout],
+				push_constant_ranges: &[],
+			});
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some(label),
+				layout: Some(&pipeline_layout),
+				module: &shader,
+				entry_point: Some(entry_point),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			})
+		};
+		let downsample_pipeline = pipeline(
+			"Compute Pipeline (Environment Downsample)",
+			&downsample_layout,
+			"cs_downsample",
+		);
+		let irradiance_pipeline = pipeline(
+			"Compute Pipeline (Environment Irradiance)",
+			&downsample_layout,
+			"cs_irradiance",
+		);
+		let specular_pipeline = pipeline(
+			"Compute Pipeline (Environment Specular)",
+			&prefilter_layout,
+			"cs_specular",
+		);
+
+		let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("Environment Sampler"),
+			address_mode_u: wgpu::AddressMode::Repeat,
+			address_mode_v: wgpu::AddressMode::ClampToEdge,
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			mipmap_filter: wgpu::FilterMode::Linear,
+			..Default::default()
+		});
+
+		let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("Environment Buffer"),
+			contents: bytemuck::bytes_of(&EnvironmentUniform::default()),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		});
+
+		// Never sampled until an environment is loaded
+		let placeholder = create_texture(device, "Environment Placeholder Texture", 1, 1, 1);
+		let placeholder_view = placeholder.create_view(&Default::default());
+		let render_group = create_render_group(
+			device,
+			&render_layout,
+			&sampler,
+			[&placeholder_view, &placeholder_view, &placeholder_view],
+			&uniform_buffer,
+		);
+
+		Ok(Self {
+			intensity: 1.0,
+			render_layout,
+			render_group,
+			downsample_layout,
+			prefilter_layout,
+			downsample_pipeline,
+			irradiance_pipeline,
+			specular_pipeline,
+			sampler,
+			uniform_buffer,
+			textures: [placeholder.clone(), placeholder.clone(), placeholder],
+			loaded: false,
+		})
+	}
+
+	/// Uploads `image` and prefilters it, replacing the sky gradient or the previous environment.
+	pub fn load(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, image: &HdrImage) {
+		let max_size = device.limits().max_texture_dimension_2d;
+		let mut halved;
+		let mut image = image;
+		while image.width.max(image.height) > max_size {
+			halved = image.halve();
+			image = &halved;
+		}
+
+		let source_texture = device.create_texture_with_data(
+			queue,
+			&wgpu::TextureDescriptor {
+				label: Some("Environment Source Texture"),
+				size: wgpu::Extent3d {
+					width: image.width,
+					height: image.height,
+					depth_or_array_layers: 1,
+				},
+				mip_level_count: 1,
+				sample_count: 1,
+				dimension: wgpu::TextureDimension::D2,
+				format: wgpu::TextureFormat::Rgba32Float,
+				usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+				view_formats: &[],
+			},
+			wgpu::util::TextureDataOrder::LayerMajor,
+			bytemuck::cast_slice(
+				&image
+					.pixels
+					.iter()
+					.map(|&[r, g, b]| [r, g, b, 1.0])
+					.collect::<Vec<_>>(),
+			),
+		);
+		let source = source_texture.create_view(&Default::default());
+
+		let width = image.width.min(MAX_RADIANCE_WIDTH);
+		let height = (image.height as u64 * width as u64 / image.width as u64).max(1) as u32;
+		let radiance_mips = u32::BITS - width.max(height).leading_zeros();
+		let radiance = create_texture(
+			device,
+			"Environment Radiance Texture",
+			width,
+			height,
+			radiance_mips,
+		);
+		let specular = create_texture(
+			device,
+			"Environment Specular Texture",
+			SPECULAR_WIDTH,
+			SPECULAR_WIDTH / 2,
+			SPECULAR_MIPS,
+		);
+		let irradiance = create_texture(
+			device,
+			"Environment Irradiance Texture",
+			IRRADIANCE_WIDTH,
+			IRRADIANCE_WIDTH / 2,
+			1,
+		);
+
+		let downsample_group = |source: &wgpu::TextureView, destination: &wgpu::TextureView| {
+			device.create_bind_group(&wgpu::BindGroupDescriptor {
+				label: Some("Environment Downsample Group"),
+				layout: &self.downsample_layout,
+				entries: &[
+					wgpu::BindGroupEntry {
+						binding: 0,
+						resource: wgpu::BindingResource::TextureView(source),
+					},
+					wgpu::BindGroupEntry {
+						binding: 1,
+						resource: wgpu::BindingResource::TextureView(destination),
+					},
+				],
+			})
+		};
+		let radiance_view = radiance.create_view(&Default::default());
+
+		let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+			label: Some("Environment Prefilter Encoder"),
+		});
+		{
+			let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+				label: Some("Environment Prefilter Pass"),
+				timestamp_writes: None,
+			});
+			let dispatch = |pass: &mut wgpu::ComputePass, texture: &wgpu::Texture, level| {
+				let size = texture
+					.size()
+					.mip_level_size(level, wgpu::TextureDimension::D2);
+				pass.dispatch_workgroups(size.width.div_ceil(8), size.height.div_ceil(8), 1);
+			};
+
+			pass.set_pipeline(&self.downsample_pipeline);
+			// Every level is averaged from the source rather than the level above it, the GL
+			// backend drops the writes to one level of a texture while another is sampled
+			for level in 0..radiance_mips {
+				let view = mip_view(&radiance, level);
+				pass.set_bind_group(0, &downsample_group(&source, &view), &[]);
+				dispatch(&mut pass, &radiance, level);
+			}
+
+			pass.set_pipeline(&self.irradiance_pipeline);
+			let level = (width / IRRADIANCE_SOURCE_WIDTH)
+				.checked_ilog2()
+				.unwrap_or(0)
+				.min(radiance_mips - 1);
+			let group = downsample_group(
+				&mip_view(&radiance, level),
+				&irradiance.create_view(&Default::default()),
+			);
+			pass.set_bind_group(0, &group, &[]);
+			dispatch(&mut pass, &irradiance, 0);
+
+			pass.set_pipeline(&self.specular_pipeline);
+			for level in 0..SPECULAR_MIPS {
+				let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+					label: Some("Environment Prefilter Buffer"),
+					contents: bytemuck::bytes_of(&PrefilterUniform {
+						roughness: level as f32 / (SPECULAR_MIPS - 1) as f32,
+						_pad: [0; 3],
+					}),
+					usage: wgpu::BufferUsages::UNIFORM,
+				});
+				let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+					label: Some("Environment Prefilter Group"),
+					layout: &self.prefilter_layout,
+					entries: &[
+						wgpu::BindGroupEntry {
+							binding: 1,
+							resource: wgpu::BindingResource::TextureView(&mip_view(
+								&specular, level,
+							)),
+						},
+						wgpu::BindGroupEntry {
+							binding: 2,
+							resource: wgpu::BindingResource::Sampler(&self.sampler),
+						},
+						wgpu::BindGroupEntry {
+							binding: 3,
+							resource: wgpu::BindingResource::TextureView(&radiance_view),
+						},
+						wgpu::BindGroupEntry {
+							binding: 4,
+							resource: uniform.as_entire_binding(),
+						},
+					],
+				});
+				pass.set_bind_group(0, &group, &[]);
+				dispatch(&mut pass, &specular, level);
+			}
+		}
+		queue.submit([encoder.finish()]);
+
+		self.render_group = create_render_group(
+			device,
+			&self.render_layout,
+			&self.sampler,
+			[
+				&radiance_view,
+				&specular.create_view(&Default::default()),
+				&irradiance.create_view(&Default::default()),
+			],
+			&self.uniform_buffer,
+		);
+		self.textures = [radiance, specular, irradiance];
+		self.loaded = true;
+	}
+
+	pub fn render_layout(&self) -> &wgpu::BindGroupLayout {
+		&self.render_layout
+	}
+
+	pub fn render_group(&self) -> &wgpu::BindGroup {
+		&self.render_group
+	}
+
+	pub fn uniform(&self) -> EnvironmentUniform {
+		EnvironmentUniform {
+			enabled: self.loaded as u32,
+			intensity: self.intensity,
+			specular_lod: (SPECULAR_MIPS - 1) as f32,
+			_pad: 0,
+		}
+	}
+
+	pub fn write_uniform(&self, queue: &wgpu::Queue) {
+		queue.write_buffer(&self.uniform_buffer, 0, self.uniform().bytes());
+	}
+}
+
+fn create_texture(
+	device: &wgpu::Device,
+	label: &str,
+	width: u32,
+	height: u32,
+	mip_level_count: u32,
+) -> wgpu::Texture {
+	device.create_texture(&wgpu::TextureDescriptor {
+		label: Some(label),
+		size: wgpu::Extent3d {
+			width,
+			height,
+			depth_or_array_layers: 1,
+		},
+		mip_level_count,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format: FORMAT,
+		usage: wgpu::TextureUsages::TEXTURE_BINDING
+			| wgpu::TextureUsages::STORAGE_BINDING
+			| wgpu::TextureUsages::COPY_SRC,
+		view_formats: &[],
+	})
+}
+
+fn mip_view(texture: &wgpu::Texture, level: u32) -> wgpu::TextureView {
+	texture.create_view(&wgpu::TextureViewDescriptor {
+		base_mip_level: level,
+		mip_level_count: Some(1),
+		..Default::default()
+	})
+}
+
+/// Binds the radiance, specular and irradiance textures, in that order.
+fn create_render_group(
+	device: &wgpu::Device,
+	layout: &wgpu::BindGroupLayout,
+	sampler: &wgpu::Sampler,
+	textures: [&wgpu::TextureView; 3],
+	uniform_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+	let [radiance, specular, irradiance] = textures;
+	device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Environment Render Group"),
+		layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: wgpu::BindingResource::Sampler(sampler),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: wgpu::BindingResource::TextureView(radiance),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: wgpu::BindingResource::TextureView(specular),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: wgpu::BindingResource::TextureView(irradiance),
+			},
+			wgpu::BindGroupEntry {
+				binding: 4,
+				resource: uniform_buffer.as_entire_binding(),
+			},
+		],
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Reads back the texels of mip `level` of `texture`.
+	fn read_level(
+		device: &wgpu::Device,
+		queue: &wgpu::Queue,
+		texture: &wgpu::Texture,
+		level: u32,
+	) -> Vec<[f32; 3]> {
+		const TEXEL_BYTES: u32 = 8;
+		let size = texture
+			.size()
+			.mip_level_size(level, wgpu::TextureDimension::D2);
+		let row_bytes =
+			(size.width * TEXEL_BYTES).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+		let readback = device.create_buffer(&wgpu::BufferDescriptor {
+			label: None,
+			size: (row_bytes * size.height) as u64,
+			usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+			mapped_at_creation: false,
+		});
+		let mut encoder = device.create_command_encoder(&Default::default());
+		encoder.copy_texture_to_buffer(
+			wgpu::TexelCopyTextureInfo {
+				mip_level: level,
+				..texture.as_image_copy()
+			},
+			wgpu::TexelCopyBufferInfo {
+				buffer: &readback,
+				layout: wgpu::TexelCopyBufferLayout {
+					offset: 0,
+					bytes_per_row: Some(row_bytes),
+					rows_per_image: Some(size.height),
+				},
+			},
+			size,
+		);
+		queue.submit([encoder.finish()]);
+
+		readback
+			.slice(..)
+			.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+		device.poll(wgpu::PollType::Wait).unwrap();
+		let bytes = readback.slice(..).get_mapped_range().to_vec();
+		readback.unmap();
+		bytes
+			.chunks(row_bytes as usize)
+			.flat_map(|row| row[..(size.width * TEXEL_BYTES) as usize].chunks(TEXEL_BYTES as usize))
+			.map(|texel| {
+				std::array::from_fn(|i| {
+					half::f16::from_le_bytes([texel[2 * i], texel[2 * i + 1]]).to_f32()
+				})
+			})
+			.collect()
+	}
+
+	#[test]
+	fn constant_environment_prefilters_to_itself() {
+		let (device, queue) =
+			pollster::block_on(crate::headless::request_device()).expect("no adapter to test with");
+		let color = [0.5, 1.0, 2.0];
+		let image = HdrImage {
+			width: 256,
+			height: 128,
+			pixels: vec![color; 256 * 128],
+		};
+		let mut environment = Environment::new(&device, &Shaders::default()).unwrap();
+		environment.load(&device, &queue, &image);
+		let [radiance, specular, irradiance] = &environment.textures;
+		let assert_constant = |texels: Vec<[f32; 3]>, tolerance: f32, what: &str| {
+			for texel in texels {
+				for (value, expected) in texel.into_iter().zip(color) {
+					assert!(
+						(value / expected - 1.0).abs() < tolerance,
+						"{what} is {texel:?} instead of {color:?}"
+					);
+				}
+			}
+		};
+		for level in 0..radiance.mip_level_count() {
+			let texels = read_level(&device, &queue, radiance, level);
+			assert_constant(texels, 1e-3, &format!("radiance level {level}"));
+		}
+		for level in 0..radiance.mip_level_count() {
+			let texels = read_level(&device, &queue, radiance, level);
+			assert_constant(texels, 1e-3, &format!("radiance level {level}"));
+		}
+		for level in 0..SPECULAR_MIPS {
+			let texels = read_level(&device, &queue, specular, level);
+			assert_constant(texels, 1e-2, &format!("specular level {level}"));
+		}
+		// Integrated by a midpoint rule over the texels of the sky, which is only exact in the limit
+		let texels = read_level(&device, &queue, irradiance, 0);
+		assert_constant(texels, 3e-2, "irradiance");
+	}
+}
@@ -0,0 +1,139 @@
+// Prefilters an equirectangular environment once when it is loaded: a mip chain of the radiance,
+// the irradiance seen by diffuse surfaces, and the radiance reflected by increasingly rough ones.
+
+const PI = 3.14159265;
+const SPECULAR_SAMPLES = 64u;
+
+struct Prefilter {
+    roughness: f32,
+};
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@group(0) @binding(1)
+var destination: texture_storage_2d<rgba16float, write>;
+
+@group(0) @binding(2)
+var radiance_sampler: sampler;
+
+@group(0) @binding(3)
+var radiance: texture_2d<f32>;
+
+@group(0) @binding(4)
+var<uniform> u_prefilter: Prefilter;
+
+// Must be the same as the one in shader.wgsl
+fn equirect_uv(dir: vec3<f32>) -> vec2<f32> {
+    return vec2(atan2(dir.x, -dir.z) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+}
+
+fn equirect_dir(uv: vec2<f32>) -> vec3<f32> {
+    let phi = (uv.x - 0.5) * 2.0 * PI;
+    let theta = uv.y * PI;
+    return vec3(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+}
+
+fn texel_dir(id: vec2<u32>, size: vec2<u32>) -> vec3<f32> {
+    return equirect_dir((vec2<f32>(id) + 0.5) / vec2<f32>(size));
+}
+
+// Averages the texels of `source` covered by every texel of `destination`
+@compute @workgroup_size(8, 8)
+fn cs_downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(destination);
+    if any(id.xy >= size) {
+        return;
+    }
+    let source_size = textureDimensions(source);
+    let start = id.xy * source_size / size;
+    let end = max((id.xy + 1u) * source_size / size, start + 1u);
+    var sum = vec3(0.0);
+    for (var y = start.y; y < end.y; y++) {
+        for (var x = start.x; x < end.x; x++) {
+            sum += textureLoad(source, vec2(x, y), 0).rgb;
+        }
+    }
+    let count = f32((end.x - start.x) * (end.y - start.y));
+    textureStore(destination, id.xy, vec4(sum / count, 1.0));
+}
+
+// Cosine-weighted integral of `source` over the hemisphere around every texel's direction,
+// divided by PI so that a uniform sky comes out as its own radiance.
+@compute @workgroup_size(8, 8)
+fn cs_irradiance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(destination);
+    if any(id.xy >= size) {
+        return;
+    }
+    let n = texel_dir(id.xy, size);
+    let source_size = textureDimensions(source);
+    let d_phi = 2.0 * PI / f32(source_size.x);
+    let d_theta = PI / f32(source_size.y);
+    var sum = vec3(0.0);
+    for (var y = 0u; y < source_size.y; y++) {
+        let theta = (f32(y) + 0.5) * d_theta;
+        let solid_angle = d_phi * d_theta * sin(theta);
+        for (var x = 0u; x < source_size.x; x++) {
+            let dir = texel_dir(vec2(x, y), source_size);
+            let cos_theta = max(dot(n, dir), 0.0);
+            sum += textureLoad(source, vec2(x, y), 0).rgb * cos_theta * solid_angle;
+        }
+    }
+    textureStore(destination, id.xy, vec4(sum / PI, 1.0));
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Radiance reflected along every texel's direction by a surface of `u_prefilter.roughness`
+// facing it, from GGX importance samples of `radiance`. Samples read from the mip whose texels
+// cover about as much solid angle as the sample does, so that few samples don't alias.
+@compute @workgroup_size(8, 8)
+fn cs_specular(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(destination);
+    if any(id.xy >= size) {
+        return;
+    }
+    let n = texel_dir(id.xy, size);
+    let radiance_size = vec2<f32>(textureDimensions(radiance));
+    if u_prefilter.roughness == 0.0 {
+        let lod = max(log2(radiance_size.x / f32(size.x)), 0.0);
+        let color = textureSampleLevel(radiance, radiance_sampler, equirect_uv(n), lod).rgb;
+        textureStore(destination, id.xy, vec4(color, 1.0));
+        return;
+    }
+
+    // Tangent frame, see "Building an Orthonormal Basis, Revisited" (Duff et al.)
+    let s = select(-1.0, 1.0, n.z >= 0.0);
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    let tangent = vec3(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    let bitangent = vec3(b, s + n.y * n.y * a, -n.y);
+
+    let alpha = u_prefilter.roughness * u_prefilter.roughness;
+    let a2 = alpha * alpha;
+    let texel_solid_angle = 4.0 * PI / (radiance_size.x * radiance_size.y);
+    var sum = vec3(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < SPECULAR_SAMPLES; i++) {
+        let xi = hammersley(i, SPECULAR_SAMPLES);
+        let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a2 - 1.0) * xi.y));
+        let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        let phi = 2.0 * PI * xi.x;
+        let h = (cos(phi) * tangent + sin(phi) * bitangent) * sin_theta + n * cos_theta;
+        let l = 2.0 * dot(n, h) * h - n;
+        let n_l = dot(n, l);
+        if n_l > 0.0 {
+            // With the view along the normal, the pdf of `l` is D / 4
+            let d = cos_theta * cos_theta * (a2 - 1.0) + 1.0;
+            let pdf = a2 / (PI * d * d) / 4.0;
+            let sample_solid_angle = 1.0 / (f32(SPECULAR_SAMPLES) * pdf);
+            let lod = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+            sum += textureSampleLevel(radiance, radiance_sampler, equirect_uv(l), lod).rgb * n_l;
+            weight += n_l;
+        }
+    }
+    textureStore(destination, id.xy, vec4(sum / max(weight, 1e-4), 1.0));
+}
@@ -0,0 +1,480 @@
+use glam::UVec3;
+use wgpu::util::DeviceExt;
+
+use crate::{
+	readback::{Readback, Warning},
+	sdf::WorldVolume,
+};
+
+// Must be the same as the ones in compute.wgsl and shader.wgsl
+pub const MAX_PER_CELL: u32 = 256;
+pub const WORKGROUP_SIZE: u32 = 64;
+/// Most cells whose entry lists fit in the default 128 MiB storage buffer binding of wgpu.
+pub const MAX_CELLS: u32 = (128 << 20) / (MAX_PER_CELL * 4);
+
+#[derive(Debug, Copy, Clone)]
+pub struct GridSettings {
+	/// Distance past a particle's radius and blend radius it is still binned within.
+	/// Rays step at least this far through cells the nearest particle wasn't binned into.
+	pub margin: f32,
+}
+
+impl Default for GridSettings {
+	fn default() -> Self {
+		Self { margin: 0.05 }
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GridUniform {
+	min: [f32; 3],
+	count: u32,
+	max: [f32; 3],
+	margin: f32,
+	cells: [u32; 3],
+	_pad: u32,
+}
+
+impl GridUniform {
+	pub fn bytes(&self) -> &[u8] {
+		bytemuck::bytes_of(self)
+	}
+}
+
+/// Uniform grid of particle lists over the world volume, traversed by the raymarcher to
+/// evaluate the exact SDF. Bound as group 3 of the SDF compute pipelines, which build it,
+/// and group 2 of the render pipeline.
+///
+/// Only particles within reach of the world volume are binned, the ones outside of it
+/// are left out of the exact SDF like they are of the baked world volume.
+pub struct ParticleGrid {
+	pub settings: GridSettings,
+	compute_layout: wgpu::BindGroupLayout,
+	render_layout: wgpu::BindGroupLayout,
+	compute_group: wgpu::BindGroup,
+	render_group: wgpu::BindGroup,
+	uniform_buffer: wgpu::Buffer,
+	cell_counts: wgpu::Buffer,
+	cell_entries: wgpu::Buffer,
+	overflow_buffer: wgpu::Buffer,
+	overflow: Readback,
+	overflow_warning: Warning,
+	cells: UVec3,
+	count: u32,
+}
+
+impl ParticleGrid {
+	pub fn new(
+		device: &wgpu::Device,
+		particles: &wgpu::Buffer,
+		count: u32,
+		world: &WorldVolume,
+	) -> Self {
+		let entry = |binding, visibility, ty| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility,
+			ty: wgpu::BindingType::Buffer {
+				ty,
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		};
+		let compute = wgpu::ShaderStages::COMPUTE;
+		let fragment = wgpu::ShaderStages::FRAGMENT;
+		let uniform = wgpu::BufferBindingType::Uniform;
+		let read_only = wgpu::BufferBindingType::Storage { read_only: true };
+		let read_write = wgpu::BufferBindingType::Storage { read_only: false };
+
+		let compute_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Grid Compute Layout Group"),
+			entries: &[
+				entry(0, compute, read_write),
+				entry(1, compute, read_write),
+				entry(2, compute, uniform),
+				entry(3, compute, read_write),
+			],
+		});
+
+		let render_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Grid Render Layout Group"),
+			entries: &[
+				entry(0, fragment, read_only),
+				entry(1, fragment, read_only),
+				entry(2, fragment, read_only),
+				entry(3, fragment, uniform),
+			],
+		});
+
+		let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("Grid Buffer"),
+			contents: bytemuck::bytes_of(&GridUniform::default()),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		});
+
+		let overflow_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Cell Overflow Buffer"),
+			size: std::mem::size_of::<u32>() as u64,
+			usage: wgpu::BufferUsages::STORAGE
+				| wgpu::BufferUsages::COPY_SRC
+				| wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let (cell_counts, cell_entries) = create_cells(device, world.grid_cells);
+		let (compute_group, render_group) = create_groups(
+			device,
+			&compute_layout,
+			&render_layout,
+			particles,
+			&uniform_buffer,
+			&overflow_buffer,
+			&cell_counts,
+			&cell_entries,
+		);
+
+		Self {
+			settings: Default::default(),
+			compute_layout,
+			render_layout,
+			compute_group,
+			render_group,
+			uniform_buffer,
+			cell_counts,
+			cell_entries,
+			overflow_buffer,
+			overflow: Readback::new(device, "Cell Overflow Readback Buffer"),
+			overflow_warning: Warning::default(),
+			cells: world.grid_cells,
+			count,
+		}
+	}
+
+	/// Reallocates the cell lists for a new number of cells, keeping the settings.
+	pub fn resize(&mut self, device: &wgpu::Device, particles: &wgpu::Buffer, cells: UVec3) {
+		(self.cell_counts, self.cell_entries) = create_cells(device, cells);
+		(self.compute_group, self.render_group) = create_groups(
+			device,
+			&self.compute_layout,
+			&self.render_layout,
+			particles,
+			&self.uniform_buffer,
+			&self.overflow_buffer,
+			&self.cell_counts,
+			&self.cell_entries,
+		);
+		self.cells = cells;
+	}
+
+	/// Warns when cells held more particles than they fit in a recent build,
+	/// the exact SDF then misses some of them.
+	pub fn check_overflow(&mut self) {
+		if let Some(largest) = self.overflow.read() {
+			self.overflow_warning.update(largest > 0, || {
+				format!(
+					"a grid cell holds {largest} particles but only {MAX_PER_CELL} are used, \
+					raise `render.grid_cells`"
+				)
+			});
+		}
+	}
+
+	/// Resets the overflow counter, before binning.
+	pub fn clear_overflow(&self, encoder: &mut wgpu::CommandEncoder) {
+		encoder.clear_buffer(&self.overflow_buffer, 0, None);
+	}
+
+	/// Reads the overflow counter back, after binning.
+	pub fn copy_overflow(&self, encoder: &mut wgpu::CommandEncoder) {
+		self.overflow.copy(encoder, &self.overflow_buffer, 0);
+	}
+
+	pub fn compute_layout(&self) -> &wgpu::BindGroupLayout {
+		&self.compute_layout
+	}
+
+	pub fn render_layout(&self) -> &wgpu::BindGroupLayout {
+		&self.render_layout
+	}
+
+	pub fn compute_group(&self) -> &wgpu::BindGroup {
+		&self.compute_group
+	}
+
+	pub fn render_group(&self) -> &wgpu::BindGroup {
+		&self.render_group
+	}
+
+	pub fn uniform(&self, world: &WorldVolume) -> GridUniform {
+		GridUniform {
+			min: world.min.to_array(),
+			count: self.count,
+			max: world.max.to_array(),
+			margin: self.settings.margin,
+			cells: self.cells.to_array(),
+			_pad: 0,
+		}
+	}
+
+	pub fn write_uniform(&self, queue: &wgpu::Queue, world: &WorldVolume) {
+		queue.write_buffer(&self.uniform_buffer, 0, self.uniform(world).bytes());
+	}
+
+	/// Workgroups needed to clear every cell.
+	pub fn cell_workgroups(&self) -> u32 {
+		self.cells.element_product().div_ceil(WORKGROUP_SIZE)
+	}
+
+	/// Workgroups needed to bin every particle.
+	pub fn particle_workgroups(&self) -> u32 {
+		self.count.div_ceil(WORKGROUP_SIZE)
+	}
+}
+
+/// Allocates the particle count and list of `cells` cells.
+fn create_cells(device: &wgpu::Device, cells: UVec3) -> (wgpu::Buffer, wgpu::Buffer) {
+	let cells = cells.element_product() as u64;
+	let cell_counts = device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Cell Count Buffer"),
+		size: cells * std::mem::size_of::<u32>() as u64,
+		usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+		mapped_at_creation: false,
+	});
+	let cell_entries = device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Cell Entry Buffer"),
+		size: cells * MAX_PER_CELL as u64 * std::mem::size_of::<u32>() as u64,
+		usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+		mapped_at_creation: false,
+	});
+	(cell_counts, cell_entries)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_groups(
+	device: &wgpu::Device,
+	compute_layout: &wgpu::BindGroupLayout,
+	render_layout: &wgpu::BindGroupLayout,
+	particles: &wgpu::Buffer,
+	uniform_buffer: &wgpu::Buffer,
+	overflow_buffer: &wgpu::Buffer,
+	cell_counts: &wgpu::Buffer,
+	cell_entries: &wgpu::Buffer,
+) -> (wgpu::BindGroup, wgpu::BindGroup) {
+	let compute_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Grid Compute Group"),
+		layout: compute_layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: cell_counts.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: cell_entries.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: uniform_buffer.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: overflow_buffer.as_entire_binding(),
+			},
+		],
+	});
+
+	let render_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Grid Render Group"),
+		layout: render_layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: particles.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: cell_counts.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: cell_entries.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: uniform_buffer.as_entire_binding(),
+			},
+		],
+	});
+
+	(compute_group, render_group)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		blend::Blend,
+		camera::Camera,
+		particle::{self, Particle},
+		renderer::Renderer,
+		sdf::SdfSpace,
+		shaders::Shaders,
+		time::TimeUniform,
+	};
+	use glam::Vec3;
+
+	/// Builds the grid of `particles` over `world` by rendering one frame in exact space.
+	fn build(
+		device: &wgpu::Device,
+		queue: &wgpu::Queue,
+		particles: &[Particle],
+		world: WorldVolume,
+	) -> Renderer {
+		let format = wgpu::TextureFormat::Rgba8Unorm;
+		let mut renderer = Renderer::new(
+			device,
+			queue,
+			format,
+			particles,
+			UVec3::splat(8),
+			&Shaders::default(),
+		)
+		.unwrap();
+		renderer.set_world(world);
+		renderer.set_space(SdfSpace::Exact);
+
+		let target = device.create_texture(&wgpu::TextureDescriptor {
+			label: None,
+			size: wgpu::Extent3d::default(),
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+			view_formats: &[],
+		});
+		let mut encoder = device.create_command_encoder(&Default::default());
+		renderer.render(
+			&mut encoder,
+			&target.create_view(&Default::default()),
+			(1, 1),
+			&Camera::new(),
+			TimeUniform::from_seconds(0.0),
+		);
+		queue.submit([encoder.finish()]);
+		renderer
+	}
+
+	fn read(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<u32> {
+		let readback = device.create_buffer(&wgpu::BufferDescriptor {
+			label: None,
+			size: buffer.size(),
+			usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+			mapped_at_creation: false,
+		});
+		let mut encoder = device.create_command_encoder(&Default::default());
+		encoder.copy_buffer_to_buffer(buffer, 0, &readback, 0, buffer.size());
+		queue.submit([encoder.finish()]);
+
+		readback
+			.slice(..)
+			.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+		device.poll(wgpu::PollType::Wait).unwrap();
+		let values = bytemuck::cast_slice(&readback.slice(..).get_mapped_range()).to_vec();
+		readback.unmap();
+		values
+	}
+
+	/// CPU reference of `sample_exact` in `shader.wgsl`, over the cell lists the GPU built,
+	/// along with the bound it clamps the distance to. `p` must be inside the grid.
+	fn sample_exact(
+		grid: &ParticleGrid,
+		world: &WorldVolume,
+		counts: &[u32],
+		entries: &[u32],
+		particles: &[Particle],
+		blend: &Blend,
+		p: Vec3,
+	) -> (f32, f32) {
+		let size = world.max - world.min;
+		let cells = grid.cells;
+		let uvw = (p - world.min) / size;
+		let g = uvw * cells.as_vec3();
+		let cell = g.as_uvec3().min(cells - 1);
+		let i = (cell.x + cells.x * (cell.y + cells.y * cell.z)) as usize;
+
+		let count = counts[i].min(MAX_PER_CELL) as usize;
+		let start = i * MAX_PER_CELL as usize;
+		let binned: Vec<Particle> = entries[start..start + count]
+			.iter()
+			.map(|&entry| particles[entry as usize])
+			.collect();
+
+		let cell_size = size / cells.as_vec3();
+		let local = (g - cell.as_vec3()) * cell_size;
+		let to_face = local.min(cell_size - local);
+		let bound = blend.radius + grid.settings.margin + to_face.min_element();
+		(particle::sdf(&binned, blend, p).min(bound), bound)
+	}
+
+	#[test]
+	fn exact_grid_matches_particle_sdf() {
+		let (device, queue) =
+			pollster::block_on(crate::headless::request_device()).expect("no adapter to test with");
+		let world = WorldVolume {
+			grid_cells: UVec3::splat(8),
+			..Default::default()
+		};
+		// A lattice of particles with some of them hanging past the box
+		let particles: Vec<Particle> = (0..125)
+			.map(|i| {
+				let lattice = Vec3::new((i % 5) as f32, (i / 5 % 5) as f32, (i / 25) as f32);
+				Particle::new(lattice * 0.55 - 1.1, 0.05 + 0.01 * (i % 7) as f32)
+			})
+			.collect();
+		let renderer = build(&device, &queue, &particles, world);
+		let counts = read(&device, &queue, &renderer.grid.cell_counts);
+		let entries = read(&device, &queue, &renderer.grid.cell_entries);
+		assert_eq!(read(&device, &queue, &renderer.grid.overflow_buffer), [0]);
+
+		let mut within_bound = 0;
+		for i in 0..19 * 19 * 19 {
+			let lattice = Vec3::new((i % 19) as f32, (i / 19 % 19) as f32, (i / 361) as f32);
+			let p = world.min + (lattice + 0.5) / 19.0 * (world.max - world.min);
+			let (sample, bound) = sample_exact(
+				&renderer.grid,
+				&world,
+				&counts,
+				&entries,
+				&particles,
+				&renderer.blend,
+				p,
+			);
+			let exact = particle::sdf(&particles, &renderer.blend, p);
+			assert!(
+				(sample - exact.min(bound)).abs() < 1e-5,
+				"{sample} at {p} but the particles are {exact} away, bounded by {bound}"
+			);
+			within_bound += (exact < bound) as u32;
+		}
+		// Enough samples to compare the particles binned, not just the bound
+		assert!(
+			within_bound > 100,
+			"only {within_bound} samples within reach"
+		);
+	}
+
+	#[test]
+	fn reports_overflowing_cells() {
+		let (device, queue) =
+			pollster::block_on(crate::headless::request_device()).expect("no adapter to test with");
+		let crowd = MAX_PER_CELL + 10;
+		let particles = vec![Particle::new(Vec3::splat(0.1), 0.01); crowd as usize];
+		let renderer = build(&device, &queue, &particles, WorldVolume::default());
+		assert_eq!(
+			read(&device, &queue, &renderer.grid.overflow_buffer),
+			[crowd]
+		);
+	}
+}
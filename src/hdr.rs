@@ -0,0 +1,284 @@
+use std::{fmt, path::Path};
+
+#[derive(Debug)]
+pub enum HdrError {
+	Io(std::io::Error),
+	Format(String),
+}
+
+impl fmt::Display for HdrError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			HdrError::Io(err) => write!(f, "{err}"),
+			HdrError::Format(message) => write!(f, "invalid Radiance HDR: {message}"),
+		}
+	}
+}
+
+impl std::error::Error for HdrError {}
+
+impl From<std::io::Error> for HdrError {
+	fn from(err: std::io::Error) -> Self {
+		HdrError::Io(err)
+	}
+}
+
+/// Most pixels of an image, 1.5 GiB once decoded, past the largest environment maps around.
+const MAX_PIXELS: u64 = 1 << 27;
+
+/// Fewest bytes a scanline is encoded in, a single flat pixel or the run-length header.
+const MIN_SCANLINE_BYTES: u64 = 4;
+
+fn format_error<T>(message: impl Into<String>) -> Result<T, HdrError> {
+	Err(HdrError::Format(message.into()))
+}
+
+/// Linear RGB image, rows from top to bottom.
+#[derive(Debug, Clone)]
+pub struct HdrImage {
+	pub width: u32,
+	pub height: u32,
+	pub pixels: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+	/// Averages every 2x2 block of pixels, an odd last row or column on its own.
+	pub fn halve(&self) -> HdrImage {
+		let width = self.width.div_ceil(2);
+		let height = self.height.div_ceil(2);
+		let mut pixels = Vec::with_capacity(width as usize * height as usize);
+		for y in 0..height {
+			for x in 0..width {
+				let mut sum = [0.0; 3];
+				let mut count = 0.0;
+				for sy in 2 * y..(2 * y + 2).min(self.height) {
+					for sx in 2 * x..(2 * x + 2).min(self.width) {
+						let pixel = self.pixels[(sy * self.width + sx) as usize];
+						for (sum, value) in sum.iter_mut().zip(pixel) {
+							*sum += value;
+						}
+						count += 1.0;
+					}
+				}
+				pixels.push(sum.map(|sum| sum / count));
+			}
+		}
+		HdrImage {
+			width,
+			height,
+			pixels,
+		}
+	}
+}
+
+pub fn load(path: &Path) -> Result<HdrImage, HdrError> {
+	parse(&std::fs::read(path)?)
+}
+
+/// Parses a Radiance RGBE image (`.hdr`, `.pic`), flat or run-length encoded, in the usual
+/// `-Y height +X width` orientation.
+pub fn parse(bytes: &[u8]) -> Result<HdrImage, HdrError> {
+	let mut rest = bytes;
+	let mut next_line = || -> Result<&str, HdrError> {
+		let Some(end) = rest.iter().position(|&b| b == b'\n') else {
+			return format_error("header ends early");
+		};
+		let line = std::str::from_utf8(&rest[..end])
+			.map_err(|_| HdrError::Format("header is not text".into()))?;
+		rest = &rest[end + 1..];
+		Ok(line.trim_end_matches('\r'))
+	};
+
+	if !next_line()?.starts_with("#?") {
+		return format_error("missing `#?RADIANCE` signature");
+	}
+	loop {
+		let line = next_line()?;
+		if line.is_empty() {
+			break;
+		}
+		if let Some(format) = line.strip_prefix("FORMAT=") {
+			if format != "32-bit_rle_rgbe" {
+				return format_error(format!("unsupported format {format}"));
+			}
+		}
+	}
+	let resolution = next_line()?;
+	let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+		["-Y", height, "+X", width] => match (width.parse::<u32>(), height.parse::<u32>()) {
+			(Ok(width), Ok(height)) if width > 0 && height > 0 => (width, height),
+			_ => return format_error(format!("invalid resolution `{resolution}`")),
+		},
+		_ => return format_error(format!("unsupported orientation `{resolution}`")),
+	};
+	if width as u64 * height as u64 > MAX_PIXELS {
+		return format_error(format!("resolution `{resolution}` is too large"));
+	}
+	if height as u64 * MIN_SCANLINE_BYTES > rest.len() as u64 {
+		return format_error(format!("too short for resolution `{resolution}`"));
+	}
+
+	let mut pixels = Vec::with_capacity(width as usize * height as usize);
+	let mut scanline = vec![[0u8; 4]; width as usize];
+	for _ in 0..height {
+		rest = read_scanline(rest, &mut scanline)?;
+		pixels.extend(scanline.iter().map(|&rgbe| decode(rgbe)));
+	}
+	Ok(HdrImage {
+		width,
+		height,
+		pixels,
+	})
+}
+
+fn decode([r, g, b, e]: [u8; 4]) -> [f32; 3] {
+	if e == 0 {
+		return [0.0; 3];
+	}
+	let scale = 2f32.powi(e as i32 - (128 + 8));
+	[r as f32 * scale, g as f32 * scale, b as f32 * scale]
+}
+
+/// Reads one scanline into `out`, returning the bytes after it.
+fn read_scanline<'a>(bytes: &'a [u8], out: &mut [[u8; 4]]) -> Result<&'a [u8], HdrError> {
+	let width = out.len();
+	// Run-length encoded scanlines start with 2, 2 and their width, then hold every channel in turn
+	if (8..0x8000).contains(&width)
+		&& bytes.len() >= 4
+		&& bytes[0] == 2
+		&& bytes[1] == 2
+		&& bytes[2] & 0x80 == 0
+	{
+		if ((bytes[2] as usize) << 8 | bytes[3] as usize) != width {
+			return format_error("scanline width mismatch");
+		}
+		let mut rest = &bytes[4..];
+		for channel in 0..4 {
+			let mut x = 0;
+			while x < width {
+				let Some((&count, tail)) = rest.split_first() else {
+					return format_error("scanline ends early");
+				};
+				rest = tail;
+				if count > 128 {
+					let count = (count - 128) as usize;
+					let Some((&value, tail)) = rest.split_first() else {
+						return format_error("scanline ends early");
+					};
+					rest = tail;
+					if count > width - x {
+						return format_error("run overflows the scanline");
+					}
+					for pixel in &mut out[x..x + count] {
+						pixel[channel] = value;
+					}
+					x += count;
+				} else {
+					let count = count as usize;
+					if count == 0 || count > width - x || count > rest.len() {
+						return format_error("invalid literal run");
+					}
+					for (pixel, &value) in out[x..x + count].iter_mut().zip(rest) {
+						pixel[channel] = value;
+					}
+					rest = &rest[count..];
+					x += count;
+				}
+			}
+		}
+		return Ok(rest);
+	}
+
+	// Flat pixels, where 1, 1, 1 repeats the previous pixel in the older run-length encoding
+	let mut rest = bytes;
+	let mut x = 0;
+	let mut shift = 0;
+	while x < width {
+		let Some((pixel, tail)) = rest.split_first_chunk::<4>() else {
+			return format_error("scanline ends early");
+		};
+		rest = tail;
+		if pixel[..3] == [1, 1, 1] {
+			if x == 0 {
+				return format_error("repeat before the first pixel");
+			}
+			// Every consecutive repeat shifts its count a byte further
+			let Some(count) = (pixel[3] as usize).checked_shl(shift) else {
+				return format_error("too many consecutive repeats");
+			};
+			if count > width - x {
+				return format_error("run overflows the scanline");
+			}
+			let previous = out[x - 1];
+			out[x..x + count].fill(previous);
+			x += count;
+			shift += 8;
+		} else {
+			out[x] = *pixel;
+			x += 1;
+			shift = 0;
+		}
+	}
+	Ok(rest)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const HEADER: &[u8] = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n";
+
+	fn image(resolution: &str, data: &[u8]) -> Vec<u8> {
+		[HEADER, resolution.as_bytes(), b"\n", data].concat()
+	}
+
+	#[test]
+	fn parses_flat_pixels() {
+		let bytes = image("-Y 1 +X 2", &[128, 64, 0, 129, 0, 0, 0, 0]);
+		let hdr = parse(&bytes).unwrap();
+		assert_eq!((hdr.width, hdr.height), (2, 1));
+		assert_eq!(hdr.pixels, [[1.0, 0.5, 0.0], [0.0; 3]]);
+	}
+
+	#[test]
+	fn parses_run_length_encoded_scanlines() {
+		let mut data = vec![2, 2, 0, 8];
+		// Red as a run, green as literals, blue and exponent as runs
+		data.extend([128 + 8, 128]);
+		data.extend([8, 0, 0, 0, 0, 64, 64, 64, 64]);
+		data.extend([128 + 8, 0]);
+		data.extend([128 + 8, 129]);
+		let hdr = parse(&image("-Y 1 +X 8", &data)).unwrap();
+		assert_eq!(&hdr.pixels[..4], [[1.0, 0.0, 0.0]; 4]);
+		assert_eq!(&hdr.pixels[4..], [[1.0, 0.5, 0.0]; 4]);
+	}
+
+	#[test]
+	fn rejects_other_formats() {
+		let bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n\0\0\0\0";
+		assert!(matches!(parse(bytes), Err(HdrError::Format(_))));
+		assert!(matches!(parse(b"P6\n"), Err(HdrError::Format(_))));
+	}
+
+	#[test]
+	fn rejects_resolutions_before_allocating() {
+		let huge = image("-Y 4000000000 +X 4000000000", &[]);
+		assert!(matches!(parse(&huge), Err(HdrError::Format(_))));
+		// Every scanline takes at least 4 bytes
+		let short = image("-Y 3 +X 1", &[128, 128, 128, 128, 128, 128, 128, 128]);
+		assert!(matches!(parse(&short), Err(HdrError::Format(_))));
+	}
+
+	#[test]
+	fn rejects_too_many_consecutive_repeats() {
+		let mut data = vec![128, 128, 128, 129];
+		for _ in 0..usize::BITS / 8 + 1 {
+			data.extend([1, 1, 1, 0]);
+		}
+		data.extend([1, 1, 1, 1]);
+		assert!(matches!(
+			parse(&image("-Y 1 +X 2", &data)),
+			Err(HdrError::Format(_))
+		));
+	}
+}
@@ -0,0 +1,250 @@
+use std::{
+	fmt,
+	fs::File,
+	io::BufWriter,
+	path::{Path, PathBuf},
+};
+
+use crate::{
+	hdr::HdrImage,
+	particle::Particle,
+	renderer::Renderer,
+	scene::Scene,
+	shaders::{ShaderError, Shaders},
+	time::TimeUniform,
+};
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+/// Simulated time between two consecutive frames.
+const FRAME_TIME: f32 = 1.0 / 60.0;
+
+pub struct HeadlessOptions {
+	pub width: u32,
+	pub height: u32,
+	pub frames: u32,
+	pub output: PathBuf,
+	pub scene: Scene,
+	pub shaders: Shaders,
+}
+
+#[derive(Debug)]
+pub enum HeadlessError {
+	Adapter(wgpu::RequestAdapterError),
+	Device(wgpu::RequestDeviceError),
+	Shader(ShaderError),
+	Poll(wgpu::PollError),
+	Map(wgpu::BufferAsyncError),
+	Size { width: u32, height: u32, max: u32 },
+	Io(std::io::Error),
+	Png(png::EncodingError),
+}
+
+impl fmt::Display for HeadlessError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			HeadlessError::Adapter(err) => write!(f, "no usable adapter: {err}"),
+			HeadlessError::Device(err) => write!(f, "failed to create device: {err}"),
+			HeadlessError::Shader(err) => write!(f, "invalid shader: {err}"),
+			HeadlessError::Poll(err) => write!(f, "failed to wait for the GPU: {err}"),
+			HeadlessError::Map(err) => write!(f, "failed to read back the image: {err}"),
+			HeadlessError::Size { width, height, max } => write!(
+				f,
+				"image size {width}x{height} is larger than the {max} pixels a side the GPU supports"
+			),
+			HeadlessError::Io(err) => write!(f, "{err}"),
+			HeadlessError::Png(err) => write!(f, "failed to encode PNG: {err}"),
+		}
+	}
+}
+
+impl std::error::Error for HeadlessError {}
+
+impl From<std::io::Error> for HeadlessError {
+	fn from(err: std::io::Error) -> Self {
+		HeadlessError::Io(err)
+	}
+}
+
+impl From<ShaderError> for HeadlessError {
+	fn from(err: ShaderError) -> Self {
+		HeadlessError::Shader(err)
+	}
+}
+
+impl From<png::EncodingError> for HeadlessError {
+	fn from(err: png::EncodingError) -> Self {
+		HeadlessError::Png(err)
+	}
+}
+
+/// Picks a hardware adapter if there is one, falling back to a software one otherwise.
+async fn request_adapter(instance: &wgpu::Instance) -> Result<wgpu::Adapter, HeadlessError> {
+	let hardware = instance
+		.request_adapter(&wgpu::RequestAdapterOptions::default())
+		.await;
+	match hardware {
+		Ok(adapter) => Ok(adapter),
+		Err(_) => instance
+			.request_adapter(&wgpu::RequestAdapterOptions {
+				force_fallback_adapter: true,
+				..Default::default()
+			})
+			.await
+			.map_err(HeadlessError::Adapter),
+	}
+}
+
+/// Creates a device on [`request_adapter`]'s adapter, for rendering or computing without a window.
+pub async fn request_device() -> Result<(wgpu::Device, wgpu::Queue), HeadlessError> {
+	let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+	let adapter = request_adapter(&instance).await?;
+	adapter
+		.request_device(&Default::default())
+		.await
+		.map_err(HeadlessError::Device)
+}
+
+/// Path of `frame` out of `frames`, numbered only when there is more than one.
+fn frame_path(output: &Path, frame: u32, frames: u32) -> PathBuf {
+	if frames == 1 {
+		return output.to_owned();
+	}
+	let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+	let name = match output.extension() {
+		Some(extension) => format!("{stem}_{frame:04}.{}", extension.to_string_lossy()),
+		None => format!("{stem}_{frame:04}"),
+	};
+	output.with_file_name(name)
+}
+
+/// Renders `options.frames` frames without a window and writes them to `options.output` as PNGs.
+pub async fn render(
+	particles: &[Particle],
+	environment: Option<&HdrImage>,
+	options: &HeadlessOptions,
+) -> Result<(), HeadlessError> {
+	let (device, queue) = request_device().await?;
+	let max = device.limits().max_texture_dimension_2d;
+	if options.width > max || options.height > max {
+		return Err(HeadlessError::Size {
+			width: options.width,
+			height: options.height,
+			max,
+		});
+	}
+
+	let scene = &options.scene;
+	let mut renderer = Renderer::new(
+		&device,
+		&queue,
+		FORMAT,
+		particles,
+		scene.froxels((options.width, options.height)),
+		&options.shaders,
+	)?;
+	renderer.blend = scene.blend();
+	renderer.slicing = scene.render.slicing;
+	renderer.set_world(scene.world());
+	renderer.set_space(scene.render.space);
+	renderer.lighting = scene.lighting();
+	renderer.materials = scene.materials();
+	if let Some(image) = environment {
+		renderer.set_environment(image);
+	}
+	renderer.environment.intensity = scene.background.environment_intensity;
+
+	let size = wgpu::Extent3d {
+		width: options.width,
+		height: options.height,
+		depth_or_array_layers: 1,
+	};
+	let texture = device.create_texture(&wgpu::TextureDescriptor {
+		label: Some("Headless Target"),
+		size,
+		mip_level_count: 1,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format: FORMAT,
+		usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+		view_formats: &[],
+	});
+	let view = texture.create_view(&Default::default());
+
+	// Rows of a texture to buffer copy must be aligned
+	let bytes_per_pixel = FORMAT.block_copy_size(None).unwrap();
+	let unpadded_row = options.width * bytes_per_pixel;
+	let padded_row = unpadded_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+		* wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+	let readback = device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Headless Readback Buffer"),
+		size: padded_row as u64 * options.height as u64,
+		usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+		mapped_at_creation: false,
+	});
+
+	let mut camera = scene.camera();
+	camera.aspect = options.width as f32 / options.height as f32;
+
+	for frame in 0..options.frames {
+		let mut encoder = device.create_command_encoder(&Default::default());
+		renderer.render(
+			&mut encoder,
+			&view,
+			(options.width, options.height),
+			&camera,
+			TimeUniform::from_seconds(frame as f32 * FRAME_TIME),
+		);
+		encoder.copy_texture_to_buffer(
+			wgpu::TexelCopyTextureInfo {
+				texture: &texture,
+				mip_level: 0,
+				origin: wgpu::Origin3d::ZERO,
+				aspect: wgpu::TextureAspect::All,
+			},
+			wgpu::TexelCopyBufferInfo {
+				buffer: &readback,
+				layout: wgpu::TexelCopyBufferLayout {
+					offset: 0,
+					bytes_per_row: Some(padded_row),
+					rows_per_image: Some(options.height),
+				},
+			},
+			size,
+		);
+		queue.submit([encoder.finish()]);
+
+		let slice = readback.slice(..);
+		let (sender, receiver) = std::sync::mpsc::channel();
+		slice.map_async(wgpu::MapMode::Read, move |result| {
+			let _ = sender.send(result);
+		});
+		device
+			.poll(wgpu::PollType::Wait)
+			.map_err(HeadlessError::Poll)?;
+		receiver
+			.recv()
+			.expect("map_async callback was dropped")
+			.map_err(HeadlessError::Map)?;
+
+		let mut pixels = Vec::with_capacity((unpadded_row * options.height) as usize);
+		for row in slice.get_mapped_range().chunks(padded_row as usize) {
+			pixels.extend_from_slice(&row[..unpadded_row as usize]);
+		}
+		readback.unmap();
+
+		let file = BufWriter::new(File::create(frame_path(
+			&options.output,
+			frame,
+			options.frames,
+		))?);
+		let mut encoder = png::Encoder::new(file, options.width, options.height);
+		encoder.set_color(png::ColorType::Rgba);
+		encoder.set_depth(png::BitDepth::Eight);
+		encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+		let mut writer = encoder.write_header()?;
+		writer.write_image_data(&pixels)?;
+		writer.finish()?;
+	}
+
+	Ok(())
+}
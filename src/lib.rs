@@ -0,0 +1,60 @@
+//! Raymarches particles through a frustum-aligned signed distance field.
+//!
+//! [`Renderer`] owns every GPU resource and records its passes into a caller's
+//! encoder, so it can be embedded next to other wgpu code:
+//!
+//! ```no_run
+//! # fn frame(device: &wgpu::Device, queue: &wgpu::Queue, view: &wgpu::TextureView) {
+//! use wgpu_raymarcher::{particle, Camera, Renderer, Scene, Shaders, TimeUniform};
+//!
+//! let scene = Scene::default();
+//! let mut renderer = Renderer::new(
+//!     device,
+//!     queue,
+//!     wgpu::TextureFormat::Rgba8UnormSrgb,
+//!     &particle::grid(8, 8, 8),
+//!     scene.render.froxels,
+//!     &Shaders::default(),
+//! )
+//! .unwrap();
+//!
+//! let mut encoder = device.create_command_encoder(&Default::default());
+//! renderer.render(
+//!     &mut encoder,
+//!     view,
+//!     (600, 600),
+//!     &Camera::new(),
+//!     TimeUniform::from_seconds(0.0),
+//! );
+//! queue.submit([encoder.finish()]);
+//! # }
+//! ```
+
+pub mod binning;
+pub mod blend;
+pub mod bricks;
+pub mod camera;
+pub mod environment;
+pub mod grid;
+pub mod hdr;
+pub mod headless;
+pub mod light;
+pub mod loader;
+pub mod material;
+pub mod particle;
+pub mod readback;
+pub mod renderer;
+pub mod scene;
+pub mod screen;
+pub mod sdf;
+pub mod shaders;
+pub mod simulation;
+pub mod sph;
+pub mod time;
+
+pub use camera::Camera;
+pub use particle::Particle;
+pub use renderer::Renderer;
+pub use scene::Scene;
+pub use shaders::{ShaderError, Shaders};
+pub use time::TimeUniform;
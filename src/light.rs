@@ -0,0 +1,308 @@
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+// Must be the same as the one in shader.wgsl
+pub const MAX_LIGHTS: usize = 4;
+/// Most steps a shadow ray can take.
+pub const MAX_SHADOW_STEPS: u32 = 256;
+/// Most SDF samples taken to estimate the occlusion of a hit.
+pub const MAX_OCCLUSION_SAMPLES: u32 = 32;
+
+/// Directional light, shining along `direction`, which doesn't need to be normalized.
+#[derive(Debug, Copy, Clone)]
+pub struct Light {
+	pub direction: Vec3,
+	pub color: Vec3,
+	pub intensity: f32,
+}
+
+impl Default for Light {
+	fn default() -> Self {
+		Self {
+			direction: Vec3::new(-1.0, -1.0, 1.0),
+			color: Vec3::ONE,
+			intensity: 1.0,
+		}
+	}
+}
+
+/// How the shadow rays marched from every hit toward the lights are resolved.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShadowMode {
+	/// Every light reaches every surface facing it.
+	#[default]
+	Off,
+	/// Lit or not, stopping at the first blocker.
+	Hard,
+	/// Penumbrae estimated from how closely the ray passes by blockers, like a cone traced through the SDF.
+	Soft,
+}
+
+impl ShadowMode {
+	// Must match the SHADOW_* constants in shader.wgsl
+	fn id(self) -> u32 {
+		match self {
+			ShadowMode::Off => 0,
+			ShadowMode::Hard => 1,
+			ShadowMode::Soft => 2,
+		}
+	}
+
+	pub fn next(self) -> Self {
+		match self {
+			ShadowMode::Off => ShadowMode::Hard,
+			ShadowMode::Hard => ShadowMode::Soft,
+			ShadowMode::Soft => ShadowMode::Off,
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Shadows {
+	pub mode: ShadowMode,
+	/// Steps a shadow ray takes before the light is assumed to reach the surface.
+	pub steps: u32,
+	/// Width of the penumbra cone, relative to the distance travelled.
+	pub softness: f32,
+}
+
+impl Default for Shadows {
+	fn default() -> Self {
+		Self {
+			mode: ShadowMode::default(),
+			steps: 48,
+			softness: 0.1,
+		}
+	}
+}
+
+/// Where the SDF is sampled around a hit to estimate how much of the sky it sees.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OcclusionMode {
+	/// The ambient term is left as is.
+	#[default]
+	Off,
+	/// Samples lined up along the normal.
+	Normal,
+	/// Samples spiralling out within a cone around the normal, catching blockers off to the side.
+	Cone,
+}
+
+impl OcclusionMode {
+	// Must match the OCCLUSION_* constants in shader.wgsl
+	fn id(self) -> u32 {
+		match self {
+			OcclusionMode::Off => 0,
+			OcclusionMode::Normal => 1,
+			OcclusionMode::Cone => 2,
+		}
+	}
+
+	pub fn next(self) -> Self {
+		match self {
+			OcclusionMode::Off => OcclusionMode::Normal,
+			OcclusionMode::Normal => OcclusionMode::Cone,
+			OcclusionMode::Cone => OcclusionMode::Off,
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Occlusion {
+	pub mode: OcclusionMode,
+	pub samples: u32,
+	/// Distance from the hit of the farthest sample.
+	pub radius: f32,
+}
+
+impl Default for Occlusion {
+	fn default() -> Self {
+		Self {
+			mode: OcclusionMode::default(),
+			samples: 5,
+			radius: 0.1,
+		}
+	}
+}
+
+/// Lights of the scene and the sky gradient behind it.
+#[derive(Debug, Clone)]
+pub struct Lighting {
+	pub lights: Vec<Light>,
+	pub shadows: Shadows,
+	pub occlusion: Occlusion,
+	/// Colour of the sky below the horizon.
+	pub ground: Vec3,
+	/// Colour of the sky above the horizon.
+	pub sky: Vec3,
+}
+
+impl Default for Lighting {
+	fn default() -> Self {
+		Self {
+			lights: vec![Light::default()],
+			shadows: Shadows::default(),
+			occlusion: Occlusion::default(),
+			ground: Vec3::new(0.58, 0.529, 0.459),
+			sky: Vec3::new(0.714, 0.812, 0.78),
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+	direction: [f32; 3],
+	_pad: f32,
+	color: [f32; 3],
+	intensity: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightingUniform {
+	ground: [f32; 3],
+	count: u32,
+	sky: [f32; 3],
+	shadow_mode: u32,
+	lights: [LightUniform; MAX_LIGHTS],
+	shadow_steps: u32,
+	shadow_softness: f32,
+	occlusion_mode: u32,
+	occlusion_samples: u32,
+	occlusion_radius: f32,
+	_pad: [u32; 3],
+}
+
+impl Lighting {
+	/// Lights past [`MAX_LIGHTS`] are left out.
+	pub fn uniform(&self) -> LightingUniform {
+		let mut lights = [LightUniform::default(); MAX_LIGHTS];
+		for (uniform, light) in lights.iter_mut().zip(&self.lights) {
+			*uniform = LightUniform {
+				direction: light.direction.normalize_or_zero().to_array(),
+				_pad: 0.0,
+				color: light.color.to_array(),
+				intensity: light.intensity,
+			};
+		}
+		LightingUniform {
+			ground: self.ground.to_array(),
+			count: self.lights.len().min(MAX_LIGHTS) as u32,
+			sky: self.sky.to_array(),
+			shadow_mode: self.shadows.mode.id(),
+			lights,
+			shadow_steps: self.shadows.steps,
+			shadow_softness: self.shadows.softness,
+			occlusion_mode: self.occlusion.mode.id(),
+			occlusion_samples: self.occlusion.samples,
+			occlusion_radius: self.occlusion.radius,
+			_pad: [0; 3],
+		}
+	}
+}
+
+impl LightingUniform {
+	pub fn bytes(&self) -> &[u8] {
+		bytemuck::bytes_of(self)
+	}
+}
+
+pub fn create_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+	device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		label: Some("Lighting Buffer"),
+		contents: bytemuck::bytes_of(&LightingUniform::default()),
+		usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn shadows_are_opt_in() {
+		let uniform = Lighting::default().uniform();
+		assert_eq!(ShadowMode::default(), ShadowMode::Off);
+		assert_eq!(uniform.shadow_mode, ShadowMode::Off.id());
+	}
+
+	#[test]
+	fn uniform_carries_the_shadow_mode() {
+		// Every mode is reached by cycling and maps to its own constant
+		let mut mode = ShadowMode::Off;
+		let mut ids = Vec::new();
+		for _ in 0..3 {
+			let lighting = Lighting {
+				shadows: Shadows {
+					mode,
+					..Default::default()
+				},
+				..Default::default()
+			};
+			ids.push(lighting.uniform().shadow_mode);
+			mode = mode.next();
+		}
+		assert_eq!(mode, ShadowMode::Off);
+		assert_eq!(ids, [0, 1, 2]);
+	}
+
+	#[test]
+	fn uniform_normalizes_light_directions() {
+		let lighting = Lighting {
+			lights: vec![
+				Light {
+					direction: Vec3::new(0.0, -3.0, 4.0),
+					..Default::default()
+				},
+				Light::default(),
+				Light {
+					direction: Vec3::ZERO,
+					..Default::default()
+				},
+			],
+			..Default::default()
+		};
+		let uniform = lighting.uniform();
+		assert_eq!(uniform.count, 3);
+		assert_eq!(uniform.lights[0].direction, [0.0, -0.6, 0.8]);
+		let direction = Vec3::from(uniform.lights[1].direction);
+		assert!((direction.length() - 1.0).abs() < 1e-6);
+		assert!(direction.dot(Light::default().direction) > 0.0);
+		// A light without a direction is left dark rather than NaN
+		assert_eq!(uniform.lights[2].direction, [0.0; 3]);
+	}
+
+	#[test]
+	fn occlusion_is_opt_in() {
+		let uniform = Lighting::default().uniform();
+		assert_eq!(OcclusionMode::default(), OcclusionMode::Off);
+		assert_eq!(uniform.occlusion_mode, OcclusionMode::Off.id());
+	}
+
+	#[test]
+	fn uniform_carries_the_occlusion_settings() {
+		let mut mode = OcclusionMode::Off;
+		let mut ids = Vec::new();
+		for _ in 0..3 {
+			let lighting = Lighting {
+				occlusion: Occlusion {
+					mode,
+					samples: 8,
+					radius: 0.25,
+				},
+				..Default::default()
+			};
+			let uniform = lighting.uniform();
+			assert_eq!(uniform.occlusion_samples, 8);
+			assert_eq!(uniform.occlusion_radius, 0.25);
+			ids.push(uniform.occlusion_mode);
+			mode = mode.next();
+		}
+		assert_eq!(mode, OcclusionMode::Off);
+		assert_eq!(ids, [0, 1, 2]);
+	}
+}
@@ -0,0 +1,539 @@
+use std::{
+	fmt,
+	path::{Path, PathBuf},
+};
+
+use glam::Vec3;
+
+use crate::particle::Particle;
+
+/// Radius given to particles whose file doesn't specify one.
+pub const DEFAULT_RADIUS: f32 = 0.05;
+
+#[derive(Debug)]
+pub enum LoadError {
+	Io(std::io::Error),
+	UnknownFormat(PathBuf),
+	Parse { line: usize, message: String },
+	Ply(String),
+}
+
+impl fmt::Display for LoadError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LoadError::Io(err) => write!(f, "{err}"),
+			LoadError::UnknownFormat(path) => write!(
+				f,
+				"unknown particle format for {} (expected .xyz, .csv, .txt or .ply)",
+				path.display()
+			),
+			LoadError::Parse { line, message } => write!(f, "line {line}: {message}"),
+			LoadError::Ply(message) => write!(f, "invalid PLY: {message}"),
+		}
+	}
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+	fn from(err: std::io::Error) -> Self {
+		LoadError::Io(err)
+	}
+}
+
+/// Loads a particle set, picking the format from the file extension.
+pub fn load(path: &Path) -> Result<Vec<Particle>, LoadError> {
+	let extension = path
+		.extension()
+		.and_then(|ext| ext.to_str())
+		.map(|ext| ext.to_ascii_lowercase());
+	match extension.as_deref() {
+		Some("xyz" | "csv" | "txt") => parse_text(&std::fs::read_to_string(path)?),
+		Some("ply") => parse_ply(&std::fs::read(path)?),
+		_ => Err(LoadError::UnknownFormat(path.to_owned())),
+	}
+}
+
+/// Parses `x y z [radius [r g b [material]]]` records separated by commas, semicolons or whitespace.
+/// Blank lines and lines starting with `#` are skipped, and so is a header on the first record.
+pub fn parse_text(text: &str) -> Result<Vec<Particle>, LoadError> {
+	let mut particles = vec![];
+	let mut first = true;
+	for (i, line) in text.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let fields: Vec<&str> = line
+			.split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+			.filter(|field| !field.is_empty())
+			.collect();
+		let values: Result<Vec<f32>, _> = fields.iter().map(|field| field.parse::<f32>()).collect();
+		let values = match values {
+			Ok(values) => values,
+			Err(_) if first => {
+				first = false;
+				continue;
+			}
+			Err(err) => {
+				return Err(LoadError::Parse {
+					line: i + 1,
+					message: err.to_string(),
+				})
+			}
+		};
+		first = false;
+		let (radius, color, material) = match *values.as_slice() {
+			[_, _, _] => (DEFAULT_RADIUS, None, None),
+			[_, _, _, radius] => (radius, None, None),
+			[_, _, _, radius, r, g, b] => (radius, Some(Vec3::new(r, g, b)), None),
+			[_, _, _, radius, r, g, b, material] => {
+				(radius, Some(Vec3::new(r, g, b)), Some(material))
+			}
+			_ => {
+				return Err(LoadError::Parse {
+					line: i + 1,
+					message: format!("expected 3, 4, 7 or 8 values, found {}", values.len()),
+				})
+			}
+		};
+		if !valid_radius(radius) {
+			return Err(LoadError::Parse {
+				line: i + 1,
+				message: format!("radius `{radius}` is not a finite non-negative number"),
+			});
+		}
+		let mut particle = Particle::new(Vec3::new(values[0], values[1], values[2]), radius);
+		if let Some(color) = color {
+			particle = particle.with_color(color);
+		}
+		if let Some(material) = material {
+			if !valid_material(material as f64) {
+				return Err(LoadError::Parse {
+					line: i + 1,
+					message: format!("material `{material}` is not a valid material index"),
+				});
+			}
+			particle = particle.with_material(material as u32);
+		}
+		particles.push(particle);
+	}
+	Ok(particles)
+}
+
+fn valid_radius(radius: f32) -> bool {
+	radius.is_finite() && radius >= 0.0
+}
+
+fn valid_material(material: f64) -> bool {
+	material >= 0.0 && material.fract() == 0.0 && material <= u32::MAX as f64
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum PlyFormat {
+	Ascii,
+	BinaryLittleEndian,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum PlyType {
+	I8,
+	U8,
+	I16,
+	U16,
+	I32,
+	U32,
+	F32,
+	F64,
+}
+
+impl PlyType {
+	fn parse(name: &str) -> Result<Self, LoadError> {
+		Ok(match name {
+			"char" | "int8" => PlyType::I8,
+			"uchar" | "uint8" => PlyType::U8,
+			"short" | "int16" => PlyType::I16,
+			"ushort" | "uint16" => PlyType::U16,
+			"int" | "int32" => PlyType::I32,
+			"uint" | "uint32" => PlyType::U32,
+			"float" | "float32" => PlyType::F32,
+			"double" | "float64" => PlyType::F64,
+			_ => return Err(LoadError::Ply(format!("unknown property type `{name}`"))),
+		})
+	}
+
+	fn size(self) -> usize {
+		match self {
+			PlyType::I8 | PlyType::U8 => 1,
+			PlyType::I16 | PlyType::U16 => 2,
+			PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+			PlyType::F64 => 8,
+		}
+	}
+
+	fn read_le(self, bytes: &[u8]) -> f64 {
+		match self {
+			PlyType::I8 => bytes[0] as i8 as f64,
+			PlyType::U8 => bytes[0] as f64,
+			PlyType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+			PlyType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+			PlyType::I32 => i32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+			PlyType::U32 => u32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+			PlyType::F32 => f32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+			PlyType::F64 => f64::from_le_bytes(bytes[..8].try_into().unwrap()),
+		}
+	}
+}
+
+#[derive(Debug)]
+enum PlyProperty {
+	Scalar(String, PlyType),
+	List(PlyType, PlyType),
+}
+
+#[derive(Debug)]
+struct PlyElement {
+	name: String,
+	count: usize,
+	properties: Vec<PlyProperty>,
+}
+
+struct PlyHeader {
+	format: PlyFormat,
+	elements: Vec<PlyElement>,
+	body: usize,
+}
+
+fn parse_ply_header(bytes: &[u8]) -> Result<PlyHeader, LoadError> {
+	// The header ends at the first line that is exactly `end_header`, the body may contain anything
+	let mut end = None;
+	let mut start = 0;
+	while start < bytes.len() {
+		let next = bytes[start..]
+			.iter()
+			.position(|&b| b == b'\n')
+			.map_or(bytes.len(), |newline| start + newline + 1);
+		if bytes[start..next].trim_ascii() == b"end_header" {
+			end = Some((start, next));
+			break;
+		}
+		start = next;
+	}
+	let (end, body) = end.ok_or_else(|| LoadError::Ply("missing end_header".into()))?;
+	let header = std::str::from_utf8(&bytes[..end])
+		.map_err(|_| LoadError::Ply("header is not valid UTF-8".into()))?;
+
+	let mut lines = header.lines().map(str::trim);
+	if lines.next() != Some("ply") {
+		return Err(LoadError::Ply("missing `ply` magic".into()));
+	}
+
+	let mut format = None;
+	let mut elements: Vec<PlyElement> = vec![];
+	for line in lines {
+		let words: Vec<&str> = line.split_whitespace().collect();
+		match words.as_slice() {
+			[] | ["comment", ..] | ["obj_info", ..] => {}
+			["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+			["format", "binary_little_endian", _] => format = Some(PlyFormat::BinaryLittleEndian),
+			["format", other, _] => {
+				return Err(LoadError::Ply(format!("unsupported format `{other}`")))
+			}
+			["element", name, count] => elements.push(PlyElement {
+				name: name.to_string(),
+				count: count
+					.parse()
+					.map_err(|_| LoadError::Ply(format!("invalid element count `{count}`")))?,
+				properties: vec![],
+			}),
+			["property", "list", count_ty, item_ty, _] => elements
+				.last_mut()
+				.ok_or_else(|| LoadError::Ply("property before any element".into()))?
+				.properties
+				.push(PlyProperty::List(
+					PlyType::parse(count_ty)?,
+					PlyType::parse(item_ty)?,
+				)),
+			["property", ty, name] => elements
+				.last_mut()
+				.ok_or_else(|| LoadError::Ply("property before any element".into()))?
+				.properties
+				.push(PlyProperty::Scalar(name.to_string(), PlyType::parse(ty)?)),
+			_ => return Err(LoadError::Ply(format!("unexpected header line `{line}`"))),
+		}
+	}
+
+	let format = format.ok_or_else(|| LoadError::Ply("missing format line".into()))?;
+	Ok(PlyHeader {
+		format,
+		elements,
+		body,
+	})
+}
+
+/// Parses an ASCII or binary little-endian PLY point cloud.
+/// Vertices need `x`, `y` and `z` properties and may carry a `radius`, a `red`, `green` and
+/// `blue` colour (0 to 255 for integer properties, 0 to 1 for floating point ones) and a `material`.
+pub fn parse_ply(bytes: &[u8]) -> Result<Vec<Particle>, LoadError> {
+	let header = parse_ply_header(bytes)?;
+	let body = &bytes[header.body..];
+
+	let mut particles = vec![];
+	let mut ascii = match header.format {
+		PlyFormat::Ascii => Some(
+			std::str::from_utf8(body)
+				.map_err(|_| LoadError::Ply("body is not valid UTF-8".into()))?
+				.split_whitespace(),
+		),
+		PlyFormat::BinaryLittleEndian => None,
+	};
+	let mut cursor = 0;
+	let mut read = |ty: PlyType| -> Result<f64, LoadError> {
+		match ascii.as_mut() {
+			Some(words) => {
+				let word = words
+					.next()
+					.ok_or_else(|| LoadError::Ply("unexpected end of data".into()))?;
+				word.parse()
+					.map_err(|_| LoadError::Ply(format!("invalid number `{word}`")))
+			}
+			None => {
+				let bytes = body
+					.get(cursor..cursor + ty.size())
+					.ok_or_else(|| LoadError::Ply("unexpected end of data".into()))?;
+				cursor += ty.size();
+				Ok(ty.read_le(bytes))
+			}
+		}
+	};
+
+	for element in &header.elements {
+		let is_vertex = element.name == "vertex";
+		let index_of = |name: &str| {
+			element
+				.properties
+				.iter()
+				.position(|property| matches!(property, PlyProperty::Scalar(n, _) if n == name))
+		};
+		let (x, y, z, radius) = (
+			index_of("x"),
+			index_of("y"),
+			index_of("z"),
+			index_of("radius"),
+		);
+		let (red, green, blue, material) = (
+			index_of("red"),
+			index_of("green"),
+			index_of("blue"),
+			index_of("material"),
+		);
+		let color_scale = match red.map(|i| &element.properties[i]) {
+			Some(PlyProperty::Scalar(_, PlyType::F32 | PlyType::F64)) | None => 1.0,
+			Some(PlyProperty::Scalar(_, _)) => 1.0 / 255.0,
+			Some(PlyProperty::List(..)) => unreachable!(),
+		};
+		if is_vertex && (x.is_none() || y.is_none() || z.is_none()) {
+			return Err(LoadError::Ply("vertex element lacks x, y or z".into()));
+		}
+
+		let mut values = vec![0.0; element.properties.len()];
+		for index in 0..element.count {
+			for (value, property) in values.iter_mut().zip(&element.properties) {
+				match property {
+					PlyProperty::Scalar(_, ty) => *value = read(*ty)?,
+					PlyProperty::List(count_ty, item_ty) => {
+						for _ in 0..read(*count_ty)? as usize {
+							read(*item_ty)?;
+						}
+					}
+				}
+			}
+			if is_vertex {
+				let get = |index: Option<usize>| index.map(|i| values[i] as f32);
+				let radius = get(radius).unwrap_or(DEFAULT_RADIUS);
+				if !valid_radius(radius) {
+					return Err(LoadError::Ply(format!(
+						"vertex {index} has radius `{radius}`, expected a finite non-negative number"
+					)));
+				}
+				let mut particle = Particle::new(
+					Vec3::new(get(x).unwrap(), get(y).unwrap(), get(z).unwrap()),
+					radius,
+				);
+				if let (Some(r), Some(g), Some(b)) = (get(red), get(green), get(blue)) {
+					particle = particle.with_color(Vec3::new(r, g, b) * color_scale);
+				}
+				if let Some(material) = material.map(|i| values[i]) {
+					if !valid_material(material) {
+						return Err(LoadError::Ply(format!(
+							"vertex {index} has material `{material}`, which is not a valid material index"
+						)));
+					}
+					particle = particle.with_material(material as u32);
+				}
+				particles.push(particle);
+			}
+		}
+	}
+
+	Ok(particles)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn positions(particles: &[Particle]) -> Vec<Vec3> {
+		particles.iter().map(Particle::position).collect()
+	}
+
+	fn binary_ply(properties: &str, count: usize, body: &[u8]) -> Vec<u8> {
+		let mut bytes = format!(
+			"ply\nformat binary_little_endian 1.0\nelement vertex {count}\n{properties}end_header\n"
+		)
+		.into_bytes();
+		bytes.extend_from_slice(body);
+		bytes
+	}
+
+	#[test]
+	fn text_without_radius() {
+		let particles = parse_text("0 0 0\n1.5\t2 -3\n\n# comment\n4 5 6\n").unwrap();
+		assert_eq!(
+			positions(&particles),
+			[
+				Vec3::ZERO,
+				Vec3::new(1.5, 2.0, -3.0),
+				Vec3::new(4.0, 5.0, 6.0)
+			]
+		);
+		assert!(particles.iter().all(|p| p.radius() == DEFAULT_RADIUS));
+	}
+
+	#[test]
+	fn csv_with_header_radius_colour_and_material() {
+		let particles =
+			parse_text("x,y,z,radius,r,g,b,material\n1,2,3,0.25,1,0,0.5,2\n4;5;6;0.5\n").unwrap();
+		assert_eq!(
+			positions(&particles),
+			[Vec3::new(1.0, 2.0, 3.0), Vec3::new(4.0, 5.0, 6.0)]
+		);
+		assert_eq!(particles[0].radius(), 0.25);
+		assert_eq!(particles[0].color(), Vec3::new(1.0, 0.0, 0.5));
+		assert_eq!(particles[0].material(), 2);
+		assert_eq!(particles[1].radius(), 0.5);
+		assert_eq!(particles[1].color(), Vec3::ONE);
+		assert_eq!(particles[1].material(), 0);
+	}
+
+	#[test]
+	fn text_reports_line_of_bad_record() {
+		let err = parse_text("x y z\n0 0 0\n\n1 a 2\n").unwrap_err();
+		assert!(matches!(err, LoadError::Parse { line: 4, .. }), "{err}");
+		let err = parse_text("0 0 0\n1 2\n").unwrap_err();
+		assert!(matches!(err, LoadError::Parse { line: 2, .. }), "{err}");
+	}
+
+	#[test]
+	fn text_rejects_negative_and_nan_radius() {
+		for text in ["0 0 0 -0.1", "0 0 0 NaN", "0 0 0 inf"] {
+			let err = parse_text(&format!("0 0 0\n{text}\n")).unwrap_err();
+			assert!(matches!(err, LoadError::Parse { line: 2, .. }), "{err}");
+		}
+	}
+
+	#[test]
+	fn text_rejects_fractional_and_negative_material() {
+		for material in ["1.5", "-1", "NaN", "1e10"] {
+			let err = parse_text(&format!("0 0 0 0.1 1 1 1 {material}\n")).unwrap_err();
+			assert!(matches!(err, LoadError::Parse { line: 1, .. }), "{err}");
+		}
+	}
+
+	#[test]
+	fn ply_rejects_fractional_and_negative_material() {
+		for material in ["2.5", "-1", "1e10"] {
+			let bytes = format!(
+				"ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\n\
+				property float z\nproperty float material\nend_header\n0 0 0 3\n1 1 1 {material}\n"
+			);
+			assert!(
+				matches!(parse_ply(bytes.as_bytes()), Err(LoadError::Ply(_))),
+				"{material}"
+			);
+		}
+	}
+
+	#[test]
+	fn ascii_ply() {
+		let bytes = b"ply\r\nformat ascii 1.0\r\ncomment not the end_header yet\r\n\
+			element vertex 2\r\nproperty float x\r\nproperty float y\r\nproperty float z\r\n\
+			property uchar red\r\nproperty uchar green\r\nproperty uchar blue\r\n\
+			element face 1\r\nproperty list uchar int vertex_indices\r\nend_header\r\n\
+			1 2 3 255 0 51\r\n-1 -2 -3 0 255 0\r\n3 0 1 1\r\n";
+		let particles = parse_ply(bytes).unwrap();
+		assert_eq!(
+			positions(&particles),
+			[Vec3::new(1.0, 2.0, 3.0), Vec3::new(-1.0, -2.0, -3.0)]
+		);
+		assert!(particles[0]
+			.color()
+			.abs_diff_eq(Vec3::new(1.0, 0.0, 0.2), 1.0e-6));
+		assert_eq!(particles[1].radius(), DEFAULT_RADIUS);
+	}
+
+	#[test]
+	fn binary_little_endian_ply() {
+		let mut body = vec![];
+		for (position, radius) in [([1.0f32, 2.0, 3.0], 0.5f32), ([4.0, 5.0, 6.0], 0.125)] {
+			for value in position.into_iter().chain([radius]) {
+				body.extend_from_slice(&value.to_le_bytes());
+			}
+			body.extend_from_slice(&7u16.to_le_bytes());
+		}
+		let bytes = binary_ply(
+			"property float x\nproperty float y\nproperty float z\nproperty float radius\n\
+			property ushort material\n",
+			2,
+			&body,
+		);
+		let particles = parse_ply(&bytes).unwrap();
+		assert_eq!(
+			positions(&particles),
+			[Vec3::new(1.0, 2.0, 3.0), Vec3::new(4.0, 5.0, 6.0)]
+		);
+		assert_eq!(particles[0].radius(), 0.5);
+		assert_eq!(particles[1].radius(), 0.125);
+		assert!(particles.iter().all(|p| p.material() == 7));
+	}
+
+	#[test]
+	fn truncated_binary_ply() {
+		let bytes = binary_ply(
+			"property float x\nproperty float y\nproperty float z\n",
+			2,
+			&[0; 4 * 3 * 2 - 1],
+		);
+		assert!(matches!(parse_ply(&bytes), Err(LoadError::Ply(_))));
+	}
+
+	#[test]
+	fn ply_without_x() {
+		let bytes = b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float y\n\
+			property float z\nend_header\n1 2\n";
+		assert!(matches!(parse_ply(bytes), Err(LoadError::Ply(_))));
+	}
+
+	#[test]
+	fn ply_without_end_header_line() {
+		let bytes = b"ply\nformat ascii 1.0\ncomment end_header\nelement vertex 0\n";
+		assert!(matches!(parse_ply(bytes), Err(LoadError::Ply(_))));
+	}
+
+	#[test]
+	fn ply_rejects_negative_radius() {
+		let bytes = b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n\
+			property float y\nproperty float z\nproperty float radius\nend_header\n0 0 0 -1\n";
+		assert!(matches!(parse_ply(bytes), Err(LoadError::Ply(_))));
+	}
+}
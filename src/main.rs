@@ -1,20 +1,83 @@
-use app::App;
+use app::{App, WindowOptions};
+use clap::Parser;
+use cli::Cli;
+use wgpu_raymarcher::{headless::HeadlessOptions, Scene};
 use winit::event_loop::{ControlFlow, EventLoop};
 
 mod app;
-mod camera;
-mod particle;
-mod screen;
-mod sdf;
+mod cli;
 mod state;
-mod time;
 
 fn main() {
 	env_logger::init();
 
+	let cli = Cli::parse();
+
+	let mut scene = match &cli.scene {
+		Some(path) => match Scene::load(path) {
+			Ok(scene) => scene,
+			Err(err) => {
+				eprintln!("Failed to load scene from {}: {err}", path.display());
+				std::process::exit(1);
+			}
+		},
+		None => Scene::default(),
+	};
+	cli.apply(&mut scene);
+
+	if let Some(path) = &cli.save_scene {
+		if let Err(err) = scene.save(path) {
+			eprintln!("Failed to save scene to {}: {err}", path.display());
+			std::process::exit(1);
+		}
+	}
+
+	let particles = match scene.particles() {
+		Ok(particles) => particles,
+		Err(err) => {
+			eprintln!("Failed to load particles: {err}");
+			std::process::exit(1);
+		}
+	};
+
+	let environment = match scene.environment() {
+		Ok(environment) => environment,
+		Err(err) => {
+			eprintln!("Failed to load environment: {err}");
+			std::process::exit(1);
+		}
+	};
+
+	if cli.headless {
+		let options = HeadlessOptions {
+			width: cli.size.0,
+			height: cli.size.1,
+			frames: cli.frames,
+			output: cli.output.clone(),
+			scene,
+			shaders: cli.shaders(),
+		};
+		if let Err(err) = pollster::block_on(wgpu_raymarcher::headless::render(
+			&particles,
+			environment.as_ref(),
+			&options,
+		)) {
+			eprintln!("Headless render failed: {err}");
+			std::process::exit(1);
+		}
+		return;
+	}
+
 	let event_loop = EventLoop::new().unwrap();
 	event_loop.set_control_flow(ControlFlow::Poll);
 
-	let mut app = App::default();
+	let options = WindowOptions {
+		size: cli.size,
+		present_mode: cli.present_mode.into(),
+		shaders: cli.shaders(),
+		scene,
+		save_path: cli.save_scene,
+	};
+	let mut app = App::new(particles, environment, options);
 	event_loop.run_app(&mut app).unwrap();
 }
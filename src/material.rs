@@ -0,0 +1,311 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+// Must be the same as the one in shader.wgsl
+pub const MAX_MATERIALS: usize = 16;
+/// Reflectance at normal incidence of every dielectric, about that of water and most plastics.
+pub const DIELECTRIC_F0: f32 = 0.04;
+/// Roughness is clamped to at least this, a perfect mirror's highlight is too small to hit.
+pub const MIN_ROUGHNESS: f32 = 0.045;
+/// Most steps a refracted ray can take through the inside of the surface.
+pub const MAX_INTERIOR_STEPS: u32 = 256;
+
+/// How surfaces respond to light.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shading {
+	/// Plain Lambert diffuse, ignoring the materials.
+	#[default]
+	Lambert,
+	/// GGX specular over an energy-conserving diffuse, from the roughness and metalness of each material.
+	Pbr,
+	/// Clear dielectric: the sky is reflected and refracted through the inside of the surface,
+	/// absorbed along the way, as for water. Only the roughness of the materials is used.
+	Liquid,
+}
+
+impl Shading {
+	// Must match the SHADING_* constants in shader.wgsl
+	fn id(self) -> u32 {
+		match self {
+			Shading::Lambert => 0,
+			Shading::Pbr => 1,
+			Shading::Liquid => 2,
+		}
+	}
+
+	pub fn next(self) -> Self {
+		match self {
+			Shading::Lambert => Shading::Pbr,
+			Shading::Pbr => Shading::Liquid,
+			Shading::Liquid => Shading::Lambert,
+		}
+	}
+}
+
+/// Surface parameters looked up by the material id of a particle, the colour comes from the particle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Material {
+	/// Perceptual roughness, from mirror-like at 0 to fully matte at 1.
+	pub roughness: f32,
+	/// 0 for dielectrics, 1 for metals, which tint their reflections with their colour and have no diffuse.
+	pub metallic: f32,
+}
+
+impl Default for Material {
+	fn default() -> Self {
+		Self {
+			roughness: 0.5,
+			metallic: 0.0,
+		}
+	}
+}
+
+/// Medium inside the surface under [`Shading::Liquid`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Liquid {
+	/// Index of refraction, relative to the air outside.
+	pub ior: f32,
+	/// Fraction of each channel absorbed per unit of distance travelled inside, following Beer-Lambert.
+	pub absorption: Vec3,
+	/// Steps a refracted ray takes looking for the far side of the surface.
+	pub steps: u32,
+}
+
+impl Default for Liquid {
+	fn default() -> Self {
+		Self {
+			ior: 1.33,
+			absorption: Vec3::new(1.5, 0.4, 0.2),
+			steps: 32,
+		}
+	}
+}
+
+impl Liquid {
+	/// Fraction of each channel left after travelling `distance` inside,
+	/// CPU reference of the absorption in `shade_liquid` in `shader.wgsl`.
+	pub fn transmittance(&self, distance: f32) -> Vec3 {
+		(-self.absorption * distance).exp()
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Materials {
+	pub shading: Shading,
+	pub liquid: Liquid,
+	/// Indexed by material id, ids past the end use the last material.
+	pub materials: Vec<Material>,
+}
+
+impl Default for Materials {
+	fn default() -> Self {
+		Self {
+			shading: Shading::default(),
+			liquid: Liquid::default(),
+			materials: vec![Material::default()],
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+	roughness: f32,
+	metallic: f32,
+	_pad: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialsUniform {
+	shading: u32,
+	count: u32,
+	ior: f32,
+	interior_steps: u32,
+	absorption: [f32; 3],
+	_pad: f32,
+	materials: [MaterialUniform; MAX_MATERIALS],
+}
+
+impl Materials {
+	/// Materials past [`MAX_MATERIALS`] are left out.
+	pub fn uniform(&self) -> MaterialsUniform {
+		let mut materials = [MaterialUniform::default(); MAX_MATERIALS];
+		for (uniform, material) in materials.iter_mut().zip(&self.materials) {
+			*uniform = MaterialUniform {
+				roughness: material.roughness,
+				metallic: material.metallic,
+				_pad: [0.0; 2],
+			};
+		}
+		MaterialsUniform {
+			shading: self.shading.id(),
+			count: self.materials.len().min(MAX_MATERIALS) as u32,
+			ior: self.liquid.ior,
+			interior_steps: self.liquid.steps,
+			absorption: self.liquid.absorption.to_array(),
+			_pad: 0.0,
+			materials,
+		}
+	}
+}
+
+impl MaterialsUniform {
+	pub fn bytes(&self) -> &[u8] {
+		bytemuck::bytes_of(self)
+	}
+}
+
+pub fn create_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+	device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		label: Some("Materials Buffer"),
+		contents: bytemuck::bytes_of(&MaterialsUniform::default()),
+		usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+	})
+}
+
+// Must match `brdf` in shader.wgsl
+
+fn fresnel_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+	f0 + (Vec3::ONE - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+/// Trowbridge-Reitz (GGX) distribution of microfacet normals.
+fn distribution_ggx(n_h: f32, alpha: f32) -> f32 {
+	let a2 = alpha * alpha;
+	let d = n_h * n_h * (a2 - 1.0) + 1.0;
+	a2 / (PI * d * d)
+}
+
+/// Height-correlated Smith visibility, the masking term divided by `4 n·v n·l`.
+fn visibility_smith(n_v: f32, n_l: f32, alpha: f32) -> f32 {
+	let a2 = alpha * alpha;
+	let v = n_l * (n_v * n_v * (1.0 - a2) + a2).sqrt();
+	let l = n_v * (n_l * n_l * (1.0 - a2) + a2).sqrt();
+	0.5 / (v + l).max(1e-6)
+}
+
+/// Light reflected toward `v` per unit of light arriving from `l`, all unit vectors pointing away from the surface.
+pub fn brdf(albedo: Vec3, material: &Material, n: Vec3, v: Vec3, l: Vec3) -> Vec3 {
+	let h = (v + l).normalize();
+	let n_v = n.dot(v).max(1e-4);
+	let n_l = n.dot(l).clamp(0.0, 1.0);
+	let n_h = n.dot(h).clamp(0.0, 1.0);
+	let v_h = v.dot(h).clamp(0.0, 1.0);
+	let roughness = material.roughness.clamp(MIN_ROUGHNESS, 1.0);
+	let alpha = roughness * roughness;
+	let metallic = material.metallic.clamp(0.0, 1.0);
+
+	let f0 = Vec3::splat(DIELECTRIC_F0).lerp(albedo, metallic);
+	let fresnel = fresnel_schlick(f0, v_h);
+	let specular = fresnel * distribution_ggx(n_h, alpha) * visibility_smith(n_v, n_l, alpha);
+	// Whatever the specular lobe can reflect toward `v` at most is taken out of the diffuse,
+	// weighting by the Fresnel of each microfacet instead lets grazing views reflect more than they receive
+	let diffuse = (Vec3::ONE - fresnel_schlick(f0, n_v)) * (1.0 - metallic) * albedo / PI;
+	diffuse + specular
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn transmittance_follows_beer_lambert() {
+		let liquid = Liquid::default();
+		assert_eq!(liquid.transmittance(0.0), Vec3::ONE);
+		let t = liquid.transmittance(2.0);
+		assert!(
+			(t - Vec3::new(-3.0f32, -0.8, -0.4).exp())
+				.abs()
+				.max_element()
+				< 1e-6
+		);
+		// Absorbed a fraction per unit of distance, so splitting the path changes nothing
+		let split = liquid.transmittance(0.5) * liquid.transmittance(1.5);
+		assert!((t - split).abs().max_element() < 1e-6);
+		// The most absorbed channel fades first
+		assert!(t.x < t.y && t.y < t.z);
+		let clear = Liquid {
+			absorption: Vec3::ZERO,
+			..liquid
+		};
+		assert_eq!(clear.transmittance(100.0), Vec3::ONE);
+	}
+
+	#[test]
+	fn pbr_shading_is_opt_in() {
+		assert_eq!(Shading::default(), Shading::Lambert);
+		assert_eq!(
+			Materials::default().uniform().shading,
+			Shading::Lambert.id()
+		);
+	}
+
+	/// Fraction of the light arriving along `v` reflected over the whole hemisphere, by midpoint
+	/// integration of `brdf * n·l` over the solid angle.
+	fn directional_albedo(albedo: Vec3, material: &Material, n_v: f32) -> Vec3 {
+		const THETA_STEPS: usize = 512;
+		const PHI_STEPS: usize = 256;
+		let n = Vec3::Z;
+		let v = Vec3::new((1.0 - n_v * n_v).sqrt(), 0.0, n_v);
+		let d_theta = 0.5 * PI / THETA_STEPS as f32;
+		let d_phi = 2.0 * PI / PHI_STEPS as f32;
+		let mut total = Vec3::ZERO;
+		for i in 0..THETA_STEPS {
+			let theta = (i as f32 + 0.5) * d_theta;
+			for j in 0..PHI_STEPS {
+				let phi = (j as f32 + 0.5) * d_phi;
+				let l = Vec3::new(
+					theta.sin() * phi.cos(),
+					theta.sin() * phi.sin(),
+					theta.cos(),
+				);
+				total +=
+					brdf(albedo, material, n, v, l) * theta.cos() * theta.sin() * d_theta * d_phi;
+			}
+		}
+		total
+	}
+
+	#[test]
+	fn brdf_conserves_energy() {
+		for roughness in [0.3, 0.6, 1.0] {
+			for metallic in [0.0, 0.5, 1.0] {
+				for n_v in [0.1, 0.5, 1.0] {
+					let material = Material {
+						roughness,
+						metallic,
+					};
+					let reflected = directional_albedo(Vec3::ONE, &material, n_v);
+					assert!(
+						reflected.max_element() <= 1.01,
+						"{material:?} at n·v = {n_v} reflects {reflected}"
+					);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn rough_white_dielectric_reflects_most_light() {
+		let material = Material {
+			roughness: 1.0,
+			metallic: 0.0,
+		};
+		let reflected = directional_albedo(Vec3::ONE, &material, 1.0);
+		assert!(reflected.min_element() > 0.9, "reflects only {reflected}");
+	}
+
+	#[test]
+	fn black_dielectric_only_reflects_specular() {
+		let reflected = directional_albedo(Vec3::ZERO, &Material::default(), 1.0);
+		assert!(
+			reflected.max_element() < 2.0 * DIELECTRIC_F0,
+			"reflects {reflected}"
+		);
+	}
+}
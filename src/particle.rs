@@ -1,27 +1,100 @@
+use crate::blend::Blend;
 use glam::{vec3, Vec3};
 use rand::Rng;
 use wgpu::util::DeviceExt;
 
 pub const BUNDLE_SIZE: u32 = 32;
-pub const BUNDLE_SIZE_BYTES: u32 = std::mem::size_of::<Particle>() as u32 * BUNDLE_SIZE;
+/// Most particles a generated source may hold, 128 MiB of them, wgpu's default storage buffer binding.
+pub const MAX_PARTICLES: u32 = (128 << 20) / std::mem::size_of::<Particle>() as u32;
 
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Particle {
 	position: [f32; 3],
 	radius: f32,
+	color: [f32; 3],
+	material: u32,
+}
+
+impl Particle {
+	/// Padding particle placed so far away that it never wins a (smooth) minimum.
+	pub const SENTINEL: Particle = Particle {
+		position: [1.0e5; 3],
+		radius: 0.0,
+		color: [0.0; 3],
+		material: 0,
+	};
+
+	/// A white particle of the default material.
+	pub fn new(position: Vec3, radius: f32) -> Self {
+		Self {
+			position: position.to_array(),
+			radius,
+			color: [1.0; 3],
+			material: 0,
+		}
+	}
+
+	pub fn with_color(mut self, color: Vec3) -> Self {
+		self.color = color.to_array();
+		self
+	}
+
+	pub fn with_material(mut self, material: u32) -> Self {
+		self.material = material;
+		self
+	}
+
+	pub fn position(&self) -> Vec3 {
+		Vec3::from_array(self.position)
+	}
+
+	pub fn set_position(&mut self, position: Vec3) {
+		self.position = position.to_array();
+	}
+
+	pub fn radius(&self) -> f32 {
+		self.radius
+	}
+
+	pub fn color(&self) -> Vec3 {
+		Vec3::from_array(self.color)
+	}
+
+	pub fn material(&self) -> u32 {
+		self.material
+	}
+
+	/// CPU reference of `sdf_particle` in `compute.wgsl`.
+	pub fn distance(&self, p: Vec3) -> f32 {
+		(p - self.position()).length() - self.radius
+	}
+}
+
+/// CPU reference of the SDF baked by `cs_sdf`, evaluated over every particle.
+pub fn sdf(particles: &[Particle], blend: &Blend, p: Vec3) -> f32 {
+	// Same starting value as `cs_clear`
+	particles
+		.iter()
+		.map(|particle| particle.distance(p))
+		.fold(1000.0, |a, b| blend.apply(a, b))
 }
 
 const fn uvec3(x: usize, y: usize, z: usize) -> Vec3 {
 	vec3(x as f32, y as f32, z as f32)
 }
 
-pub fn bundled(particles: &[Particle]) -> &[Particle] {
+/// Pads `particles` with [`Particle::SENTINEL`] up to a whole number of bundles.
+/// Always yields at least one bundle so the buffer can be bound even when empty.
+pub fn bundled(particles: &[Particle]) -> Vec<Particle> {
 	const N: usize = BUNDLE_SIZE as usize;
-	&particles[0..((particles.len() / N) * N)]
+	let len = particles.len().div_ceil(N).max(1) * N;
+	let mut bundled = Vec::with_capacity(len);
+	bundled.extend_from_slice(particles);
+	bundled.resize(len, Particle::SENTINEL);
+	bundled
 }
 
-#[allow(unused)]
 pub fn grid(size_x: usize, size_y: usize, size_z: usize) -> Vec<Particle> {
 	let mut particles = vec![];
 	let mut rng = rand::rng();
@@ -33,48 +106,118 @@ pub fn grid(size_x: usize, size_y: usize, size_z: usize) -> Vec<Particle> {
 				position += vec3(0.5, 0.5, 0.5);
 				position /= size;
 				position -= vec3(0.5, 0.5, 0.5);
-				let position = position.to_array();
-				particles.push(Particle {
-					position,
-					radius: rng.random_range(0.05..=0.1),
-					..Default::default()
-				})
+				particles.push(Particle::new(position, rng.random_range(0.05..=0.1)))
 			}
 		}
 	}
 	particles
 }
 
-#[allow(unused)]
 pub fn random(n: usize) -> Vec<Particle> {
 	let mut rng = rand::rng();
 	let mut particles = Vec::with_capacity(n);
 
 	for _ in 0..n {
-		let position = [
+		let position = vec3(
 			rng.random_range(0.2..=0.8),
 			rng.random_range(0.2..=0.8),
 			rng.random_range(0.2..=0.8),
-		];
+		);
 		let radius = rng.random_range(0.025..=0.05);
-		particles.push(Particle {
-			position,
-			radius,
-			..Default::default()
-		});
+		particles.push(Particle::new(position, radius));
 	}
 
 	particles
 }
 
-pub fn create_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+pub fn create_buffer(device: &wgpu::Device, particles: &[Particle]) -> wgpu::Buffer {
 	device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
 		label: Some("Particle Buffer"),
-		contents: bytemuck::cast_slice(bundled(&grid(8, 8, 8))),
-		usage: wgpu::BufferUsages::STORAGE,
+		contents: bytemuck::cast_slice(&bundled(particles)),
+		usage: wgpu::BufferUsages::STORAGE
+			| wgpu::BufferUsages::COPY_DST
+			| wgpu::BufferUsages::COPY_SRC,
 	})
 }
 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const EPSILON: f32 = 1.0e-6;
+
+	#[test]
+	fn sdf_of_single_particle() {
+		let hard = Blend::default();
+		for radius in [0.05, 0.25, 1.0] {
+			let center = vec3(0.5, -0.25, 2.0);
+			let particles = [Particle::new(center, radius)];
+			let at = |p: Vec3| sdf(&particles, &hard, p);
+
+			assert!((at(center) + radius).abs() < EPSILON);
+			assert!((at(center + vec3(0.5 * radius, 0.0, 0.0)) + 0.5 * radius).abs() < EPSILON);
+			assert!(at(center + vec3(0.0, radius, 0.0)).abs() < EPSILON);
+			assert!((at(center + vec3(0.0, 0.0, -3.0)) - (3.0 - radius)).abs() < EPSILON);
+		}
+	}
+
+	#[test]
+	fn sdf_is_hard_minimum_over_particles() {
+		let hard = Blend::default();
+		let particles = [
+			Particle::new(vec3(0.0, 0.0, 0.0), 0.1),
+			Particle::new(vec3(1.0, 0.0, 0.0), 0.3),
+			Particle::new(vec3(0.0, 2.0, 0.0), 0.5),
+		];
+		for p in [
+			vec3(0.0, 0.0, 0.0),
+			vec3(0.6, 0.0, 0.0),
+			vec3(0.0, 1.0, 0.0),
+			vec3(-1.0, -1.0, 3.0),
+		] {
+			let expected = particles
+				.iter()
+				.map(|particle| particle.distance(p))
+				.fold(f32::INFINITY, f32::min);
+			assert_eq!(sdf(&particles, &hard, p), expected, "at {p}");
+		}
+		// The larger particle wins even though the smaller one has the nearer centre
+		assert!((sdf(&particles, &hard, vec3(0.45, 0.0, 0.0)) - 0.25).abs() < EPSILON);
+	}
+
+	#[test]
+	fn bundled_pads_with_sentinels() {
+		for n in [0, 1, 31, 32, 33] {
+			let particles: Vec<Particle> = (0..n)
+				.map(|i| Particle::new(Vec3::splat(i as f32), 0.1).with_material(i as u32))
+				.collect();
+			let bundled = bundled(&particles);
+
+			assert_eq!(bundled.len() % BUNDLE_SIZE as usize, 0, "{n} particles");
+			assert!(!bundled.is_empty(), "{n} particles");
+			assert_eq!(
+				bytemuck::cast_slice::<_, u8>(&bundled[..n]),
+				bytemuck::cast_slice::<_, u8>(&particles),
+				"{n} particles"
+			);
+			for sentinel in &bundled[n..] {
+				assert_eq!(
+					bytemuck::bytes_of(sentinel),
+					bytemuck::bytes_of(&Particle::SENTINEL)
+				);
+			}
+		}
+		for (n, len) in [(0, 32), (1, 32), (31, 32), (32, 32), (33, 64)] {
+			assert_eq!(bundled(&vec![Particle::default(); n]).len(), len);
+		}
+	}
+
+	#[test]
+	fn sdf_without_particles_is_clear_value() {
+		assert_eq!(sdf(&[], &Blend::default(), Vec3::ZERO), 1000.0);
+	}
+}
+
 // impl ParticleBindingData {
 // 	pub fn new(device: &wgpu::Device, data: &[Particle]) -> Self {
 // 		let particles_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -0,0 +1,111 @@
+use std::sync::{
+	atomic::{AtomicU8, Ordering},
+	Arc,
+};
+
+// States of a `Readback`, in the order it goes through them
+const IDLE: u8 = 0;
+const COPIED: u8 = 1;
+const MAPPING: u8 = 2;
+const MAPPED: u8 = 3;
+
+/// Reads a GPU `u32` back to the CPU without ever waiting for the GPU, for counters and flags
+/// the passes write. The value arrives a frame or two after it was written.
+pub struct Readback {
+	staging: wgpu::Buffer,
+	state: Arc<AtomicU8>,
+}
+
+impl Readback {
+	pub fn new(device: &wgpu::Device, label: &str) -> Self {
+		let staging = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some(label),
+			size: std::mem::size_of::<u32>() as u64,
+			usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+			mapped_at_creation: false,
+		});
+		Self {
+			staging,
+			state: Arc::new(AtomicU8::new(IDLE)),
+		}
+	}
+
+	/// Records a copy of the `u32` at `offset` in `source`, unless the previous one is still in flight.
+	/// `source` needs `COPY_SRC`.
+	pub fn copy(&self, encoder: &mut wgpu::CommandEncoder, source: &wgpu::Buffer, offset: u64) {
+		if self.state.load(Ordering::Acquire) != IDLE {
+			return;
+		}
+		encoder.copy_buffer_to_buffer(source, offset, &self.staging, 0, self.staging.size());
+		self.state.store(COPIED, Ordering::Release);
+	}
+
+	/// Returns the value of the last copy once the GPU is done with it.
+	///
+	/// Must be called before recording the next frame: a copy recorded by the previous one has
+	/// been submitted by then, so it is mapped here and read on a later call.
+	pub fn read(&self) -> Option<u32> {
+		match self.state.load(Ordering::Acquire) {
+			COPIED => {
+				self.state.store(MAPPING, Ordering::Release);
+				let state = self.state.clone();
+				self.staging
+					.slice(..)
+					.map_async(wgpu::MapMode::Read, move |result| {
+						let next = if result.is_ok() { MAPPED } else { IDLE };
+						state.store(next, Ordering::Release);
+					});
+				None
+			}
+			MAPPED => {
+				let value = *bytemuck::from_bytes(&self.staging.slice(..).get_mapped_range());
+				self.staging.unmap();
+				self.state.store(IDLE, Ordering::Release);
+				Some(value)
+			}
+			_ => None,
+		}
+	}
+}
+
+/// Prints `message` when a condition starts holding, instead of on every frame it does.
+#[derive(Debug, Default)]
+pub struct Warning {
+	active: bool,
+}
+
+impl Warning {
+	pub fn update(&mut self, active: bool, message: impl FnOnce() -> String) {
+		if active && !self.active {
+			eprintln!("Warning: {}", message());
+		}
+		self.active = active;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use wgpu::util::DeviceExt;
+
+	#[test]
+	fn reads_value_after_copy_is_submitted() {
+		let (device, queue) =
+			pollster::block_on(crate::headless::request_device()).expect("no adapter to test with");
+		let source = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: None,
+			contents: bytemuck::cast_slice(&[7u32, 42]),
+			usage: wgpu::BufferUsages::COPY_SRC,
+		});
+		let readback = Readback::new(&device, "Test Readback Buffer");
+		assert_eq!(readback.read(), None);
+
+		let mut encoder = device.create_command_encoder(&Default::default());
+		readback.copy(&mut encoder, &source, 4);
+		queue.submit([encoder.finish()]);
+		assert_eq!(readback.read(), None);
+		device.poll(wgpu::PollType::Wait).unwrap();
+		assert_eq!(readback.read(), Some(42));
+		assert_eq!(readback.read(), None);
+	}
+}
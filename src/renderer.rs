@@ -0,0 +1,1189 @@
+use crate::{
+	binning::{self, Binning, SdfMethod},
+	blend::{self, Blend},
+	bricks::Bricks,
+	camera::{self, Camera},
+	environment::Environment,
+	grid::ParticleGrid,
+	hdr::HdrImage,
+	light::{self, Lighting},
+	material::{self, Materials},
+	particle::{self, Particle},
+	screen,
+	sdf::{self, SdfSpace, Slicing, WorldVolume},
+	shaders::{self, ShaderError, Shaders},
+	simulation::Simulation,
+	time::{self, TimeUniform},
+};
+use glam::{UVec2, UVec3};
+
+/// Owns every GPU resource of the raymarcher and records its passes.
+/// Knows nothing about windows, so it can draw into any texture view.
+pub struct Renderer {
+	device: wgpu::Device,
+	queue: wgpu::Queue,
+	shaders: Shaders,
+	target_format: wgpu::TextureFormat,
+	compute_pipeline_layout: wgpu::PipelineLayout,
+	render_pipeline_layout: wgpu::PipelineLayout,
+	pipelines: Pipelines,
+	compute_layout: wgpu::BindGroupLayout,
+	render_layout: wgpu::BindGroupLayout,
+	sdf_sampler: wgpu::Sampler,
+	sdf_texture: wgpu::Texture,
+	compute_group: wgpu::BindGroup,
+	render_group: wgpu::BindGroup,
+	particles_buffer: wgpu::Buffer,
+	uniforms: Uniforms,
+	froxels: UVec3,
+	space: SdfSpace,
+	world: WorldVolume,
+	/// Blend the world volume or particle grid was last built with, `None` when it has to be built again.
+	world_baked: Option<Blend>,
+	pub blend: Blend,
+	pub lighting: Lighting,
+	pub materials: Materials,
+	pub slicing: Slicing,
+	pub simulation: Simulation,
+	pub binning: Binning,
+	bricks: Bricks,
+	pub grid: ParticleGrid,
+	pub environment: Environment,
+	pub sdf_method: SdfMethod,
+}
+
+impl Renderer {
+	pub fn new(
+		device: &wgpu::Device,
+		queue: &wgpu::Queue,
+		target_format: wgpu::TextureFormat,
+		particles: &[Particle],
+		froxels: UVec3,
+		shaders: &Shaders,
+	) -> Result<Renderer, ShaderError> {
+		let device = device.clone();
+		let queue = queue.clone();
+		let froxels = fit_froxels(&device, froxels);
+
+		let compute_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Compute Layout Group"),
+			entries: &[
+				wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::COMPUTE,
+					ty: wgpu::BindingType::StorageTexture {
+						access: wgpu::StorageTextureAccess::WriteOnly,
+						format: wgpu::TextureFormat::Rgba16Float,
+						view_dimension: wgpu::TextureViewDimension::D3,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 1,
+					visibility: wgpu::ShaderStages::COMPUTE,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 2,
+					visibility: wgpu::ShaderStages::COMPUTE,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 3,
+					visibility: wgpu::ShaderStages::COMPUTE,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 4,
+					visibility: wgpu::ShaderStages::COMPUTE,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 5,
+					visibility: wgpu::ShaderStages::COMPUTE,
+					ty: wgpu::BindingType::StorageTexture {
+						access: wgpu::StorageTextureAccess::WriteOnly,
+						format: wgpu::TextureFormat::R32Uint,
+						view_dimension: wgpu::TextureViewDimension::D3,
+					},
+					count: None,
+				},
+			],
+		});
+
+		let render_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Render Layout Group"),
+			entries: &[
+				wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 1,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 2,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 3,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 4,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Texture {
+						sample_type: wgpu::TextureSampleType::Float { filterable: true },
+						view_dimension: wgpu::TextureViewDimension::D3,
+						multisampled: false,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 5,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 6,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 7,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 8,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 9,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Texture {
+						sample_type: wgpu::TextureSampleType::Uint,
+						view_dimension: wgpu::TextureViewDimension::D3,
+						multisampled: false,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 10,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+			],
+		});
+
+		let particles_buffer = particle::create_buffer(&device, particles);
+		let binning = Binning::new(
+			&device,
+			&particles_buffer,
+			particles.len() as u32,
+			binning::tile_grid(froxels.x, froxels.y, froxels.z),
+		);
+
+		let world = WorldVolume::default();
+		let bricks = Bricks::new(&device, &world);
+		let grid = ParticleGrid::new(&device, &particles_buffer, particles.len() as u32, &world);
+		let environment = Environment::new(&device, shaders)?;
+
+		let compute_pipeline_layout =
+			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+				label: Some("Raymarch Compute Pipeline Layout"),
+				bind_group_layouts: &[
+					&compute_layout,
+					binning.layout(),
+					bricks.compute_layout(),
+					grid.compute_layout(),
+				],
+				push_constant_ranges: &[],
+			});
+
+		let render_pipeline_layout =
+			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+				label: Some("Raymarch Render Pipeline Layout"),
+				bind_group_layouts: &[
+					&render_layout,
+					bricks.render_layout(),
+					grid.render_layout(),
+					environment.render_layout(),
+				],
+				push_constant_ranges: &[],
+			});
+
+		let pipelines = Pipelines::new(
+			&device,
+			shaders,
+			&compute_pipeline_layout,
+			&render_pipeline_layout,
+			target_format,
+		)?;
+
+		let uniforms = Uniforms::new(&device);
+		let sdf_sampler = sdf::create_sampler(&device);
+		let (sdf_texture, compute_group, render_group) = create_sdf_groups(
+			&device,
+			&compute_layout,
+			&render_layout,
+			&sdf_sampler,
+			&uniforms,
+			froxels,
+		);
+
+		let simulation =
+			Simulation::new(&device, &particles_buffer, particles.len() as u32, shaders)?;
+
+		Ok(Renderer {
+			device,
+			queue,
+			shaders: shaders.clone(),
+			target_format,
+			compute_pipeline_layout,
+			render_pipeline_layout,
+			pipelines,
+			compute_layout,
+			render_layout,
+			sdf_sampler,
+			sdf_texture,
+			compute_group,
+			render_group,
+			particles_buffer,
+			uniforms,
+			froxels,
+			space: SdfSpace::default(),
+			world,
+			world_baked: None,
+			blend: Blend::default(),
+			lighting: Lighting::default(),
+			materials: Materials::default(),
+			slicing: Slicing::default(),
+			simulation,
+			binning,
+			bricks,
+			grid,
+			environment,
+			sdf_method: SdfMethod::default(),
+		})
+	}
+
+	pub fn froxels(&self) -> UVec3 {
+		self.froxels
+	}
+
+	/// Recreates the tile lists, and the SDF texture when it holds froxels, at a new resolution.
+	/// Grids too large for the device are made coarser, [`Renderer::froxels`] returns the one in use.
+	pub fn set_froxels(&mut self, froxels: UVec3) {
+		let froxels = fit_froxels(&self.device, froxels);
+		if froxels == self.froxels {
+			return;
+		}
+		self.froxels = froxels;
+		self.binning.resize(
+			&self.device,
+			&self.particles_buffer,
+			binning::tile_grid(froxels.x, froxels.y, froxels.z),
+		);
+		if self.space == SdfSpace::Froxels {
+			self.rebuild_sdf();
+		}
+	}
+
+	pub fn space(&self) -> SdfSpace {
+		self.space
+	}
+
+	/// Switches the space the SDF is baked in, recreating its texture.
+	pub fn set_space(&mut self, space: SdfSpace) {
+		if space == self.space {
+			return;
+		}
+		self.space = space;
+		self.rebuild_sdf();
+	}
+
+	/// The texture the SDF was last baked into, the one the raymarch samples.
+	pub fn sdf_texture(&self) -> &wgpu::Texture {
+		&self.sdf_texture
+	}
+
+	pub fn world(&self) -> WorldVolume {
+		self.world
+	}
+
+	/// Lights the scene with `image` instead of the sky gradient.
+	pub fn set_environment(&mut self, image: &HdrImage) {
+		self.environment.load(&self.device, &self.queue, image);
+	}
+
+	/// Moves or resizes the world volume, recreating the SDF texture when it's in use.
+	pub fn set_world(&mut self, world: WorldVolume) {
+		if world == self.world {
+			return;
+		}
+		self.world = world;
+		self.bricks.resize(&self.device, &world);
+		self.grid
+			.resize(&self.device, &self.particles_buffer, world.grid_cells);
+		if self.space != SdfSpace::Froxels {
+			self.rebuild_sdf();
+		}
+	}
+
+	/// Whether the SDF, or the particle grid in [`SdfSpace::Exact`], has to be built this frame.
+	/// Froxels follow the camera, the world volume and the grid only the particles.
+	fn needs_bake(&self, steps: u32) -> bool {
+		match self.space {
+			SdfSpace::Froxels => true,
+			SdfSpace::World | SdfSpace::Bricks | SdfSpace::Exact => {
+				steps > 0 || self.world_baked != Some(self.blend)
+			}
+		}
+	}
+
+	/// Number of voxels of the SDF texture along each axis.
+	fn sdf_size(&self) -> UVec3 {
+		match self.space {
+			SdfSpace::Froxels => self.froxels,
+			SdfSpace::World => self.world.resolution,
+			SdfSpace::Bricks => self.bricks.atlas_size(),
+			// Never sampled, the raymarcher evaluates the particles instead
+			SdfSpace::Exact => UVec3::ONE,
+		}
+	}
+
+	fn rebuild_sdf(&mut self) {
+		(self.sdf_texture, self.compute_group, self.render_group) = create_sdf_groups(
+			&self.device,
+			&self.compute_layout,
+			&self.render_layout,
+			&self.sdf_sampler,
+			&self.uniforms,
+			self.sdf_size(),
+		);
+		self.world_baked = None;
+	}
+
+	/// Rebuilds the SDF and raymarch pipelines from the current shader sources.
+	/// On failure the previous pipelines are kept, so rendering carries on.
+	pub fn reload_shaders(&mut self) -> Result<(), ShaderError> {
+		self.pipelines = Pipelines::new(
+			&self.device,
+			&self.shaders,
+			&self.compute_pipeline_layout,
+			&self.render_pipeline_layout,
+			self.target_format,
+		)?;
+		self.world_baked = None;
+		Ok(())
+	}
+
+	pub fn device(&self) -> &wgpu::Device {
+		&self.device
+	}
+
+	pub fn queue(&self) -> &wgpu::Queue {
+		&self.queue
+	}
+
+	/// Advances the simulation, then records the SDF build and the raymarch into `target`.
+	pub fn render(
+		&mut self,
+		encoder: &mut wgpu::CommandEncoder,
+		target: &wgpu::TextureView,
+		size: (u32, u32),
+		camera: &Camera,
+		time: TimeUniform,
+	) {
+		let steps = self.simulation.advance(&time);
+		self.binning.check_overflow();
+		self.bricks.check_capacity();
+		self.grid.check_overflow();
+		let u_screen = screen::ScreenUniform::new(size);
+		let u_camera = camera.uniform();
+		let u_blend = self.blend.uniform();
+		let u_lighting = self.lighting.uniform();
+		let u_materials = self.materials.uniform();
+		let u_slicing = self.slicing.uniform();
+		let u_volume = sdf::VolumeUniform::new(self.space, &self.world);
+
+		let u_screen = u_screen.bytes();
+		let u_time = time.bytes();
+		let u_camera = u_camera.bytes();
+		let u_blend = u_blend.bytes();
+		let u_lighting = u_lighting.bytes();
+		let u_materials = u_materials.bytes();
+		let u_slicing = u_slicing.bytes();
+		let u_volume = u_volume.bytes();
+
+		self.queue.write_buffer(&self.uniforms.screen, 0, u_screen);
+		self.queue.write_buffer(&self.uniforms.time, 0, u_time);
+		self.queue.write_buffer(&self.uniforms.camera, 0, u_camera);
+		self.queue.write_buffer(&self.uniforms.blend, 0, u_blend);
+		self.queue
+			.write_buffer(&self.uniforms.lighting, 0, u_lighting);
+		self.queue
+			.write_buffer(&self.uniforms.materials, 0, u_materials);
+		self.queue
+			.write_buffer(&self.uniforms.slicing, 0, u_slicing);
+		self.queue.write_buffer(&self.uniforms.volume, 0, u_volume);
+		self.binning.write_uniform(&self.queue);
+		self.grid.write_uniform(&self.queue, &self.world);
+		self.environment.write_uniform(&self.queue);
+
+		// Simulation Pass
+		self.simulation
+			.encode(&self.queue, encoder, &self.particles_buffer, steps);
+
+		let bake = self.needs_bake(steps);
+
+		let binned = self.space == SdfSpace::Froxels && self.sdf_method == SdfMethod::Binned;
+		if bake && binned {
+			self.binning.allocate(&self.device, &self.particles_buffer);
+			self.binning.clear_overflow(encoder);
+		}
+		if bake && self.space == SdfSpace::Exact {
+			self.grid.clear_overflow(encoder);
+		}
+
+		// Compute Pass
+		if bake {
+			let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+				label: Some("Compute Pass"),
+				timestamp_writes: None,
+			});
+
+			let size = self.sdf_size();
+			let (wg_x, wg_y, wg_z) = (8, 4, 4);
+			let dispatch_x = size.x.div_ceil(wg_x);
+			let dispatch_y = size.y.div_ceil(wg_y);
+			let dispatch_z = size.z.div_ceil(wg_z);
+
+			pass.set_bind_group(0, &self.compute_group, &[]);
+			pass.set_bind_group(1, self.binning.group(), &[]);
+			pass.set_bind_group(2, self.bricks.compute_group(), &[]);
+			pass.set_bind_group(3, self.grid.compute_group(), &[]);
+
+			match (self.space, self.sdf_method) {
+				// Tiles are binned in screen space, so the world volume always evaluates every particle
+				(SdfSpace::World, _) => {
+					pass.set_pipeline(&self.pipelines.compute_world_pipeline);
+					pass.dispatch_workgroups(dispatch_x, dispatch_y, dispatch_z);
+					self.world_baked = Some(self.blend);
+				}
+				(SdfSpace::Exact, _) => {
+					pass.set_pipeline(&self.pipelines.compute_clear_cells_pipeline);
+					pass.dispatch_workgroups(self.grid.cell_workgroups(), 1, 1);
+
+					pass.set_pipeline(&self.pipelines.compute_bin_cells_pipeline);
+					pass.dispatch_workgroups(self.grid.particle_workgroups(), 1, 1);
+					self.world_baked = Some(self.blend);
+				}
+				(SdfSpace::Bricks, _) => {
+					let (cells_x, cells_y, cells_z) = self.bricks.cell_workgroups();
+					pass.set_pipeline(&self.pipelines.compute_bricks_clear_pipeline);
+					pass.dispatch_workgroups(1, 1, 1);
+
+					pass.set_pipeline(&self.pipelines.compute_bricks_mark_pipeline);
+					pass.dispatch_workgroups(cells_x, cells_y, cells_z);
+
+					pass.set_pipeline(&self.pipelines.compute_bricks_args_pipeline);
+					pass.dispatch_workgroups(1, 1, 1);
+
+					// Only as many bricks as were allocated are filled
+					pass.set_pipeline(&self.pipelines.compute_bricks_fill_pipeline);
+					pass.dispatch_workgroups_indirect(self.bricks.args(), 0);
+					self.world_baked = Some(self.blend);
+				}
+				(SdfSpace::Froxels, SdfMethod::Bundled) => {
+					pass.set_pipeline(&self.pipelines.compute_calc_pipeline);
+					pass.dispatch_workgroups(dispatch_x, dispatch_y, dispatch_z);
+				}
+				(SdfSpace::Froxels, SdfMethod::Binned) => {
+					pass.set_pipeline(&self.pipelines.compute_clear_tiles_pipeline);
+					pass.dispatch_workgroups(self.binning.tile_workgroups(), 1, 1);
+
+					pass.set_pipeline(&self.pipelines.compute_bin_pipeline);
+					pass.dispatch_workgroups(self.binning.particle_workgroups(), 1, 1);
+
+					pass.set_pipeline(&self.pipelines.compute_binned_pipeline);
+					pass.dispatch_workgroups(dispatch_x, dispatch_y, dispatch_z);
+				}
+			}
+		}
+		if bake && binned {
+			self.binning.copy_overflow(encoder);
+		}
+		if bake && self.space == SdfSpace::Bricks {
+			self.bricks.copy_count(encoder);
+		}
+		if bake && self.space == SdfSpace::Exact {
+			self.grid.copy_overflow(encoder);
+		}
+
+		// Render Pass
+		{
+			let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+				label: Some("Render Pass"),
+				color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+					view: target,
+					resolve_target: None,
+					ops: wgpu::Operations {
+						load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+						store: wgpu::StoreOp::Store,
+					},
+				})],
+				timestamp_writes: None,
+				depth_stencil_attachment: None,
+				occlusion_query_set: None,
+			});
+			pass.set_bind_group(0, &self.render_group, &[]);
+			pass.set_bind_group(1, self.bricks.render_group(), &[]);
+			pass.set_bind_group(2, self.grid.render_group(), &[]);
+			pass.set_bind_group(3, self.environment.render_group(), &[]);
+			pass.set_pipeline(&self.pipelines.render_pipeline);
+			pass.draw(0..6, 0..1);
+		}
+	}
+}
+
+/// Halves the width and height of `froxels` until its tile lists and SDF texture fit the limits of `device`.
+fn fit_froxels(device: &wgpu::Device, froxels: UVec3) -> UVec3 {
+	let limits = device.limits();
+	let max_bytes = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+	let mut froxels = froxels.min(UVec3::splat(limits.max_texture_dimension_3d));
+	while binning::tile_entry_bytes(froxels) > max_bytes && froxels.truncate() != UVec2::ONE {
+		froxels = (froxels.truncate() / 2).max(UVec2::ONE).extend(froxels.z);
+	}
+	froxels
+}
+
+/// Uniform buffers shared by the compute and render groups.
+struct Uniforms {
+	screen: wgpu::Buffer,
+	time: wgpu::Buffer,
+	camera: wgpu::Buffer,
+	blend: wgpu::Buffer,
+	lighting: wgpu::Buffer,
+	materials: wgpu::Buffer,
+	slicing: wgpu::Buffer,
+	volume: wgpu::Buffer,
+}
+
+impl Uniforms {
+	fn new(device: &wgpu::Device) -> Self {
+		Self {
+			screen: screen::create_buffer(device),
+			time: time::create_buffer(device),
+			camera: camera::create_buffer(device),
+			blend: blend::create_buffer(device),
+			lighting: light::create_buffer(device),
+			materials: material::create_buffer(device),
+			slicing: sdf::create_buffer(device),
+			volume: sdf::create_volume_buffer(device),
+		}
+	}
+}
+
+/// Creates the SDF and material textures of `size` voxels and the compute and render groups bound to them,
+/// returning the SDF texture alongside the groups.
+fn create_sdf_groups(
+	device: &wgpu::Device,
+	compute_layout: &wgpu::BindGroupLayout,
+	render_layout: &wgpu::BindGroupLayout,
+	sdf_sampler: &wgpu::Sampler,
+	uniforms: &Uniforms,
+	size: UVec3,
+) -> (wgpu::Texture, wgpu::BindGroup, wgpu::BindGroup) {
+	let sdf_texture = sdf::create_texture(device, size.x, size.y, size.z);
+	let sdf_view = sdf::create_view(&sdf_texture);
+	let material_texture = sdf::create_material_texture(device, size.x, size.y, size.z);
+	let material_view = sdf::create_view(&material_texture);
+
+	let compute_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Compute Group"),
+		layout: compute_layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: wgpu::BindingResource::TextureView(&sdf_view),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: uniforms.camera.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: uniforms.blend.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: uniforms.slicing.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 4,
+				resource: uniforms.volume.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 5,
+				resource: wgpu::BindingResource::TextureView(&material_view),
+			},
+		],
+	});
+
+	let render_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Render Group"),
+		layout: render_layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: uniforms.screen.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: uniforms.camera.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: uniforms.time.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: wgpu::BindingResource::Sampler(sdf_sampler),
+			},
+			wgpu::BindGroupEntry {
+				binding: 4,
+				resource: wgpu::BindingResource::TextureView(&sdf_view),
+			},
+			wgpu::BindGroupEntry {
+				binding: 5,
+				resource: uniforms.lighting.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 6,
+				resource: uniforms.slicing.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 7,
+				resource: uniforms.volume.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 8,
+				resource: uniforms.blend.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 9,
+				resource: wgpu::BindingResource::TextureView(&material_view),
+			},
+			wgpu::BindGroupEntry {
+				binding: 10,
+				resource: uniforms.materials.as_entire_binding(),
+			},
+		],
+	});
+
+	(sdf_texture, compute_group, render_group)
+}
+
+/// Every pipeline built from `compute.wgsl` and `shader.wgsl`, replaced as a whole on reload.
+struct Pipelines {
+	compute_calc_pipeline: wgpu::ComputePipeline,
+	compute_clear_tiles_pipeline: wgpu::ComputePipeline,
+	compute_bin_pipeline: wgpu::ComputePipeline,
+	compute_binned_pipeline: wgpu::ComputePipeline,
+	compute_world_pipeline: wgpu::ComputePipeline,
+	compute_bricks_clear_pipeline: wgpu::ComputePipeline,
+	compute_bricks_mark_pipeline: wgpu::ComputePipeline,
+	compute_bricks_args_pipeline: wgpu::ComputePipeline,
+	compute_bricks_fill_pipeline: wgpu::ComputePipeline,
+	compute_clear_cells_pipeline: wgpu::ComputePipeline,
+	compute_bin_cells_pipeline: wgpu::ComputePipeline,
+	render_pipeline: wgpu::RenderPipeline,
+}
+
+impl Pipelines {
+	fn new(
+		device: &wgpu::Device,
+		shaders: &Shaders,
+		compute_pipeline_layout: &wgpu::PipelineLayout,
+		render_pipeline_layout: &wgpu::PipelineLayout,
+		target_format: wgpu::TextureFormat,
+	) -> Result<Self, ShaderError> {
+		let compute_shader = shaders.create_module(device, shaders::COMPUTE)?;
+		let render_shader = shaders.create_module(device, shaders::RENDER)?;
+
+		// A shader can be valid on its own and still not fit its pipeline, e.g. a renamed entry point
+		device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+		let compute_calc_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Calc SDF)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_sdf"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let compute_clear_tiles_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Clear Tiles)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_clear_tiles"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let compute_bin_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Bin Particles)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_bin_particles"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let compute_binned_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Calc Binned SDF)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_sdf_binned"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let compute_world_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Calc World SDF)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_sdf_world"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let compute_bricks_clear_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Clear Bricks)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_bricks_clear"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let compute_bricks_mark_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Mark Bricks)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_bricks_mark"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let compute_bricks_args_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Brick Args)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_bricks_args"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let compute_bricks_fill_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Fill Bricks)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_bricks_fill"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let compute_clear_cells_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Clear Cells)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_clear_cells"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let compute_bin_cells_pipeline =
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some("Compute Pipeline (Bin Cells)"),
+				layout: Some(compute_pipeline_layout),
+				module: &compute_shader,
+				entry_point: Some("cs_bin_cells"),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			});
+
+		let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("Render Pipeline"),
+			layout: Some(render_pipeline_layout),
+			vertex: wgpu::VertexState {
+				module: &render_shader,
+				entry_point: Some("vs_main"),
+				compilation_options: Default::default(),
+				buffers: &[],
+			},
+			fragment: Some(wgpu::FragmentState {
+				module: &render_shader,
+				entry_point: Some("fs_main"),
+				compilation_options: Default::default(),
+				targets: &[Some(wgpu::ColorTargetState {
+					format: target_format,
+					blend: Some(wgpu::BlendState::REPLACE),
+					write_mask: wgpu::ColorWrites::ALL,
+				})],
+			}),
+			primitive: Default::default(),
+			multisample: Default::default(),
+			depth_stencil: Default::default(),
+			multiview: Default::default(),
+			cache: Default::default(),
+		});
+
+		if let Some(err) = pollster::block_on(device.pop_error_scope()) {
+			return Err(ShaderError::Pipeline(err.to_string()));
+		}
+
+		Ok(Self {
+			compute_calc_pipeline,
+			compute_clear_tiles_pipeline,
+			compute_bin_pipeline,
+			compute_binned_pipeline,
+			compute_world_pipeline,
+			compute_bricks_clear_pipeline,
+			compute_bricks_mark_pipeline,
+			compute_bricks_args_pipeline,
+			compute_bricks_fill_pipeline,
+			compute_clear_cells_pipeline,
+			compute_bin_cells_pipeline,
+			render_pipeline,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::blend::BlendMode;
+	use glam::Vec3;
+
+	// Rows of 32 Rgba16Float voxels are already aligned for the copy
+	const FROXELS: UVec3 = UVec3::new(32, 32, 64);
+	const VOXEL_BYTES: u32 = 8;
+
+	/// Bakes one frame of `particles` once `configure`d and returns the voxels of the SDF texture.
+	fn bake(
+		device: &wgpu::Device,
+		queue: &wgpu::Queue,
+		particles: &[Particle],
+		configure: impl FnOnce(&mut Renderer),
+	) -> Vec<u8> {
+		let format = wgpu::TextureFormat::Rgba8Unorm;
+		let mut renderer = Renderer::new(
+			device,
+			queue,
+			format,
+			particles,
+			FROXELS,
+			&Shaders::default(),
+		)
+		.unwrap();
+		configure(&mut renderer);
+
+		let target = device.create_texture(&wgpu::TextureDescriptor {
+			label: None,
+			size: wgpu::Extent3d::default(),
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+			view_formats: &[],
+		});
+		let readback = device.create_buffer(&wgpu::BufferDescriptor {
+			label: None,
+			size: FROXELS.element_product() as u64 * VOXEL_BYTES as u64,
+			usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+			mapped_at_creation: false,
+		});
+
+		let mut encoder = device.create_command_encoder(&Default::default());
+		renderer.render(
+			&mut encoder,
+			&target.create_view(&Default::default()),
+			(1, 1),
+			&Camera::new(),
+			TimeUniform::from_seconds(0.0),
+		);
+		encoder.copy_texture_to_buffer(
+			renderer.sdf_texture().as_image_copy(),
+			wgpu::TexelCopyBufferInfo {
+				buffer: &readback,
+				layout: wgpu::TexelCopyBufferLayout {
+					offset: 0,
+					bytes_per_row: Some(FROXELS.x * VOXEL_BYTES),
+					rows_per_image: Some(FROXELS.y),
+				},
+			},
+			renderer.sdf_texture().size(),
+		);
+		queue.submit([encoder.finish()]);
+
+		readback
+			.slice(..)
+			.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+		device.poll(wgpu::PollType::Wait).unwrap();
+		let voxels = readback.slice(..).get_mapped_range().to_vec();
+		readback.unmap();
+		voxels
+	}
+
+	#[test]
+	fn froxels_fit_device_limits() {
+		let (device, _) =
+			pollster::block_on(crate::headless::request_device()).expect("no adapter to test with");
+		let limits = device.limits();
+		let max_bytes = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+
+		let small = UVec3::new(64, 64, 256);
+		assert_eq!(fit_froxels(&device, small), small);
+		for froxels in [
+			UVec3::new(960, 540, 256),
+			UVec3::new(1920, 1080, 256),
+			UVec3::splat(4096),
+		] {
+			let fitted = fit_froxels(&device, froxels);
+			assert!(
+				binning::tile_entry_bytes(fitted) <= max_bytes,
+				"{froxels} -> {fitted}"
+			);
+			assert!(fitted.max_element() <= limits.max_texture_dimension_3d);
+			assert!(fitted.x <= froxels.x && fitted.y <= froxels.y);
+		}
+	}
+
+	#[test]
+	fn odd_and_even_bundle_counts_bake_identically() {
+		let (device, queue) =
+			pollster::block_on(crate::headless::request_device()).expect("no adapter to test with");
+
+		// Less than a bundle in front of the camera, then a whole bundle more far behind it
+		let near: Vec<Particle> = (0..20)
+			.map(|i| {
+				let position = Vec3::new((i % 5) as f32 - 2.0, (i / 5) as f32 - 1.5, 0.0) * 0.3;
+				Particle::new(position, 0.1 + 0.01 * i as f32)
+			})
+			.collect();
+		let mut far = near.clone();
+		far.extend(
+			(0..particle::BUNDLE_SIZE).map(|i| Particle::new(Vec3::splat(500.0 + i as f32), 0.1)),
+		);
+
+		for method in [SdfMethod::Bundled, SdfMethod::Binned] {
+			let one_bundle = bake(&device, &queue, &near, |r| r.sdf_method = method);
+			let two_bundles = bake(&device, &queue, &far, |r| r.sdf_method = method);
+			let voxel = VOXEL_BYTES as usize;
+			assert!(
+				one_bundle
+					.chunks(voxel)
+					.any(|value| value != &one_bundle[..voxel]),
+				"{method:?} baked a constant SDF"
+			);
+			assert!(
+				one_bundle == two_bundles,
+				"{method:?} bakes differently with a second bundle"
+			);
+		}
+	}
+
+	#[test]
+	fn binned_bake_matches_bundled_within_reach() {
+		let (device, queue) =
+			pollster::block_on(crate::headless::request_device()).expect("no adapter to test with");
+		// A reach short enough for the first slices to hold voxels on both sides of it
+		let blend = Blend {
+			mode: BlendMode::Min,
+			radius: 0.0,
+		};
+		let margin = 0.01;
+		let reach = blend.radius + margin;
+
+		// One particle in the first slices, the rest spread further down the frustum
+		let eye = Camera::new().position;
+		let mut particles = vec![Particle::new(eye + Vec3::new(0.0, 0.0, 0.09), 0.01)];
+		particles.extend((0..40).map(|i| {
+			let offset = Vec3::new((i % 8) as f32 - 3.5, (i / 8) as f32 - 2.0, 0.0) * 0.25;
+			Particle::new(offset + Vec3::Z * (0.5 + 0.1 * i as f32), 0.05)
+		}));
+		let distances = |method| -> Vec<f32> {
+			let voxels = bake(&device, &queue, &particles, |renderer| {
+				renderer.sdf_method = method;
+				renderer.blend = blend;
+				renderer.binning.settings.margin = margin;
+			});
+			voxels
+				.chunks(VOXEL_BYTES as usize)
+				.map(|voxel| half::f16::from_le_bytes([voxel[0], voxel[1]]).to_f32())
+				.collect()
+		};
+		let bundled = distances(SdfMethod::Bundled);
+		let binned = distances(SdfMethod::Binned);
+
+		let (mut inside, mut outside) = (0, 0);
+		for (&bundled, &binned) in bundled.iter().zip(&binned) {
+			if bundled < reach {
+				inside += 1;
+				assert!(
+					(bundled - binned).abs() <= 1e-4,
+					"binned {binned} differs from bundled {bundled}"
+				);
+			} else {
+				outside += 1;
+				// Voxels out of reach of every particle store the reach instead of their distance
+				assert!(
+					(binned - reach).abs() <= 1e-4,
+					"binned {binned} instead of the reach for bundled {bundled}"
+				);
+			}
+		}
+		assert!(inside > 0, "no voxel within reach of a particle");
+		assert!(outside > 0, "no voxel out of reach of every particle");
+	}
+
+	#[test]
+	fn world_spaces_only_rebake_when_particles_change() {
+		let (device, queue) =
+			pollster::block_on(crate::headless::request_device()).expect("no adapter to test with");
+		let format = wgpu::TextureFormat::Rgba8Unorm;
+		let mut renderer = Renderer::new(
+			&device,
+			&queue,
+			format,
+			&particle::grid(4, 4, 4),
+			FROXELS,
+			&Shaders::default(),
+		)
+		.unwrap();
+		let target = device.create_texture(&wgpu::TextureDescriptor {
+			label: None,
+			size: wgpu::Extent3d::default(),
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+			view_formats: &[],
+		});
+		let view = target.create_view(&Default::default());
+		let frame = |renderer: &mut Renderer| {
+			let mut encoder = device.create_command_encoder(&Default::default());
+			renderer.render(
+				&mut encoder,
+				&view,
+				(1, 1),
+				&Camera::new(),
+				TimeUniform::from_seconds(0.0),
+			);
+			queue.submit([encoder.finish()]);
+		};
+
+		assert!(renderer.needs_bake(0));
+		frame(&mut renderer);
+		assert!(renderer.needs_bake(0), "froxels follow the camera");
+
+		for space in [SdfSpace::World, SdfSpace::Bricks, SdfSpace::Exact] {
+			renderer.set_space(space);
+			assert!(renderer.needs_bake(0), "{space:?} starts out unbaked");
+			frame(&mut renderer);
+			assert!(
+				!renderer.needs_bake(0),
+				"{space:?} rebakes unchanged particles"
+			);
+			assert!(renderer.needs_bake(1), "{space:?} skips a simulation step");
+
+			renderer.blend.radius += 0.05;
+			assert!(renderer.needs_bake(0), "{space:?} skips a blend change");
+			frame(&mut renderer);
+			assert!(!renderer.needs_bake(0));
+		}
+	}
+}
@@ -0,0 +1,931 @@
+use std::{
+	fmt,
+	path::{Path, PathBuf},
+};
+
+use glam::{UVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	blend::{Blend, BlendMode},
+	bricks::MAX_BRICKS,
+	camera::Camera,
+	grid::MAX_CELLS,
+	hdr::{self, HdrError, HdrImage},
+	light::{
+		Light, Lighting, Occlusion, OcclusionMode, ShadowMode, Shadows, MAX_LIGHTS,
+		MAX_OCCLUSION_SAMPLES, MAX_SHADOW_STEPS,
+	},
+	loader::{self, LoadError},
+	material::{Liquid, Material, Materials, Shading, MAX_INTERIOR_STEPS, MAX_MATERIALS},
+	particle::{self, Particle, MAX_PARTICLES},
+	sdf::{SdfSpace, Slicing, WorldVolume, MAX_WORLD_VOXELS},
+};
+
+/// Serializers writing floats with the shortest representation that reads back as the same
+/// `f32`, instead of the `f64` widening TOML would otherwise print, e.g. `0.1` over `0.10000000149011612`.
+mod short {
+	use glam::Vec3;
+	use serde::{ser::SerializeSeq, Serializer};
+
+	fn widen(value: f32) -> f64 {
+		value.to_string().parse().unwrap()
+	}
+
+	pub fn f32<S: Serializer>(value: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_f64(widen(*value))
+	}
+
+	pub fn vec3<S: Serializer>(value: &Vec3, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_seq(value.to_array().map(widen))
+	}
+
+	pub fn points<S: Serializer>(points: &[[f32; 4]], serializer: S) -> Result<S::Ok, S::Error> {
+		let mut seq = serializer.serialize_seq(Some(points.len()))?;
+		for point in points {
+			seq.serialize_element(&point.map(widen))?;
+		}
+		seq.end()
+	}
+}
+
+/// Largest froxel grid dimension, the guaranteed `max_texture_dimension_3d` of wgpu.
+pub const MAX_FROXELS: u32 = 2048;
+
+#[derive(Debug)]
+pub enum SceneError {
+	Io(std::io::Error),
+	Parse(toml::de::Error),
+	Serialize(toml::ser::Error),
+	/// A field holds a value outside of its valid range.
+	Invalid {
+		field: String,
+		message: String,
+	},
+	/// A particle source couldn't be loaded.
+	Particles {
+		field: String,
+		path: PathBuf,
+		error: LoadError,
+	},
+	/// The environment map couldn't be loaded.
+	Environment {
+		path: PathBuf,
+		error: HdrError,
+	},
+}
+
+impl fmt::Display for SceneError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			SceneError::Io(err) => write!(f, "{err}"),
+			SceneError::Parse(err) => write!(f, "{err}"),
+			SceneError::Serialize(err) => write!(f, "failed to serialize scene: {err}"),
+			SceneError::Invalid { field, message } => write!(f, "`{field}`: {message}"),
+			SceneError::Particles { field, path, error } => {
+				write!(f, "`{field}` ({}): {error}", path.display())
+			}
+			SceneError::Environment { path, error } => {
+				write!(f, "`background.environment` ({}): {error}", path.display())
+			}
+		}
+	}
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+	fn from(err: std::io::Error) -> Self {
+		SceneError::Io(err)
+	}
+}
+
+/// Everything needed to reproduce a frame, as stored in a TOML scene file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scene {
+	pub camera: SceneCamera,
+	pub render: SceneRender,
+	pub background: SceneBackground,
+	pub lights: Vec<SceneLight>,
+	pub shadows: SceneShadows,
+	pub occlusion: SceneOcclusion,
+	/// Indexed by the material id of the particles.
+	pub materials: Vec<SceneMaterial>,
+	/// Used when `render.shading` is `liquid`.
+	pub liquid: SceneLiquid,
+	pub particles: Vec<ParticleSource>,
+}
+
+/// Camera pose, angles are in degrees.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneCamera {
+	#[serde(serialize_with = "short::vec3")]
+	pub position: Vec3,
+	#[serde(serialize_with = "short::f32")]
+	pub yaw: f32,
+	#[serde(serialize_with = "short::f32")]
+	pub pitch: f32,
+	#[serde(serialize_with = "short::f32")]
+	pub fov: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneRender {
+	/// Froxel grid, or just its depth when `froxel_pixels` is set.
+	pub froxels: UVec3,
+	/// Derive the froxel grid's width and height from the target, one froxel every this many pixels.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub froxel_pixels: Option<u32>,
+	pub slicing: Slicing,
+	pub space: SdfSpace,
+	/// Corners of the box baked when `space` is `world`.
+	#[serde(serialize_with = "short::vec3")]
+	pub world_min: Vec3,
+	#[serde(serialize_with = "short::vec3")]
+	pub world_max: Vec3,
+	pub world_resolution: UVec3,
+	/// Bricks allocated when `space` is `bricks`.
+	pub brick_capacity: u32,
+	/// Cells of the particle grid when `space` is `exact`, over the same box as `world`, so particles outside of it are left out.
+	pub grid_cells: UVec3,
+	pub blend: BlendMode,
+	#[serde(serialize_with = "short::f32")]
+	pub blend_radius: f32,
+	pub shading: Shading,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneBackground {
+	#[serde(serialize_with = "short::vec3")]
+	pub ground: Vec3,
+	#[serde(serialize_with = "short::vec3")]
+	pub sky: Vec3,
+	/// Equirectangular Radiance HDR replacing the gradient, relative to the scene file.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub environment: Option<PathBuf>,
+	#[serde(serialize_with = "short::f32")]
+	pub environment_intensity: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneLight {
+	#[serde(serialize_with = "short::vec3")]
+	pub direction: Vec3,
+	#[serde(serialize_with = "short::vec3")]
+	pub color: Vec3,
+	#[serde(serialize_with = "short::f32")]
+	pub intensity: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneShadows {
+	pub mode: ShadowMode,
+	/// Steps of every shadow ray, fewer is faster but lets thin blockers through.
+	pub steps: u32,
+	/// Width of the penumbra, only used by `soft` shadows.
+	#[serde(serialize_with = "short::f32")]
+	pub softness: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneOcclusion {
+	pub mode: OcclusionMode,
+	pub samples: u32,
+	/// Distance from the surface of the farthest sample.
+	#[serde(serialize_with = "short::f32")]
+	pub radius: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneMaterial {
+	#[serde(serialize_with = "short::f32")]
+	pub roughness: f32,
+	#[serde(serialize_with = "short::f32")]
+	pub metallic: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneLiquid {
+	#[serde(serialize_with = "short::f32")]
+	pub ior: f32,
+	/// Absorbed fraction of red, green and blue per unit of distance.
+	#[serde(serialize_with = "short::vec3")]
+	pub absorption: Vec3,
+	pub steps: u32,
+}
+
+/// Where a group of particles comes from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ParticleSource {
+	/// A particle file, relative to the scene file.
+	File { path: PathBuf },
+	/// A regular grid filling the unit cube around the origin.
+	Grid { size: UVec3 },
+	/// Particles scattered at random.
+	Random { count: u32 },
+	/// Particles given inline as `[x, y, z, radius]`.
+	Points {
+		#[serde(serialize_with = "short::points")]
+		points: Vec<[f32; 4]>,
+	},
+}
+
+impl Default for SceneCamera {
+	fn default() -> Self {
+		Self {
+			position: Camera::new().position,
+			yaw: 0.0,
+			pitch: 0.0,
+			fov: 60.0,
+		}
+	}
+}
+
+impl Default for SceneRender {
+	fn default() -> Self {
+		let blend = Blend::default();
+		let world = WorldVolume::default();
+		Self {
+			froxels: UVec3::new(64, 64, 256),
+			froxel_pixels: None,
+			slicing: Slicing::default(),
+			space: SdfSpace::default(),
+			world_min: world.min,
+			world_max: world.max,
+			world_resolution: world.resolution,
+			brick_capacity: world.brick_capacity,
+			grid_cells: world.grid_cells,
+			blend: blend.mode,
+			blend_radius: blend.radius,
+			shading: Shading::default(),
+		}
+	}
+}
+
+impl Default for SceneBackground {
+	fn default() -> Self {
+		let lighting = Lighting::default();
+		Self {
+			ground: lighting.ground,
+			sky: lighting.sky,
+			environment: None,
+			environment_intensity: 1.0,
+		}
+	}
+}
+
+impl Default for SceneLight {
+	fn default() -> Self {
+		let light = Light::default();
+		Self {
+			direction: light.direction,
+			color: light.color,
+			intensity: light.intensity,
+		}
+	}
+}
+
+impl Default for SceneShadows {
+	fn default() -> Self {
+		let shadows = Shadows::default();
+		Self {
+			mode: shadows.mode,
+			steps: shadows.steps,
+			softness: shadows.softness,
+		}
+	}
+}
+
+impl Default for SceneOcclusion {
+	fn default() -> Self {
+		let occlusion = Occlusion::default();
+		Self {
+			mode: occlusion.mode,
+			samples: occlusion.samples,
+			radius: occlusion.radius,
+		}
+	}
+}
+
+impl Default for SceneMaterial {
+	fn default() -> Self {
+		let material = Material::default();
+		Self {
+			roughness: material.roughness,
+			metallic: material.metallic,
+		}
+	}
+}
+
+impl Default for SceneLiquid {
+	fn default() -> Self {
+		let liquid = Liquid::default();
+		Self {
+			ior: liquid.ior,
+			absorption: liquid.absorption,
+			steps: liquid.steps,
+		}
+	}
+}
+
+impl Default for Scene {
+	fn default() -> Self {
+		Self {
+			camera: Default::default(),
+			render: Default::default(),
+			background: Default::default(),
+			lights: vec![SceneLight::default()],
+			shadows: Default::default(),
+			occlusion: Default::default(),
+			materials: vec![SceneMaterial::default()],
+			liquid: Default::default(),
+			particles: vec![ParticleSource::Grid {
+				size: UVec3::splat(8),
+			}],
+		}
+	}
+}
+
+pub fn check_yaw(yaw: f32) -> Result<(), String> {
+	if !yaw.is_finite() {
+		return Err("yaw must be finite".into());
+	}
+	Ok(())
+}
+
+pub fn check_pitch(pitch: f32) -> Result<(), String> {
+	if !(-90.0..=90.0).contains(&pitch) {
+		return Err("pitch must be within [-90, 90]".into());
+	}
+	Ok(())
+}
+
+pub fn check_fov(fov: f32) -> Result<(), String> {
+	if !(fov > 0.0 && fov < 180.0) {
+		return Err("field of view must be within (0, 180)".into());
+	}
+	Ok(())
+}
+
+pub fn check_froxels(froxels: UVec3) -> Result<(), String> {
+	if froxels.min_element() == 0 {
+		return Err("dimensions must be greater than zero".into());
+	}
+	if froxels.max_element() > MAX_FROXELS {
+		return Err(format!("dimensions must be at most {MAX_FROXELS}"));
+	}
+	Ok(())
+}
+
+fn check_world_resolution(resolution: UVec3) -> Result<(), String> {
+	if resolution.min_element() == 0 {
+		return Err("dimensions must be greater than zero".into());
+	}
+	if resolution.max_element() > MAX_FROXELS {
+		return Err(format!("dimensions must be at most {MAX_FROXELS}"));
+	}
+	if resolution.as_u64vec3().element_product() > MAX_WORLD_VOXELS {
+		return Err(format!(
+			"must have at most {MAX_WORLD_VOXELS} voxels in total, e.g. 256x256x256"
+		));
+	}
+	Ok(())
+}
+
+pub fn check_froxel_pixels(pixels: u32) -> Result<(), String> {
+	if pixels == 0 {
+		return Err("must be greater than zero".into());
+	}
+	Ok(())
+}
+
+fn check_finite(v: Vec3) -> Result<(), String> {
+	if !v.is_finite() {
+		return Err("components must be finite".into());
+	}
+	Ok(())
+}
+
+fn check_color(color: Vec3) -> Result<(), String> {
+	if !color.is_finite() || color.min_element() < 0.0 {
+		return Err("components must be finite and not negative".into());
+	}
+	Ok(())
+}
+
+fn check_unit(value: f32) -> Result<(), String> {
+	if !(0.0..=1.0).contains(&value) {
+		return Err("must be within [0, 1]".into());
+	}
+	Ok(())
+}
+
+fn check_non_negative(value: f32) -> Result<(), String> {
+	if !(value.is_finite() && value >= 0.0) {
+		return Err("must be finite and not negative".into());
+	}
+	Ok(())
+}
+
+/// Attaches the path of the offending field to a range check.
+fn field(field: impl Into<String>, result: Result<(), String>) -> Result<(), SceneError> {
+	result.map_err(|message| SceneError::Invalid {
+		field: field.into(),
+		message,
+	})
+}
+
+impl Scene {
+	/// Reads and validates a scene. File particle sources are resolved against its directory.
+	pub fn load(path: &Path) -> Result<Self, SceneError> {
+		let mut scene = Self::parse(&std::fs::read_to_string(path)?)?;
+		let base = path.parent().unwrap_or(Path::new(""));
+		for source in &mut scene.particles {
+			if let ParticleSource::File { path } = source {
+				*path = base.join(&*path);
+			}
+		}
+		if let Some(path) = &mut scene.background.environment {
+			*path = base.join(&*path);
+		}
+		Ok(scene)
+	}
+
+	pub fn parse(text: &str) -> Result<Self, SceneError> {
+		let scene: Scene = toml::from_str(text).map_err(SceneError::Parse)?;
+		scene.validate()?;
+		Ok(scene)
+	}
+
+	/// Writes the scene out, with file particle sources and the environment turned into absolute paths.
+	pub fn save(&self, path: &Path) -> Result<(), SceneError> {
+		let mut scene = self.clone();
+		for source in &mut scene.particles {
+			if let ParticleSource::File { path } = source {
+				*path = std::path::absolute(&*path)?;
+			}
+		}
+		if let Some(path) = &mut scene.background.environment {
+			*path = std::path::absolute(&*path)?;
+		}
+		let text = toml::to_string(&scene).map_err(SceneError::Serialize)?;
+		std::fs::write(path, text)?;
+		Ok(())
+	}
+
+	pub fn validate(&self) -> Result<(), SceneError> {
+		let camera = &self.camera;
+		field("camera.position", check_finite(camera.position))?;
+		field("camera.yaw", check_yaw(camera.yaw))?;
+		field("camera.pitch", check_pitch(camera.pitch))?;
+		field("camera.fov", check_fov(camera.fov))?;
+
+		field("render.froxels", check_froxels(self.render.froxels))?;
+		if let Some(pixels) = self.render.froxel_pixels {
+			field("render.froxel_pixels", check_froxel_pixels(pixels))?;
+		}
+		field("render.world_min", check_finite(self.render.world_min))?;
+		field(
+			"render.world_max",
+			check_finite(self.render.world_max).and_then(|()| {
+				match self.render.world_min.cmplt(self.render.world_max).all() {
+					true => Ok(()),
+					false => Err("must be greater than `world_min` on every axis".into()),
+				}
+			}),
+		)?;
+		field(
+			"render.world_resolution",
+			check_world_resolution(self.render.world_resolution),
+		)?;
+		field(
+			"render.brick_capacity",
+			match self.render.brick_capacity {
+				1..=MAX_BRICKS => Ok(()),
+				_ => Err(format!("must be between 1 and {MAX_BRICKS}")),
+			},
+		)?;
+		field(
+			"render.grid_cells",
+			match self.render.grid_cells {
+				cells if cells.min_element() == 0 => {
+					Err("dimensions must be greater than zero".into())
+				}
+				cells if cells.as_u64vec3().element_product() > MAX_CELLS as u64 => {
+					Err(format!("must have at most {MAX_CELLS} cells in total"))
+				}
+				_ => Ok(()),
+			},
+		)?;
+		field(
+			"render.blend_radius",
+			check_non_negative(self.render.blend_radius),
+		)?;
+
+		field("background.ground", check_color(self.background.ground))?;
+		field("background.sky", check_color(self.background.sky))?;
+		field(
+			"background.environment_intensity",
+			check_non_negative(self.background.environment_intensity),
+		)?;
+
+		if self.lights.len() > MAX_LIGHTS {
+			return Err(SceneError::Invalid {
+				field: "lights".into(),
+				message: format!("at most {MAX_LIGHTS} lights are supported"),
+			});
+		}
+		for (i, light) in self.lights.iter().enumerate() {
+			field(
+				format!("lights[{i}].direction"),
+				check_finite(light.direction).and_then(|()| match light.direction {
+					Vec3::ZERO => Err("must not be zero".into()),
+					_ => Ok(()),
+				}),
+			)?;
+			field(format!("lights[{i}].color"), check_color(light.color))?;
+			field(
+				format!("lights[{i}].intensity"),
+				check_non_negative(light.intensity),
+			)?;
+		}
+
+		field(
+			"shadows.steps",
+			match self.shadows.steps {
+				1..=MAX_SHADOW_STEPS => Ok(()),
+				_ => Err(format!("must be between 1 and {MAX_SHADOW_STEPS}")),
+			},
+		)?;
+		field(
+			"shadows.softness",
+			match self.shadows.softness {
+				softness if softness.is_finite() && softness > 0.0 => Ok(()),
+				_ => Err("must be finite and greater than zero".into()),
+			},
+		)?;
+		field(
+			"occlusion.samples",
+			match self.occlusion.samples {
+				1..=MAX_OCCLUSION_SAMPLES => Ok(()),
+				_ => Err(format!("must be between 1 and {MAX_OCCLUSION_SAMPLES}")),
+			},
+		)?;
+		field(
+			"occlusion.radius",
+			match self.occlusion.radius {
+				radius if radius.is_finite() && radius > 0.0 => Ok(()),
+				_ => Err("must be finite and greater than zero".into()),
+			},
+		)?;
+
+		field(
+			"materials",
+			match self.materials.len() {
+				// Particles default to the first material, so there has to be one
+				0 => Err("at least one material is required".into()),
+				1..=MAX_MATERIALS => Ok(()),
+				_ => Err(format!("at most {MAX_MATERIALS} materials are supported")),
+			},
+		)?;
+		for (i, material) in self.materials.iter().enumerate() {
+			field(
+				format!("materials[{i}].roughness"),
+				check_unit(material.roughness),
+			)?;
+			field(
+				format!("materials[{i}].metallic"),
+				check_unit(material.metallic),
+			)?;
+		}
+		field(
+			"liquid.ior",
+			match self.liquid.ior {
+				ior if ior.is_finite() && ior >= 1.0 => Ok(()),
+				_ => Err("must be finite and at least 1".into()),
+			},
+		)?;
+		field("liquid.absorption", check_color(self.liquid.absorption))?;
+		field(
+			"liquid.steps",
+			match self.liquid.steps {
+				1..=MAX_INTERIOR_STEPS => Ok(()),
+				_ => Err(format!("must be between 1 and {MAX_INTERIOR_STEPS}")),
+			},
+		)?;
+
+		for (i, source) in self.particles.iter().enumerate() {
+			match source {
+				ParticleSource::File { .. } => {}
+				ParticleSource::Grid { size } => field(
+					format!("particles[{i}].size"),
+					match size {
+						size if size.min_element() == 0 => {
+							Err("dimensions must be greater than zero".into())
+						}
+						size if size.as_u64vec3().element_product() > MAX_PARTICLES as u64 => Err(
+							format!("must have at most {MAX_PARTICLES} particles in total"),
+						),
+						_ => Ok(()),
+					},
+				)?,
+				ParticleSource::Random { count } => field(
+					format!("particles[{i}].count"),
+					match count {
+						0..=MAX_PARTICLES => Ok(()),
+						_ => Err(format!("must be at most {MAX_PARTICLES}")),
+					},
+				)?,
+				ParticleSource::Points { points } => {
+					for (j, [x, y, z, radius]) in points.iter().enumerate() {
+						field(
+							format!("particles[{i}].points[{j}]"),
+							check_finite(Vec3::new(*x, *y, *z))
+								.and_then(|()| check_non_negative(*radius)),
+						)?;
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Loads the environment map, if there is one.
+	pub fn environment(&self) -> Result<Option<HdrImage>, SceneError> {
+		let Some(path) = &self.background.environment else {
+			return Ok(None);
+		};
+		hdr::load(path)
+			.map(Some)
+			.map_err(|error| SceneError::Environment {
+				path: path.clone(),
+				error,
+			})
+	}
+
+	/// Gathers the particles of every source.
+	pub fn particles(&self) -> Result<Vec<Particle>, SceneError> {
+		let mut particles = vec![];
+		for (i, source) in self.particles.iter().enumerate() {
+			match source {
+				ParticleSource::File { path } => {
+					particles.extend(loader::load(path).map_err(|error| SceneError::Particles {
+						field: format!("particles[{i}].path"),
+						path: path.clone(),
+						error,
+					})?)
+				}
+				ParticleSource::Grid { size } => particles.extend(particle::grid(
+					size.x as usize,
+					size.y as usize,
+					size.z as usize,
+				)),
+				ParticleSource::Random { count } => {
+					particles.extend(particle::random(*count as usize))
+				}
+				ParticleSource::Points { points } => particles.extend(
+					points
+						.iter()
+						.map(|&[x, y, z, radius]| Particle::new(Vec3::new(x, y, z), radius)),
+				),
+			}
+		}
+		Ok(particles)
+	}
+
+	/// Froxel grid to bake the SDF into when rendering `width` by `height` pixels.
+	pub fn froxels(&self, (width, height): (u32, u32)) -> UVec3 {
+		match self.render.froxel_pixels {
+			Some(pixels) => UVec3::new(
+				width.div_ceil(pixels),
+				height.div_ceil(pixels),
+				self.render.froxels.z,
+			)
+			.clamp(UVec3::ONE, UVec3::splat(MAX_FROXELS)),
+			None => self.render.froxels,
+		}
+	}
+
+	pub fn world(&self) -> WorldVolume {
+		WorldVolume {
+			min: self.render.world_min,
+			max: self.render.world_max,
+			resolution: self.render.world_resolution,
+			brick_capacity: self.render.brick_capacity,
+			grid_cells: self.render.grid_cells,
+		}
+	}
+
+	pub fn camera(&self) -> Camera {
+		let mut camera = Camera::new();
+		camera.position = self.camera.position;
+		camera.yaw = self.camera.yaw.to_radians();
+		camera.pitch = self.camera.pitch.to_radians();
+		camera.fov = self.camera.fov.to_radians();
+		camera
+	}
+
+	/// Stores the pose of `camera`, e.g. after it has been flown around.
+	pub fn set_camera(&mut self, camera: &Camera) {
+		self.camera.position = camera.position;
+		self.camera.yaw = camera.yaw.to_degrees();
+		self.camera.pitch = camera.pitch.to_degrees();
+	}
+
+	pub fn blend(&self) -> Blend {
+		Blend {
+			mode: self.render.blend,
+			radius: self.render.blend_radius,
+		}
+	}
+
+	pub fn set_blend(&mut self, blend: &Blend) {
+		self.render.blend = blend.mode;
+		self.render.blend_radius = blend.radius;
+	}
+
+	pub fn materials(&self) -> Materials {
+		Materials {
+			shading: self.render.shading,
+			liquid: Liquid {
+				ior: self.liquid.ior,
+				absorption: self.liquid.absorption,
+				steps: self.liquid.steps,
+			},
+			materials: self
+				.materials
+				.iter()
+				.map(|material| Material {
+					roughness: material.roughness,
+					metallic: material.metallic,
+				})
+				.collect(),
+		}
+	}
+
+	pub fn lighting(&self) -> Lighting {
+		Lighting {
+			lights: self
+				.lights
+				.iter()
+				.map(|light| Light {
+					direction: light.direction,
+					color: light.color,
+					intensity: light.intensity,
+				})
+				.collect(),
+			shadows: Shadows {
+				mode: self.shadows.mode,
+				steps: self.shadows.steps,
+				softness: self.shadows.softness,
+			},
+			occlusion: Occlusion {
+				mode: self.occlusion.mode,
+				samples: self.occlusion.samples,
+				radius: self.occlusion.radius,
+			},
+			ground: self.background.ground,
+			sky: self.background.sky,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Path of the field `text` fails to validate at.
+	fn invalid_field(text: &str) -> String {
+		match Scene::parse(text) {
+			Err(SceneError::Invalid { field, .. }) => field,
+			other => panic!("expected an invalid field, got {other:?}"),
+		}
+	}
+
+	#[test]
+	fn parses_and_validates_settings() {
+		let scene = Scene::parse(
+			r#"
+			[camera]
+			position = [1.0, 2.0, -3.0]
+			yaw = 45.0
+			fov = 75.0
+
+			[render]
+			froxels = [32, 32, 128]
+			blend = "cubic"
+			blend_radius = 0.2
+
+			[[lights]]
+			direction = [0.0, -1.0, 0.0]
+			color = [1.0, 0.5, 0.25]
+
+			[[particles]]
+			type = "random"
+			count = 10
+
+			[[particles]]
+			type = "points"
+			points = [[0.0, 0.0, 0.0, 0.5]]
+			"#,
+		)
+		.unwrap();
+		assert_eq!(scene.camera.position, Vec3::new(1.0, 2.0, -3.0));
+		assert_eq!(scene.render.froxels, UVec3::new(32, 32, 128));
+		assert_eq!(
+			scene.blend(),
+			Blend {
+				mode: BlendMode::Cubic,
+				radius: 0.2,
+			}
+		);
+		assert_eq!(scene.lights.len(), 1);
+		// Fields left out keep their defaults
+		assert_eq!(scene.camera.pitch, 0.0);
+		assert_eq!(scene.materials.len(), 1);
+		assert_eq!(scene.particles().unwrap().len(), 11);
+	}
+
+	#[test]
+	fn serialized_scene_parses_back() {
+		let mut scene = Scene::default();
+		scene.camera.yaw = 30.0;
+		scene.render.blend_radius = 0.1;
+		let text = toml::to_string(&scene).unwrap();
+		let parsed = Scene::parse(&text).unwrap();
+		assert_eq!(toml::to_string(&parsed).unwrap(), text);
+		assert!(text.contains("blend_radius = 0.1\n"), "{text}");
+	}
+
+	#[test]
+	fn errors_point_at_the_field() {
+		assert_eq!(
+			invalid_field("[render]\nfroxels = [0, 64, 64]"),
+			"render.froxels"
+		);
+		assert_eq!(invalid_field("[camera]\nyaw = nan"), "camera.yaw");
+		assert_eq!(invalid_field("[camera]\npitch = 120.0"), "camera.pitch");
+		assert_eq!(
+			invalid_field("[[lights]]\n[[lights]]\ndirection = [0.0, 0.0, 0.0]"),
+			"lights[1].direction"
+		);
+		assert_eq!(
+			invalid_field("[[materials]]\n[[materials]]\nroughness = 2.0"),
+			"materials[1].roughness"
+		);
+		assert_eq!(invalid_field("materials = []"), "materials");
+		assert_eq!(
+			invalid_field("[[particles]]\ntype = \"random\"\ncount = 4000000000"),
+			"particles[0].count"
+		);
+		assert_eq!(
+			invalid_field("[[particles]]\ntype = \"grid\"\nsize = [4096, 4096, 4096]"),
+			"particles[0].size"
+		);
+		assert!(matches!(
+			Scene::parse("[render]\nfroxel = [1, 1, 1]"),
+			Err(SceneError::Parse(_))
+		));
+	}
+
+	#[test]
+	fn saved_file_sources_load_back() {
+		// Relative to the working directory, which `save` resolves it against
+		let dir = Path::new("target").join(format!("scene-test-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let points = dir.join("points.xyz");
+		std::fs::write(&points, "0 0 0 0.1\n1 0 0 0.2\n").unwrap();
+
+		let scene = Scene {
+			particles: vec![ParticleSource::File {
+				path: points.clone(),
+			}],
+			..Default::default()
+		};
+		let saved = std::env::temp_dir().join(format!("scene-test-{}.toml", std::process::id()));
+		scene.save(&saved).unwrap();
+		let loaded = Scene::load(&saved);
+		std::fs::remove_file(&saved).unwrap();
+		let loaded = loaded.unwrap();
+
+		match &loaded.particles[..] {
+			[ParticleSource::File { path }] => {
+				assert_eq!(path, &std::path::absolute(&points).unwrap());
+			}
+			sources => panic!("unexpected particle sources {sources:?}"),
+		}
+		let particles = loaded.particles();
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(particles.unwrap().len(), 2);
+	}
+}
@@ -1,5 +1,4 @@
 use wgpu::util::DeviceExt;
-use winit::dpi::PhysicalSize;
 
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -8,9 +7,9 @@ pub struct ScreenUniform {
 }
 
 impl ScreenUniform {
-	pub fn new(size: PhysicalSize<u32>) -> Self {
-		let width = size.width as f32;
-		let height = size.height as f32;
+	pub fn new((width, height): (u32, u32)) -> Self {
+		let width = width as f32;
+		let height = height as f32;
 		let size = [width, height];
 		Self { size }
 	}
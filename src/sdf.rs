@@ -1,3 +1,179 @@
+use glam::{UVec3, Vec3};
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+/// How the depth slices of the froxel grid are spread between the near and far planes.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Slicing {
+	/// Evenly spaced in normalized device depth, which crowds slices next to the near plane.
+	#[default]
+	NdcLinear,
+	/// Evenly spaced in view space depth.
+	ViewLinear,
+	/// Exponentially spaced in view space depth, every slice covering the same ratio of depths.
+	Logarithmic,
+}
+
+impl Slicing {
+	// Must match the SLICING_* constants in compute.wgsl and shader.wgsl
+	fn id(self) -> u32 {
+		match self {
+			Slicing::NdcLinear => 0,
+			Slicing::ViewLinear => 1,
+			Slicing::Logarithmic => 2,
+		}
+	}
+
+	pub fn next(self) -> Self {
+		match self {
+			Slicing::NdcLinear => Slicing::ViewLinear,
+			Slicing::ViewLinear => Slicing::Logarithmic,
+			Slicing::Logarithmic => Slicing::NdcLinear,
+		}
+	}
+
+	pub fn uniform(self) -> SlicingUniform {
+		SlicingUniform { mode: self.id() }
+	}
+
+	/// CPU reference of `slice_to_view_depth` in `compute.wgsl` and `shader.wgsl`,
+	/// the view space depth a fraction `s` of the way through the slices.
+	pub fn slice_to_view_depth(self, s: f32, near: f32, far: f32) -> f32 {
+		match self {
+			Slicing::NdcLinear => near / (1.0 - s * (far - near) / far),
+			Slicing::ViewLinear => near + (far - near) * s,
+			Slicing::Logarithmic => near * (far / near).powf(s),
+		}
+	}
+
+	/// CPU reference of `view_depth_to_slice`, the inverse of [`Slicing::slice_to_view_depth`].
+	pub fn view_depth_to_slice(self, z: f32, near: f32, far: f32) -> f32 {
+		match self {
+			Slicing::NdcLinear => far / (far - near) * (1.0 - near / z),
+			Slicing::ViewLinear => (z - near) / (far - near),
+			Slicing::Logarithmic => (z / near).ln() / (far / near).ln(),
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SlicingUniform {
+	mode: u32,
+}
+
+impl SlicingUniform {
+	pub fn bytes(&self) -> &[u8] {
+		bytemuck::bytes_of(self)
+	}
+}
+
+pub fn create_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+	device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		label: Some("Slicing Buffer"),
+		contents: bytemuck::bytes_of(&SlicingUniform::default()),
+		usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+	})
+}
+
+/// Which space the SDF texture is laid out in.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SdfSpace {
+	/// Frustum-aligned froxels, rebaked every frame since they follow the camera.
+	#[default]
+	Froxels,
+	/// An axis-aligned box of world space, only rebaked when the particles change.
+	World,
+	/// The same box, stored sparsely as bricks allocated only near the surface.
+	Bricks,
+	/// Nothing is baked, the raymarcher evaluates the particles of a grid over the same box exactly.
+	/// Like the baked spaces, it only holds the particles within the box.
+	Exact,
+}
+
+impl SdfSpace {
+	// Must match the SPACE_* constants in compute.wgsl and shader.wgsl
+	fn id(self) -> u32 {
+		match self {
+			SdfSpace::Froxels => 0,
+			SdfSpace::World => 1,
+			SdfSpace::Bricks => 2,
+			SdfSpace::Exact => 3,
+		}
+	}
+
+	pub fn next(self) -> Self {
+		match self {
+			SdfSpace::Froxels => SdfSpace::World,
+			SdfSpace::World => SdfSpace::Bricks,
+			SdfSpace::Bricks => SdfSpace::Exact,
+			SdfSpace::Exact => SdfSpace::Froxels,
+		}
+	}
+}
+
+/// Most voxels of a world volume, 192 MiB of SDF and material textures at 12 bytes a voxel.
+pub const MAX_WORLD_VOXELS: u64 = 1 << 24;
+
+/// Box of world space baked in [`SdfSpace::World`] and [`SdfSpace::Bricks`],
+/// or covered by the particle grid of [`SdfSpace::Exact`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WorldVolume {
+	pub min: Vec3,
+	pub max: Vec3,
+	pub resolution: UVec3,
+	/// Bricks allocated for [`SdfSpace::Bricks`], the ones past it are skipped as empty.
+	pub brick_capacity: u32,
+	/// Cells of the particle grid traversed in [`SdfSpace::Exact`].
+	pub grid_cells: UVec3,
+}
+
+impl Default for WorldVolume {
+	fn default() -> Self {
+		Self {
+			min: Vec3::splat(-1.0),
+			max: Vec3::splat(1.0),
+			resolution: UVec3::splat(128),
+			brick_capacity: 4096,
+			grid_cells: UVec3::splat(16),
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct VolumeUniform {
+	min: [f32; 3],
+	space: u32,
+	max: [f32; 3],
+	_pad: u32,
+}
+
+impl VolumeUniform {
+	pub fn new(space: SdfSpace, world: &WorldVolume) -> Self {
+		Self {
+			min: world.min.to_array(),
+			space: space.id(),
+			max: world.max.to_array(),
+			_pad: 0,
+		}
+	}
+
+	pub fn bytes(&self) -> &[u8] {
+		bytemuck::bytes_of(self)
+	}
+}
+
+pub fn create_volume_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+	device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		label: Some("Volume Buffer"),
+		contents: bytemuck::bytes_of(&VolumeUniform::default()),
+		usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+	})
+}
+
 pub fn create_texture(device: &wgpu::Device, width: u32, height: u32, depth: u32) -> wgpu::Texture {
 	device.create_texture(&wgpu::TextureDescriptor {
 		label: Some("SDF texture"),
@@ -10,11 +186,36 @@ pub fn create_texture(device: &wgpu::Device, width: u32, height: u32, depth: u32
 		sample_count: 1,
 		dimension: wgpu::TextureDimension::D3,
 		format: wgpu::TextureFormat::Rgba16Float,
-		usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+		usage: wgpu::TextureUsages::TEXTURE_BINDING
+			| wgpu::TextureUsages::STORAGE_BINDING
+			| wgpu::TextureUsages::COPY_SRC,
 		view_formats: &[wgpu::TextureFormat::Rgba16Float],
 	})
 }
 
+/// Companion of the SDF texture holding the material of the nearest particle of every voxel.
+pub fn create_material_texture(
+	device: &wgpu::Device,
+	width: u32,
+	height: u32,
+	depth: u32,
+) -> wgpu::Texture {
+	device.create_texture(&wgpu::TextureDescriptor {
+		label: Some("Material texture"),
+		size: wgpu::Extent3d {
+			width,
+			height,
+			depth_or_array_layers: depth,
+		},
+		mip_level_count: 1,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D3,
+		format: wgpu::TextureFormat::R32Uint,
+		usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+		view_formats: &[wgpu::TextureFormat::R32Uint],
+	})
+}
+
 pub fn create_sampler(device: &wgpu::Device) -> wgpu::Sampler {
 	device.create_sampler(&wgpu::wgt::SamplerDescriptor {
 		label: Some("SDF sampler"),
@@ -32,3 +233,62 @@ pub fn create_view(sdf_texture: &wgpu::Texture) -> wgpu::TextureView {
 		..Default::default()
 	})
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		camera::{FAR, NEAR},
+		shaders::{self, Shaders},
+	};
+
+	const SLICINGS: [Slicing; 3] = [
+		Slicing::NdcLinear,
+		Slicing::ViewLinear,
+		Slicing::Logarithmic,
+	];
+
+	#[test]
+	fn slices_span_near_to_far() {
+		for slicing in SLICINGS {
+			let near = slicing.slice_to_view_depth(0.0, NEAR, FAR);
+			let far = slicing.slice_to_view_depth(1.0, NEAR, FAR);
+			assert!((near - NEAR).abs() < 1e-6, "{slicing:?} starts at {near}");
+			assert!((far - FAR).abs() < 1e-3, "{slicing:?} ends at {far}");
+		}
+	}
+
+	#[test]
+	fn slice_mapping_round_trips() {
+		for slicing in SLICINGS {
+			let mut previous = 0.0;
+			for i in 0..=64 {
+				let s = i as f32 / 64.0;
+				let z = slicing.slice_to_view_depth(s, NEAR, FAR);
+				assert!(z >= previous, "{slicing:?} isn't increasing at {s}");
+				previous = z;
+				let back = slicing.view_depth_to_slice(z, NEAR, FAR);
+				assert!(
+					(back - s).abs() < 1e-4,
+					"{slicing:?} maps {s} to {z} and back to {back}"
+				);
+			}
+		}
+	}
+
+	/// Source of the slicing functions of `shader`, from their constants to `slice_to_ndc_depth`.
+	fn slicing_source(shader: shaders::Shader) -> String {
+		let source = Shaders::default().source(shader).unwrap();
+		let start = source.find("const SLICING_NDC_LINEAR").unwrap();
+		let end = start + source[start..].find("fn slice_to_ndc_depth").unwrap();
+		source[start..end].to_string()
+	}
+
+	#[test]
+	fn shaders_slice_alike() {
+		assert_eq!(
+			slicing_source(shaders::COMPUTE),
+			slicing_source(shaders::RENDER)
+		);
+	}
+}
@@ -18,8 +18,129 @@ struct Camera {
     view: mat4x4<f32>,
     inv_proj: mat4x4<f32>,
     inv_view: mat4x4<f32>,
+    near: f32,
+    far: f32,
 };
 
+struct Slicing {
+    mode: u32,
+};
+
+struct Volume {
+    min: vec3<f32>,
+    space: u32,
+    max: vec3<f32>,
+};
+
+// Must match SdfSpace::id in sdf.rs
+const SPACE_FROXELS = 0u;
+const SPACE_WORLD = 1u;
+const SPACE_BRICKS = 2u;
+
+struct Blend {
+    mode: u32,
+    radius: f32,
+};
+
+struct Particle {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    material: u32,
+}
+
+struct Grid {
+    min: vec3<f32>,
+    count: u32,
+    max: vec3<f32>,
+    margin: f32,
+    cells: vec3<u32>,
+};
+
+// Must match BlendMode::id in blend.rs
+const BLEND_MIN = 0u;
+const BLEND_POLYNOMIAL = 1u;
+const BLEND_EXPONENTIAL = 2u;
+const BLEND_CUBIC = 3u;
+
+const SPACE_EXACT = 3u;
+
+// Must be the same as the one in grid.rs
+const MAX_PER_CELL = 256u;
+
+// Value of the exact SDF before any particle is merged into it, as in compute.wgsl
+const FAR = 1000.0;
+
+struct Bricks {
+    cells: vec3<u32>,
+    capacity: u32,
+    atlas: vec3<u32>,
+};
+
+// Must be the same as the one in bricks.rs
+const BRICK_SIZE = 8u;
+
+struct Light {
+    direction: vec3<f32>,
+    color: vec3<f32>,
+    intensity: f32,
+};
+
+// Must be the same as the one in light.rs
+const MAX_LIGHTS = 4;
+
+struct Lighting {
+    ground: vec3<f32>,
+    count: u32,
+    sky: vec3<f32>,
+    shadow_mode: u32,
+    lights: array<Light, MAX_LIGHTS>,
+    shadow_steps: u32,
+    shadow_softness: f32,
+    occlusion_mode: u32,
+    occlusion_samples: u32,
+    occlusion_radius: f32,
+};
+
+// Must match ShadowMode::id in light.rs
+const SHADOW_OFF = 0u;
+const SHADOW_HARD = 1u;
+const SHADOW_SOFT = 2u;
+
+// Must be the same as the one in material.rs
+const MAX_MATERIALS = 16;
+const DIELECTRIC_F0 = 0.04;
+const MIN_ROUGHNESS = 0.045;
+
+struct Material {
+    roughness: f32,
+    metallic: f32,
+    _pad: vec2<f32>,
+};
+
+struct Materials {
+    shading: u32,
+    count: u32,
+    ior: f32,
+    interior_steps: u32,
+    absorption: vec3<f32>,
+    materials: array<Material, MAX_MATERIALS>,
+};
+
+// Must match Shading::id in material.rs
+const SHADING_LAMBERT = 0u;
+const SHADING_PBR = 1u;
+const SHADING_LIQUID = 2u;
+
+const PI = 3.14159265;
+// Strength of the light from the sky compared to the lights
+const AMBIENT = 0.2;
+
+// Must match OcclusionMode::id in light.rs
+const OCCLUSION_OFF = 0u;
+const OCCLUSION_NORMAL = 1u;
+const OCCLUSION_CONE = 2u;
+
 @group(0) @binding(0)
 var<uniform> u_screen: Screen;
 
@@ -35,6 +156,70 @@ var sdf_sampler: sampler;
 @group(0) @binding(4)
 var sdf_tex_read: texture_3d<f32>;
 
+// Material of the nearest particle of every voxel of `sdf_tex_read`
+@group(0) @binding(9)
+var material_tex_read: texture_3d<u32>;
+
+@group(0) @binding(5)
+var<uniform> u_lighting: Lighting;
+
+@group(0) @binding(10)
+var<uniform> u_materials: Materials;
+
+@group(0) @binding(6)
+var<uniform> u_slicing: Slicing;
+
+@group(0) @binding(7)
+var<uniform> u_volume: Volume;
+
+// Slot of every brick of the volume in the atlas plus one, 0 when empty
+@group(1) @binding(0)
+var<storage> brick_slots: array<u32>;
+
+// Distance at the center of every brick of the volume
+@group(1) @binding(1)
+var<storage> brick_distances: array<f32>;
+
+@group(1) @binding(2)
+var<uniform> u_bricks: Bricks;
+
+@group(0) @binding(8)
+var<uniform> u_blend: Blend;
+
+@group(2) @binding(0)
+var<storage> all_particles: array<Particle>;
+
+@group(2) @binding(1)
+var<storage> cell_counts: array<u32>;
+
+@group(2) @binding(2)
+var<storage> cell_entries: array<u32>;
+
+@group(2) @binding(3)
+var<uniform> u_grid: Grid;
+
+// Must be the same as the one in environment.rs
+struct Environment {
+    enabled: u32,
+    intensity: f32,
+    specular_lod: f32,
+};
+
+@group(3) @binding(0)
+var environment_sampler: sampler;
+
+@group(3) @binding(1)
+var environment_radiance: texture_2d<f32>;
+
+@group(3) @binding(2)
+var environment_specular: texture_2d<f32>;
+
+@group(3) @binding(3)
+var environment_irradiance: texture_2d<f32>;
+
+@group(3) @binding(4)
+var<uniform> u_environment: Environment;
+
 // @group(3) @binding(0)
 // var<storage, read> u_particles: array<Particle>;
 
@@ -44,13 +229,65 @@ var sdf_tex_read: texture_3d<f32>;
 const NUM_OF_STEPS = 128;
 const MIN_DIST_TO_SDF = 0.005;
 const MAX_DIST_TO_TRAVEL = 64.0;
-const SUN_DIR = vec3(-1.0,-1.0,1.0) / 1.73205080757;
 
 fn sdf_box(p: vec3<f32>, size: vec3<f32>) -> f32 {
     let q = abs(p-size/2) - size/2;
     return length(max(q,vec3(0.0,0.0,0.0))) + min(max(q.x,max(q.y,q.z)),0.0);
 }
 
+// Must match Slicing::id in sdf.rs. The slicing functions below must be the same as the ones
+// in compute.wgsl, and match the CPU references of Slicing
+const SLICING_NDC_LINEAR = 0u;
+const SLICING_VIEW_LINEAR = 1u;
+const SLICING_LOGARITHMIC = 2u;
+
+// Normalized device depth of a view space depth, as projected by `perspective_lh`.
+fn view_to_ndc_depth(z: f32) -> f32 {
+    return u_camera.far / (u_camera.far - u_camera.near) * (1.0 - u_camera.near / z);
+}
+
+fn ndc_to_view_depth(d: f32) -> f32 {
+    return u_camera.near / (1.0 - d * (u_camera.far - u_camera.near) / u_camera.far);
+}
+
+// View space depth at a fraction `s` of the way through the slices of the froxel grid.
+fn slice_to_view_depth(s: f32) -> f32 {
+    switch u_slicing.mode {
+        case SLICING_VIEW_LINEAR: {
+            return mix(u_camera.near, u_camera.far, s);
+        }
+        case SLICING_LOGARITHMIC: {
+            return u_camera.near * pow(u_camera.far / u_camera.near, s);
+        }
+        default: {
+            return ndc_to_view_depth(s);
+        }
+    }
+}
+
+// Inverse of `slice_to_view_depth`, 0 at the near plane and 1 at the far one.
+fn view_depth_to_slice(z: f32) -> f32 {
+    switch u_slicing.mode {
+        case SLICING_VIEW_LINEAR: {
+            return (z - u_camera.near) / (u_camera.far - u_camera.near);
+        }
+        case SLICING_LOGARITHMIC: {
+            return log(z / u_camera.near) / log(u_camera.far / u_camera.near);
+        }
+        default: {
+            return view_to_ndc_depth(z);
+        }
+    }
+}
+
+fn slice_to_ndc_depth(s: f32) -> f32 {
+    if u_slicing.mode == SLICING_NDC_LINEAR {
+        return s;
+    }
+    return view_to_ndc_depth(slice_to_view_depth(s));
+}
+
+// Inverse of `screen_to_world`.
 fn world_to_screen(world_pos: vec3<f32>) -> vec3<f32> {
     // Transform world → view space
     let view_pos_hom = u_camera.view * vec4(world_pos, 1.0);
@@ -63,7 +300,7 @@ fn world_to_screen(world_pos: vec3<f32>) -> vec3<f32> {
     let screen_pos = vec3(
         ndc.x * 0.5 + 0.5,
         ndc.y * 0.5 + 0.5,
-        ndc.z
+        view_depth_to_slice(view_pos.z)
     );
 
     return screen_pos;
@@ -75,7 +312,7 @@ fn screen_to_world(pos: vec3<f32>) -> vec3<f32> {
     let ndc = vec3(
         pos.x * 2.0 - 1.0,
         pos.y * 2.0 - 1.0,
-        pos.z
+        slice_to_ndc_depth(pos.z)
     );
     
     // Reverts the projection matrix
@@ -89,7 +326,167 @@ fn screen_to_world(pos: vec3<f32>) -> vec3<f32> {
     return world_pos;
 }
 
+// Samples the world space volume, which is addressed by world coordinates directly.
+fn sdf_world(p: vec3<f32>) -> f32 {
+    let size = u_volume.max - u_volume.min;
+    let uvw = (p - u_volume.min) / size;
+    if any(uvw < vec3(0.0)) || any(uvw > vec3(1.0)) {
+        // Outside of the volume, step just past its boundary
+        return sdf_box(p - u_volume.min, size) + MIN_DIST_TO_SDF * 2.0;
+    }
+    return textureSample(sdf_tex_read, sdf_sampler, uvw).r;
+}
+
+// Samples the sparse volume, looking the brick containing `p` up in the atlas.
+fn sdf_bricks(p: vec3<f32>) -> f32 {
+    let size = u_volume.max - u_volume.min;
+    let uvw = (p - u_volume.min) / size;
+    if any(uvw < vec3(0.0)) || any(uvw > vec3(1.0)) {
+        // Outside of the volume, step just past its boundary
+        return sdf_box(p - u_volume.min, size) + MIN_DIST_TO_SDF * 2.0;
+    }
+
+    let cells = u_bricks.cells;
+    let grid = uvw * vec3<f32>(cells);
+    let cell = min(vec3<u32>(grid), cells - 1u);
+    let i = cell.x + cells.x * (cell.y + cells.y * cell.z);
+    let slot = brick_slots[i];
+    if slot == 0u {
+        // No surface within the brick, skip ahead by the distance at its center
+        // less how far from the center we are
+        let brick_size = size / vec3<f32>(cells);
+        let center = u_volume.min + (vec3<f32>(cell) + 0.5) * brick_size;
+        let d = brick_distances[i];
+        return sign(d) * max(abs(d) - length(p - center), MIN_DIST_TO_SDF * 2.0);
+    }
+
+    return textureSample(sdf_tex_read, sdf_sampler, atlas_coord(grid, cell, slot)).r;
+}
+
+// Normalized atlas coordinate of the position `grid`, in bricks, inside the brick `cell` stored in `slot`.
+fn atlas_coord(grid: vec3<f32>, cell: vec3<u32>, slot: u32) -> vec3<f32> {
+    let atlas = u_bricks.atlas;
+    let index = slot - 1u;
+    let origin = vec3(index % atlas.x, (index / atlas.x) % atlas.y, index / (atlas.x * atlas.y));
+    // Samples sit on the brick's boundaries, so its far face is BRICK_SIZE - 1 voxels away
+    let texel = vec3<f32>(origin * BRICK_SIZE) + 0.5 + (grid - vec3<f32>(cell)) * f32(BRICK_SIZE - 1u);
+    return texel / vec3<f32>(textureDimensions(sdf_tex_read));
+}
+
+// Same as in compute.wgsl
+fn smin_polynomial(a: f32, b: f32, k: f32) -> f32 {
+    let h = max(k - abs(a - b), 0.0) / k;
+    return min(a, b) - h * h * k * 0.25;
+}
+
+fn smin_exponential(a: f32, b: f32, k: f32) -> f32 {
+    let m = min(a, b);
+    let r = exp2(-(a - m) / k) + exp2(-(b - m) / k);
+    return m - k * log2(r);
+}
+
+fn smin_cubic(a: f32, b: f32, k: f32) -> f32 {
+    let h = max(k - abs(a - b), 0.0) / k;
+    return min(a, b) - h * h * h * k * (1.0 / 6.0);
+}
+
+fn blend(a: f32, b: f32) -> f32 {
+    let k = u_blend.radius;
+    if k <= 0.0 {
+        return min(a, b);
+    }
+    switch u_blend.mode {
+        case BLEND_POLYNOMIAL: {
+            return smin_polynomial(a, b, k);
+        }
+        case BLEND_EXPONENTIAL: {
+            return smin_exponential(a, b, k);
+        }
+        case BLEND_CUBIC: {
+            return smin_cubic(a, b, k);
+        }
+        default: {
+            return min(a, b);
+        }
+    }
+}
+
+// Same as in compute.wgsl
+fn blend_weight(a: f32, b: f32) -> f32 {
+    let k = u_blend.radius;
+    if k <= 0.0 || u_blend.mode == BLEND_MIN {
+        return select(0.0, 1.0, b < a);
+    }
+    if u_blend.mode == BLEND_EXPONENTIAL {
+        let m = min(a, b);
+        let wa = exp2(-(a - m) / k);
+        let wb = exp2(-(b - m) / k);
+        return wb / (wa + wb);
+    }
+    return saturate(0.5 + 0.5 * (a - b) / k);
+}
+
+// Same as in compute.wgsl
+struct SdfSample {
+    distance: f32,
+    color: vec3<f32>,
+    material: u32,
+};
+
+fn merge(sample: SdfSample, p: vec3<f32>, particle: Particle) -> SdfSample {
+    let d = length(p - particle.position) - particle.radius;
+    return SdfSample(
+        blend(sample.distance, d),
+        mix(sample.color, particle.color, blend_weight(sample.distance, d)),
+        select(sample.material, particle.material, d < sample.distance),
+    );
+}
+
+// Evaluates the particles binned into the grid cell containing `p`, without any filtering.
+fn sample_exact(p: vec3<f32>) -> SdfSample {
+    let size = u_grid.max - u_grid.min;
+    let uvw = (p - u_grid.min) / size;
+    if any(uvw < vec3(0.0)) || any(uvw > vec3(1.0)) {
+        // Outside of the grid, step just past its boundary
+        let d = sdf_box(p - u_grid.min, size) + MIN_DIST_TO_SDF * 2.0;
+        return SdfSample(d, vec3(0.0), 0u);
+    }
+
+    let cells = u_grid.cells;
+    let grid = uvw * vec3<f32>(cells);
+    let cell = min(vec3<u32>(grid), cells - 1u);
+    let i = cell.x + cells.x * (cell.y + cells.y * cell.z);
+
+    var value = SdfSample(FAR, vec3(0.0), 0u);
+    let count = min(cell_counts[i], MAX_PER_CELL);
+    for (var k = 0u; k < count; k++) {
+        let particle = all_particles[cell_entries[i * MAX_PER_CELL + k]];
+        value = merge(value, p, particle);
+    }
+
+    // Every particle left out of this cell is further than its reach past the cell's boundary
+    let cell_size = size / vec3<f32>(cells);
+    let local = (grid - vec3<f32>(cell)) * cell_size;
+    let to_face = min(local, cell_size - local);
+    let bound = u_blend.radius + u_grid.margin + min(to_face.x, min(to_face.y, to_face.z));
+    value.distance = min(value.distance, bound);
+    return value;
+}
+
+fn sdf_exact(p: vec3<f32>) -> f32 {
+    return sample_exact(p).distance;
+}
+
 fn sdf(p: vec3<f32>) -> f32 {
+    if u_volume.space == SPACE_EXACT {
+        return sdf_exact(p);
+    }
+    if u_volume.space == SPACE_WORLD {
+        return sdf_world(p);
+    }
+    if u_volume.space == SPACE_BRICKS {
+        return sdf_bricks(p);
+    }
     let norm = world_to_screen(p);
     if norm.z > 1 {
         return MAX_DIST_TO_TRAVEL;
@@ -102,6 +499,41 @@ fn sdf(p: vec3<f32>) -> f32 {
     // return total + textureSample(sdf_tex_read, sdf_sampler, p).r;
 }
 
+// Blended colour and nearest material stored next to the distance at `coord`.
+fn surface_at(coord: vec3<f32>) -> SdfSample {
+    let value = textureSampleLevel(sdf_tex_read, sdf_sampler, coord, 0.0);
+    let dims = textureDimensions(material_tex_read);
+    let texel = min(vec3<u32>(saturate(coord) * vec3<f32>(dims)), dims - 1u);
+    let material = textureLoad(material_tex_read, texel, 0).r;
+    return SdfSample(value.r, value.gba, material);
+}
+
+// Colour and material of the surface at `p`, looked up the same way as its distance.
+fn surface(p: vec3<f32>) -> SdfSample {
+    switch u_volume.space {
+        case SPACE_EXACT: {
+            return sample_exact(p);
+        }
+        case SPACE_WORLD: {
+            return surface_at((p - u_volume.min) / (u_volume.max - u_volume.min));
+        }
+        case SPACE_BRICKS: {
+            let uvw = saturate((p - u_volume.min) / (u_volume.max - u_volume.min));
+            let cells = u_bricks.cells;
+            let grid = uvw * vec3<f32>(cells);
+            let cell = min(vec3<u32>(grid), cells - 1u);
+            let slot = brick_slots[cell.x + cells.x * (cell.y + cells.y * cell.z)];
+            if slot == 0u {
+                return SdfSample(MAX_DIST_TO_TRAVEL, vec3(1.0), 0u);
+            }
+            return surface_at(atlas_coord(grid, cell, slot));
+        }
+        default: {
+            return surface_at(world_to_screen(p));
+        }
+    }
+}
+
 fn raymarch(orig: vec3<f32>, dir: vec3<f32>) -> f32 {
     var dist = 0.0;
     for (var i = 0; i < NUM_OF_STEPS; i++) {
@@ -127,21 +559,252 @@ fn normal(p: vec3<f32>) -> vec3<f32> {
     return normalize(normal);
 }
 
+// Must be the same as the one in environment.wgsl
+fn equirect_uv(dir: vec3<f32>) -> vec2<f32> {
+    return vec2(atan2(dir.x, -dir.z) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+}
+
 fn sky_color(n: vec3<f32>) -> vec3<f32> {
+    if u_environment.enabled != 0u {
+        let uv = equirect_uv(n);
+        return textureSampleLevel(environment_radiance, environment_sampler, uv, 0.0).rgb * u_environment.intensity;
+    }
     return mix(
-        vec3(0.58, 0.529, 0.459),
-        vec3(0.714, 0.812, 0.78),
+        u_lighting.ground,
+        u_lighting.sky,
         saturate(((n.y/0.01) + 1.0)/2.0)
     );
 }
 
 fn sky_color_diffuse(n: vec3<f32>) -> vec3<f32> {
+    if u_environment.enabled != 0u {
+        let uv = equirect_uv(n);
+        return textureSampleLevel(environment_irradiance, environment_sampler, uv, 0.0).rgb * u_environment.intensity;
+    }
     return mix(
-        vec3(0.58, 0.529, 0.459),
-        vec3(0.714, 0.812, 0.78),
+        u_lighting.ground,
+        u_lighting.sky,
         saturate(((n.y/0.5) + 1.0)/2.0)
     );
 }
+
+// Sky reflected along `r` off a surface of `roughness` with normal `n`, blurrier the rougher it is
+fn sky_reflection(r: vec3<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    if u_environment.enabled != 0u {
+        let uv = equirect_uv(r);
+        let lod = roughness * u_environment.specular_lod;
+        return textureSampleLevel(environment_specular, environment_sampler, uv, lod).rgb * u_environment.intensity;
+    }
+    return mix(sky_color(r), sky_color_diffuse(n), roughness);
+}
+
+// Whether `p` is past what the SDF covers, froxels know nothing past the frustum.
+fn outside_sdf(p: vec3<f32>) -> bool {
+    if u_volume.space != SPACE_FROXELS {
+        return false;
+    }
+    let screen = world_to_screen(p);
+    return any(screen < vec3(0.0)) || any(screen > vec3(1.0));
+}
+
+// Fraction of the light reaching `p` from along `to_light`, marched through the SDF.
+// Soft shadows darken by how closely the ray passes by a blocker relative to how far it
+// has travelled, which approximates the coverage of a cone toward the light.
+fn shadow(p: vec3<f32>, to_light: vec3<f32>) -> f32 {
+    if u_lighting.shadow_mode == SHADOW_OFF {
+        return 1.0;
+    }
+    var t = MIN_DIST_TO_SDF * 2.0;
+    var lit = 1.0;
+    for (var i = 0u; i < u_lighting.shadow_steps; i++) {
+        let q = p + to_light * t;
+        if outside_sdf(q) {
+            break;
+        }
+        let d = sdf(q);
+        if d < MIN_DIST_TO_SDF {
+            return 0.0;
+        }
+        if u_lighting.shadow_mode == SHADOW_SOFT {
+            lit = min(lit, d / (u_lighting.shadow_softness * t));
+        }
+        t += d;
+        if t > MAX_DIST_TO_TRAVEL {
+            break;
+        }
+    }
+    return smoothstep(0.0, 1.0, lit);
+}
+
+// Fraction of the sky seen from `p`, from how much closer the SDF gets to samples around the
+// normal than their distance to `p`. Nearer samples weigh more, they are the likelier to be occluded.
+fn occlusion(p: vec3<f32>, n: vec3<f32>) -> f32 {
+    if u_lighting.occlusion_mode == OCCLUSION_OFF || u_lighting.occlusion_samples == 0u {
+        return 1.0;
+    }
+    // Tangent frame, see "Building an Orthonormal Basis, Revisited" (Duff et al.)
+    let s = select(-1.0, 1.0, n.z >= 0.0);
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    let tangent = vec3(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    let bitangent = vec3(b, s + n.y * n.y * a, -n.y);
+
+    var occluded = 0.0;
+    var total = 0.0;
+    var weight = 1.0;
+    for (var i = 0u; i < u_lighting.occlusion_samples; i++) {
+        let h = u_lighting.occlusion_radius * f32(i + 1u) / f32(u_lighting.occlusion_samples);
+        var dir = n;
+        if u_lighting.occlusion_mode == OCCLUSION_CONE {
+            // Golden angle spiral, opening up to 45 degrees off the normal
+            let angle = f32(i) * 2.39996;
+            let spread = f32(i + 1u) / f32(u_lighting.occlusion_samples);
+            dir = normalize(n + spread * (cos(angle) * tangent + sin(angle) * bitangent));
+        }
+        let d = sdf(p + dir * h);
+        occluded += weight * saturate((h - d) / h);
+        total += weight;
+        weight *= 0.75;
+    }
+    return 1.0 - occluded / total;
+}
+
+// Must match the ones in material.rs
+
+fn fresnel_schlick(f0: vec3<f32>, cos_theta: f32) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - saturate(cos_theta), 5.0);
+}
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals
+fn distribution_ggx(n_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_h * n_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Height-correlated Smith visibility, the masking term divided by `4 n.v n.l`
+fn visibility_smith(n_v: f32, n_l: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let v = n_l * sqrt(n_v * n_v * (1.0 - a2) + a2);
+    let l = n_v * sqrt(n_l * n_l * (1.0 - a2) + a2);
+    return 0.5 / max(v + l, 1e-6);
+}
+
+fn brdf(albedo: vec3<f32>, material: Material, n: vec3<f32>, v: vec3<f32>, l: vec3<f32>) -> vec3<f32> {
+    let h = normalize(v + l);
+    let n_v = max(dot(n, v), 1e-4);
+    let n_l = saturate(dot(n, l));
+    let n_h = saturate(dot(n, h));
+    let v_h = saturate(dot(v, h));
+    let roughness = clamp(material.roughness, MIN_ROUGHNESS, 1.0);
+    let alpha = roughness * roughness;
+    let metallic = saturate(material.metallic);
+
+    let f0 = mix(vec3(DIELECTRIC_F0), albedo, metallic);
+    let fresnel = fresnel_schlick(f0, v_h);
+    let specular = fresnel * distribution_ggx(n_h, alpha) * visibility_smith(n_v, n_l, alpha);
+    let diffuse = (1.0 - fresnel_schlick(f0, n_v)) * (1.0 - metallic) * albedo / PI;
+    return diffuse + specular;
+}
+
+fn material_at(id: u32) -> Material {
+    return u_materials.materials[min(id, max(u_materials.count, 1u) - 1u)];
+}
+
+fn diffuse(p: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    var total = vec3(0.0);
+    for (var i = 0u; i < min(u_lighting.count, u32(MAX_LIGHTS)); i++) {
+        let light = u_lighting.lights[i];
+        let lambert = saturate(dot(n, -light.direction));
+        if lambert > 0.0 {
+            let lit = shadow(p + n * MIN_DIST_TO_SDF * 2.0, -light.direction);
+            total += light.color * light.intensity * lambert * lit;
+        }
+    }
+    return total;
+}
+
+// Lights and sky reflected toward `v` by a surface of `material`. Lights are scaled by PI, so
+// that a white rough dielectric is about as bright as under Lambert shading.
+fn shade_pbr(p: vec3<f32>, n: vec3<f32>, v: vec3<f32>, albedo: vec3<f32>, material: Material) -> vec3<f32> {
+    var color = vec3(0.0);
+    for (var i = 0u; i < min(u_lighting.count, u32(MAX_LIGHTS)); i++) {
+        let light = u_lighting.lights[i];
+        let l = -light.direction;
+        let n_l = saturate(dot(n, l));
+        if n_l > 0.0 {
+            let lit = shadow(p + n * MIN_DIST_TO_SDF * 2.0, l);
+            color += PI * light.color * light.intensity * brdf(albedo, material, n, v, l) * n_l * lit;
+        }
+    }
+
+    // The sky is split between diffuse and specular by the Fresnel of the view, with rough
+    // surfaces reflecting a blurrier, more diffuse sky
+    let roughness = clamp(material.roughness, MIN_ROUGHNESS, 1.0);
+    let metallic = saturate(material.metallic);
+    let f0 = mix(vec3(DIELECTRIC_F0), albedo, metallic);
+    let n_v = max(dot(n, v), 1e-4);
+    let fresnel = f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(1.0 - n_v, 5.0);
+    let reflected = sky_reflection(reflect(-v, n), n, roughness);
+    let ambient = (1.0 - fresnel) * (1.0 - metallic) * albedo * sky_color_diffuse(n) + fresnel * reflected;
+    return color + AMBIENT * ambient * occlusion(p, n);
+}
+
+// Distance travelled from `p`, inside the surface, along `dir` until it leaves the surface.
+fn march_interior(p: vec3<f32>, dir: vec3<f32>) -> f32 {
+    var t = 0.0;
+    for (var i = 0u; i < u_materials.interior_steps; i++) {
+        let q = p + dir * t;
+        if outside_sdf(q) {
+            break;
+        }
+        let d = -sdf(q);
+        if d < MIN_DIST_TO_SDF {
+            break;
+        }
+        t += d;
+    }
+    return t;
+}
+
+// Water-like dielectric: the sky reflected off the surface, plus the sky refracted through the
+// inside and out the far side, tinted by what the inside absorbed over that distance. Rays
+// leaving the far side see the sky only, not particles behind.
+fn shade_liquid(p: vec3<f32>, n: vec3<f32>, dir: vec3<f32>, material: Material) -> vec3<f32> {
+    let v = -dir;
+    let n_v = max(dot(n, v), 1e-4);
+    let f0 = pow((u_materials.ior - 1.0) / (u_materials.ior + 1.0), 2.0);
+    let fresnel = fresnel_schlick(vec3(f0), n_v).x;
+    let reflected = sky_color(reflect(dir, n));
+
+    let inside = refract(dir, n, 1.0 / u_materials.ior);
+    let entry = p - n * MIN_DIST_TO_SDF * 2.0;
+    let travelled = march_interior(entry, inside);
+    let exit = entry + inside * travelled;
+    let exit_normal = normal(exit);
+    var out = refract(inside, -exit_normal, u_materials.ior);
+    if all(out == vec3(0.0)) {
+        // Total internal reflection, the light bounces around inside until it is absorbed
+        out = reflect(inside, -exit_normal);
+    }
+    // Beer-Lambert, must match `Liquid::transmittance`
+    let transmittance = exp(-u_materials.absorption * travelled);
+    let refracted = sky_color(out) * transmittance;
+
+    // Glints of the lights, no diffuse from a clear medium
+    var glints = vec3(0.0);
+    for (var i = 0u; i < min(u_lighting.count, u32(MAX_LIGHTS)); i++) {
+        let light = u_lighting.lights[i];
+        let l = -light.direction;
+        let n_l = saturate(dot(n, l));
+        if n_l > 0.0 {
+            let lit = shadow(p + n * MIN_DIST_TO_SDF * 2.0, l);
+            glints += PI * light.color * light.intensity * brdf(vec3(0.0), material, n, v, l) * n_l * lit;
+        }
+    }
+    return mix(refracted, reflected, fresnel) + glints;
+}
+
 @vertex
 fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
     var pos = array(
@@ -179,11 +842,17 @@ fn fs_main(@builtin(position) screen_pos: vec4<f32>) -> @location(0) vec4<f32> {
     var color = vec3<f32>();
 
     if dist < MAX_DIST_TO_TRAVEL {
-        color = vec3(1.0,1.0,1.0);
-        let normal = normal(ray_origin + ray_dir * dist);
-        let phong = saturate(dot(normal, -SUN_DIR));
-        let ambient = sky_color_diffuse(normal);
-        color = vec3(phong,phong,phong) + 0.2 * ambient;
+        let hit = ray_origin + ray_dir * dist;
+        let sample = surface(hit);
+        let normal = normal(hit);
+        if u_materials.shading == SHADING_PBR {
+            color = shade_pbr(hit, normal, -ray_dir, sample.color, material_at(sample.material));
+        } else if u_materials.shading == SHADING_LIQUID {
+            color = shade_liquid(hit, normal, ray_dir, material_at(sample.material));
+        } else {
+            let ambient = sky_color_diffuse(normal) * occlusion(hit, normal);
+            color = sample.color * (diffuse(hit, normal) + AMBIENT * ambient);
+        }
     } else {
         color = sky_color(ray_dir);
     }
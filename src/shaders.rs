@@ -0,0 +1,300 @@
+use std::{
+	borrow::Cow,
+	error::Error,
+	fmt,
+	path::PathBuf,
+	time::{Duration, Instant, SystemTime},
+};
+
+/// A WGSL file compiled into the binary.
+#[derive(Debug, Copy, Clone)]
+pub struct Shader {
+	pub file: &'static str,
+	pub label: &'static str,
+	source: &'static str,
+}
+
+pub const COMPUTE: Shader = Shader {
+	file: "compute.wgsl",
+	label: "Raymarch Compute Shader",
+	source: include_str!("compute.wgsl"),
+};
+
+pub const RENDER: Shader = Shader {
+	file: "shader.wgsl",
+	label: "Raymarch Render Shader",
+	source: include_str!("shader.wgsl"),
+};
+
+pub const SIMULATION: Shader = Shader {
+	file: "simulation.wgsl",
+	label: "Simulation Shader",
+	source: include_str!("simulation.wgsl"),
+};
+
+pub const SPH: Shader = Shader {
+	file: "sph.wgsl",
+	label: "SPH Shader",
+	source: include_str!("sph.wgsl"),
+};
+
+pub const ENVIRONMENT: Shader = Shader {
+	file: "environment.wgsl",
+	label: "Environment Shader",
+	source: include_str!("environment.wgsl"),
+};
+
+pub const ALL: [Shader; 5] = [COMPUTE, RENDER, SIMULATION, SPH, ENVIRONMENT];
+
+#[derive(Debug)]
+pub enum ShaderError {
+	Io {
+		path: PathBuf,
+		error: std::io::Error,
+	},
+	/// The source failed to parse or validate. `position` is the line and column, if known.
+	Invalid {
+		file: String,
+		position: Option<(u32, u32)>,
+		message: String,
+	},
+	/// The source is valid but doesn't fit the pipelines built from it.
+	Pipeline(String),
+}
+
+impl fmt::Display for ShaderError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ShaderError::Io { path, error } => write!(f, "{}: {error}", path.display()),
+			ShaderError::Invalid {
+				file,
+				position: Some((line, column)),
+				message,
+			} => write!(f, "{file}:{line}:{column}: {message}"),
+			ShaderError::Invalid {
+				file,
+				position: None,
+				message,
+			} => write!(f, "{file}: {message}"),
+			ShaderError::Pipeline(message) => write!(f, "{message}"),
+		}
+	}
+}
+
+impl std::error::Error for ShaderError {}
+
+/// Where shader sources come from: the embedded copies, or a directory of
+/// WGSL files that is read instead, for iterating on them without rebuilding.
+#[derive(Debug, Clone, Default)]
+pub struct Shaders {
+	dir: Option<PathBuf>,
+}
+
+impl Shaders {
+	pub fn from_dir(dir: PathBuf) -> Self {
+		Self { dir: Some(dir) }
+	}
+
+	/// Path the source of `shader` is read from, or just its file name when embedded.
+	pub fn path(&self, shader: Shader) -> PathBuf {
+		match &self.dir {
+			Some(dir) => dir.join(shader.file),
+			None => PathBuf::from(shader.file),
+		}
+	}
+
+	pub fn source(&self, shader: Shader) -> Result<Cow<'static, str>, ShaderError> {
+		match &self.dir {
+			Some(_) => {
+				let path = self.path(shader);
+				std::fs::read_to_string(&path)
+					.map(Cow::Owned)
+					.map_err(|error| ShaderError::Io { path, error })
+			}
+			None => Ok(Cow::Borrowed(shader.source)),
+		}
+	}
+
+	/// Loads and validates `shader`, so that mistakes are reported instead of panicking inside wgpu.
+	pub fn create_module(
+		&self,
+		device: &wgpu::Device,
+		shader: Shader,
+	) -> Result<wgpu::ShaderModule, ShaderError> {
+		let source = self.source(shader)?;
+		validate(&self.path(shader).to_string_lossy(), &source)?;
+		Ok(device.create_shader_module(wgpu::ShaderModuleDescriptor {
+			label: Some(shader.label),
+			source: wgpu::ShaderSource::Wgsl(source),
+		}))
+	}
+}
+
+/// How often [`ShaderWatcher`] looks at the files again.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Watches shader overrides for edits by polling their modification times.
+pub struct ShaderWatcher {
+	files: Vec<(PathBuf, Option<SystemTime>)>,
+	last_poll: Instant,
+}
+
+impl ShaderWatcher {
+	/// Returns `None` for embedded shaders, which can't change while running.
+	pub fn new(shaders: &Shaders, watched: &[Shader]) -> Option<Self> {
+		shaders.dir.as_ref()?;
+		let files = watched
+			.iter()
+			.map(|&shader| {
+				let path = shaders.path(shader);
+				let modified = modified(&path);
+				(path, modified)
+			})
+			.collect();
+		Some(Self {
+			files,
+			last_poll: Instant::now(),
+		})
+	}
+
+	/// Whether any of the files was written since the last call that returned `true`.
+	pub fn changed(&mut self) -> bool {
+		if self.last_poll.elapsed() < POLL_INTERVAL {
+			return false;
+		}
+		self.last_poll = Instant::now();
+		let mut changed = false;
+		for (path, last_modified) in &mut self.files {
+			let modified = modified(path);
+			if modified != *last_modified {
+				*last_modified = modified;
+				changed = true;
+			}
+		}
+		changed
+	}
+}
+
+fn modified(path: &std::path::Path) -> Option<SystemTime> {
+	std::fs::metadata(path)
+		.and_then(|meta| meta.modified())
+		.ok()
+}
+
+/// Parses and validates WGSL on the CPU with naga, the same way wgpu does.
+pub fn validate(file: &str, source: &str) -> Result<naga::Module, ShaderError> {
+	let position = |location: Option<naga::SourceLocation>| {
+		location.map(|location| (location.line_number, location.line_position))
+	};
+
+	let module = naga::front::wgsl::parse_str(source).map_err(|err| ShaderError::Invalid {
+		file: file.to_owned(),
+		position: position(err.location(source)),
+		message: err.message().to_owned(),
+	})?;
+
+	naga::valid::Validator::new(
+		naga::valid::ValidationFlags::all(),
+		naga::valid::Capabilities::default(),
+	)
+	.validate(&module)
+	.map_err(|err| {
+		let mut message = err.as_inner().to_string();
+		let mut cause = err.as_inner().source();
+		while let Some(inner) = cause {
+			message += &format!(": {inner}");
+			cause = inner.source();
+		}
+		// Spans narrow down from the enclosing function, the last one is the most precise
+		let location = err
+			.spans()
+			.last()
+			.filter(|(span, _)| span.is_defined())
+			.map(|(span, _)| span.location(source));
+		ShaderError::Invalid {
+			file: file.to_owned(),
+			position: position(location),
+			message,
+		}
+	})?;
+
+	Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn embedded_shaders_validate() {
+		for shader in ALL {
+			if let Err(err) = validate(shader.file, shader.source) {
+				panic!("{err}");
+			}
+		}
+	}
+
+	#[test]
+	fn parse_errors_point_at_their_line() {
+		let source = "fn main() {\n    let x = ;\n}\n";
+		match validate("broken.wgsl", source) {
+			Err(ShaderError::Invalid {
+				file,
+				position: Some((line, _)),
+				..
+			}) => {
+				assert_eq!(file, "broken.wgsl");
+				assert_eq!(line, 2);
+			}
+			other => panic!("expected a parse error, got {other:?}"),
+		}
+	}
+
+	#[test]
+	fn validation_errors_point_at_their_line() {
+		let source = "fn f() -> i32 {\n    return 1;\n    return 2;\n}\n";
+		match validate("invalid.wgsl", source) {
+			Err(ShaderError::Invalid {
+				position: Some((line, _)),
+				..
+			}) => assert_eq!(line, 3),
+			other => panic!("expected a validation error, got {other:?}"),
+		}
+	}
+
+	#[test]
+	fn missing_override_is_an_io_error() {
+		let shaders = Shaders::from_dir(PathBuf::from("does/not/exist"));
+		assert!(matches!(
+			shaders.source(COMPUTE),
+			Err(ShaderError::Io { .. })
+		));
+	}
+
+	#[test]
+	fn watcher_notices_edits() {
+		let dir = std::env::temp_dir().join(format!("shader_watcher_{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = dir.join(COMPUTE.file);
+		std::fs::write(&path, COMPUTE.source).unwrap();
+
+		let shaders = Shaders::from_dir(dir.clone());
+		let mut watcher = ShaderWatcher::new(&shaders, &[COMPUTE]).unwrap();
+		std::thread::sleep(POLL_INTERVAL);
+		assert!(!watcher.changed());
+
+		let file = std::fs::File::options().write(true).open(&path).unwrap();
+		file.set_modified(SystemTime::now() + Duration::from_secs(1))
+			.unwrap();
+		std::thread::sleep(POLL_INTERVAL);
+		assert!(watcher.changed());
+		assert!(!watcher.changed());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn embedded_shaders_are_not_watched() {
+		assert!(ShaderWatcher::new(&Shaders::default(), &ALL).is_none());
+	}
+}
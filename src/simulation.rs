@@ -0,0 +1,378 @@
+use glam::Vec3;
+use wgpu::util::DeviceExt;
+
+use crate::{
+	particle::Particle,
+	shaders::{self, ShaderError, Shaders},
+	sph::{Sph, SphBuffers},
+	time::TimeUniform,
+};
+
+/// Length of one simulation step in seconds.
+pub const FIXED_STEP: f32 = 1.0 / 120.0;
+/// Upper bound of steps run in a single frame, so a stall doesn't snowball.
+pub const MAX_STEPS_PER_FRAME: u32 = 8;
+
+// Must be the same as the one in simulation.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum SimulationMode {
+	/// Particles fall independently under gravity and drag.
+	#[default]
+	Ballistic,
+	/// Particles interact as a fluid, see [`Sph`].
+	Sph,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SimulationSettings {
+	pub mode: SimulationMode,
+	pub gravity: Vec3,
+	pub bounds_min: Vec3,
+	pub bounds_max: Vec3,
+	pub restitution: f32,
+	pub drag: f32,
+	/// Starts out set so scenes render as loaded until the simulation is resumed.
+	pub paused: bool,
+}
+
+impl Default for SimulationSettings {
+	fn default() -> Self {
+		Self {
+			mode: SimulationMode::Ballistic,
+			gravity: Vec3::new(0.0, -1.0, 0.0),
+			bounds_min: Vec3::splat(-1.0),
+			bounds_max: Vec3::splat(1.0),
+			restitution: 0.5,
+			drag: 0.1,
+			paused: true,
+		}
+	}
+}
+
+impl SimulationSettings {
+	/// CPU reference of `collide` in `simulation.wgsl`.
+	pub fn collide(&self, position: &mut Vec3, velocity: &mut Vec3, radius: f32) {
+		let lo = self.bounds_min + radius;
+		let hi = self.bounds_max - radius;
+		for axis in 0..3 {
+			if position[axis] < lo[axis] {
+				position[axis] = lo[axis];
+				velocity[axis] = velocity[axis].abs() * self.restitution;
+			} else if position[axis] > hi[axis] {
+				position[axis] = hi[axis];
+				velocity[axis] = -velocity[axis].abs() * self.restitution;
+			}
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SimulationUniform {
+	gravity: [f32; 3],
+	dt: f32,
+	bounds_min: [f32; 3],
+	restitution: f32,
+	bounds_max: [f32; 3],
+	drag: f32,
+	count: u32,
+	_pad: [u32; 3],
+}
+
+impl SimulationUniform {
+	pub fn bytes(&self) -> &[u8] {
+		bytemuck::bytes_of(self)
+	}
+}
+
+/// Moves the particles on the GPU with a fixed timestep.
+///
+/// Each step reads the particle buffer shared with the SDF pass, writes the result
+/// into a back buffer and copies it over the front one afterwards.
+pub struct Simulation {
+	pub settings: SimulationSettings,
+	pub sph: Sph,
+	pipeline: wgpu::ComputePipeline,
+	group: wgpu::BindGroup,
+	uniform_buffer: wgpu::Buffer,
+	back_particles: wgpu::Buffer,
+	velocities: wgpu::Buffer,
+	back_velocities: wgpu::Buffer,
+	count: u32,
+	accumulator: f32,
+	last_time: f32,
+}
+
+impl Simulation {
+	pub fn new(
+		device: &wgpu::Device,
+		particles: &wgpu::Buffer,
+		count: u32,
+		shaders: &Shaders,
+	) -> Result<Self, ShaderError> {
+		let shader = shaders.create_module(device, shaders::SIMULATION)?;
+
+		let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility: wgpu::ShaderStages::COMPUTE,
+			ty: wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Storage { read_only },
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		};
+
+		let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Simulation Layout Group"),
+			entries: &[
+				storage(0, true),
+				storage(1, true),
+				storage(2, false),
+				storage(3, false),
+				wgpu::BindGroupLayoutEntry {
+					binding: 4,
+					visibility: wgpu::ShaderStages::COMPUTE,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+			],
+		});
+
+		let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("Simulation Pipeline Layout"),
+			bind_group_layouts: &[&layout],
+			push_constant_ranges: &[],
+		});
+
+		let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+			label: Some("Compute Pipeline (Integrate)"),
+			layout: Some(&pipeline_layout),
+			module: &shader,
+			entry_point: Some("cs_integrate"),
+			compilation_options: Default::default(),
+			cache: Default::default(),
+		});
+
+		let back_particles = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Particle Back Buffer"),
+			size: particles.size(),
+			usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+			mapped_at_creation: false,
+		});
+
+		let velocities_size = particles.size() / std::mem::size_of::<Particle>() as u64
+			* std::mem::size_of::<[f32; 4]>() as u64;
+		let velocities = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Velocity Buffer"),
+			size: velocities_size,
+			usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+		let back_velocities = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Velocity Back Buffer"),
+			size: velocities_size,
+			usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+			mapped_at_creation: false,
+		});
+
+		let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("Simulation Buffer"),
+			contents: bytemuck::bytes_of(&SimulationUniform::default()),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		});
+
+		let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Simulation Group"),
+			layout: &layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: particles.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: velocities.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 2,
+					resource: back_particles.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 3,
+					resource: back_velocities.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 4,
+					resource: uniform_buffer.as_entire_binding(),
+				},
+			],
+		});
+
+		let sph = Sph::new(
+			device,
+			SphBuffers {
+				particles,
+				velocities: &velocities,
+				back_particles: &back_particles,
+				back_velocities: &back_velocities,
+				simulation: &uniform_buffer,
+			},
+			particles.size() / std::mem::size_of::<Particle>() as u64,
+			shaders,
+		)?;
+
+		Ok(Self {
+			settings: Default::default(),
+			sph,
+			pipeline,
+			group,
+			uniform_buffer,
+			back_particles,
+			velocities,
+			back_velocities,
+			count,
+			accumulator: 0.0,
+			last_time: 0.0,
+		})
+	}
+
+	pub fn uniform(&self) -> SimulationUniform {
+		SimulationUniform {
+			gravity: self.settings.gravity.to_array(),
+			dt: FIXED_STEP,
+			bounds_min: self.settings.bounds_min.to_array(),
+			restitution: self.settings.restitution,
+			bounds_max: self.settings.bounds_max.to_array(),
+			drag: self.settings.drag,
+			count: self.count,
+			_pad: [0; 3],
+		}
+	}
+
+	/// Feeds the elapsed time into the accumulator and returns how many steps are due.
+	pub fn advance(&mut self, time: &TimeUniform) -> u32 {
+		let delta = (time.seconds() - self.last_time).max(0.0);
+		self.last_time = time.seconds();
+		if self.settings.paused || self.count == 0 {
+			self.accumulator = 0.0;
+			return 0;
+		}
+		self.accumulator = (self.accumulator + delta).min(FIXED_STEP * MAX_STEPS_PER_FRAME as f32);
+		let steps = (self.accumulator / FIXED_STEP) as u32;
+		self.accumulator -= steps as f32 * FIXED_STEP;
+		steps
+	}
+
+	pub fn encode(
+		&mut self,
+		queue: &wgpu::Queue,
+		encoder: &mut wgpu::CommandEncoder,
+		particles: &wgpu::Buffer,
+		steps: u32,
+	) {
+		self.sph.check_overflow();
+		if steps == 0 {
+			return;
+		}
+		queue.write_buffer(&self.uniform_buffer, 0, self.uniform().bytes());
+		if self.settings.mode == SimulationMode::Sph {
+			self.sph.write_uniform(queue, &self.settings);
+		}
+
+		let particles_size = self.count as u64 * std::mem::size_of::<Particle>() as u64;
+		let velocities_size = self.count as u64 * std::mem::size_of::<[f32; 4]>() as u64;
+		if self.settings.mode == SimulationMode::Sph {
+			self.sph.clear_overflow(encoder);
+		}
+		for _ in 0..steps {
+			match self.settings.mode {
+				SimulationMode::Ballistic => {
+					let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+						label: Some("Simulation Pass"),
+						timestamp_writes: None,
+					});
+					pass.set_pipeline(&self.pipeline);
+					pass.set_bind_group(0, &self.group, &[]);
+					pass.dispatch_workgroups(self.count.div_ceil(WORKGROUP_SIZE), 1, 1);
+				}
+				SimulationMode::Sph => self.sph.encode(encoder, self.count),
+			}
+			encoder.copy_buffer_to_buffer(&self.back_particles, 0, particles, 0, particles_size);
+			encoder.copy_buffer_to_buffer(
+				&self.back_velocities,
+				0,
+				&self.velocities,
+				0,
+				velocities_size,
+			);
+		}
+		if self.settings.mode == SimulationMode::Sph {
+			self.sph.copy_overflow(encoder);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::particle;
+
+	fn running(count: usize) -> Simulation {
+		let (device, _queue) =
+			pollster::block_on(crate::headless::request_device()).expect("no adapter to test with");
+		let particles = particle::grid(count, 1, 1);
+		let buffer = particle::create_buffer(&device, &particles);
+		let mut simulation =
+			Simulation::new(&device, &buffer, count as u32, &Shaders::default()).unwrap();
+		simulation.settings.paused = false;
+		simulation
+	}
+
+	fn at(seconds: f32) -> TimeUniform {
+		TimeUniform::from_seconds(seconds)
+	}
+
+	#[test]
+	fn is_paused_by_default() {
+		assert!(SimulationSettings::default().paused);
+	}
+
+	#[test]
+	fn accumulates_fixed_steps() {
+		let mut simulation = running(4);
+		assert_eq!(simulation.advance(&at(0.0)), 0);
+		assert_eq!(simulation.advance(&at(FIXED_STEP * 0.5)), 0);
+		// The leftover half step carries over into the next frame
+		assert_eq!(simulation.advance(&at(FIXED_STEP * 2.0)), 2);
+		assert_eq!(simulation.advance(&at(FIXED_STEP * 2.75)), 0);
+		assert_eq!(simulation.advance(&at(FIXED_STEP * 3.25)), 1);
+	}
+
+	#[test]
+	fn clamps_steps_after_a_stall() {
+		let mut simulation = running(4);
+		assert_eq!(simulation.advance(&at(10.0)), MAX_STEPS_PER_FRAME);
+		// The stall is dropped rather than caught up on over the next frames
+		assert_eq!(simulation.advance(&at(10.0 + FIXED_STEP * 0.5)), 0);
+	}
+
+	#[test]
+	fn runs_no_steps_while_paused_or_empty() {
+		let mut simulation = running(4);
+		simulation.settings.paused = true;
+		assert_eq!(simulation.advance(&at(1.0)), 0);
+		// Time spent paused isn't caught up on either
+		simulation.settings.paused = false;
+		assert_eq!(simulation.advance(&at(1.0 + FIXED_STEP * 0.5)), 0);
+
+		let mut empty = running(0);
+		assert_eq!(empty.advance(&at(1.0)), 0);
+	}
+}
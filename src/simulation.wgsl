@@ -0,0 +1,67 @@
+struct Particle {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    material: u32,
+}
+
+struct Simulation {
+    gravity: vec3<f32>,
+    dt: f32,
+    bounds_min: vec3<f32>,
+    restitution: f32,
+    bounds_max: vec3<f32>,
+    drag: f32,
+    count: u32,
+};
+
+const WORKGROUP_SIZE = 64;
+
+@group(0) @binding(0)
+var<storage, read> particles_in: array<Particle>;
+
+@group(0) @binding(1)
+var<storage, read> velocities_in: array<vec4<f32>>;
+
+@group(0) @binding(2)
+var<storage, read_write> particles_out: array<Particle>;
+
+@group(0) @binding(3)
+var<storage, read_write> velocities_out: array<vec4<f32>>;
+
+@group(0) @binding(4)
+var<uniform> u_sim: Simulation;
+
+// Reflects the particle off the walls of the simulation box.
+fn collide(particle: ptr<function, Particle>, velocity: ptr<function, vec3<f32>>) {
+    let lo = u_sim.bounds_min + (*particle).radius;
+    let hi = u_sim.bounds_max - (*particle).radius;
+    for (var axis = 0; axis < 3; axis++) {
+        if (*particle).position[axis] < lo[axis] {
+            (*particle).position[axis] = lo[axis];
+            (*velocity)[axis] = abs((*velocity)[axis]) * u_sim.restitution;
+        } else if (*particle).position[axis] > hi[axis] {
+            (*particle).position[axis] = hi[axis];
+            (*velocity)[axis] = -abs((*velocity)[axis]) * u_sim.restitution;
+        }
+    }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_integrate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= u_sim.count {
+        return;
+    }
+    var particle = particles_in[i];
+    var velocity = velocities_in[i].xyz;
+
+    // Semi-implicit Euler
+    velocity += u_sim.gravity * u_sim.dt;
+    velocity *= max(1.0 - u_sim.drag * u_sim.dt, 0.0);
+    particle.position += velocity * u_sim.dt;
+    collide(&particle, &velocity);
+
+    particles_out[i] = particle;
+    velocities_out[i] = vec4(velocity, 0.0);
+}
@@ -0,0 +1,556 @@
+use std::f32::consts::PI;
+
+use glam::{UVec3, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::{
+	particle::Particle,
+	readback::{Readback, Warning},
+	shaders::{self, ShaderError, Shaders},
+	simulation::SimulationSettings,
+};
+
+// Must be the same as the ones in sph.wgsl
+const WORKGROUP_SIZE: u32 = 64;
+pub const MAX_PER_CELL: u32 = 64;
+
+/// Cells per axis of the neighbour grid are capped to this.
+/// Cells grow past the smoothing radius instead, so the search stays correct.
+pub const MAX_GRID_SIZE: u32 = 32;
+
+#[derive(Debug, Copy, Clone)]
+pub struct SphSettings {
+	pub smoothing_radius: f32,
+	pub rest_density: f32,
+	pub stiffness: f32,
+	pub viscosity: f32,
+	pub mass: f32,
+}
+
+impl Default for SphSettings {
+	fn default() -> Self {
+		Self {
+			smoothing_radius: 0.2,
+			rest_density: 1000.0,
+			stiffness: 3.0,
+			viscosity: 2.0,
+			mass: 1.0,
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SphUniform {
+	grid_size: [u32; 3],
+	smoothing_radius: f32,
+	rest_density: f32,
+	stiffness: f32,
+	viscosity: f32,
+	mass: f32,
+}
+
+impl SphUniform {
+	pub fn bytes(&self) -> &[u8] {
+		bytemuck::bytes_of(self)
+	}
+}
+
+impl SphSettings {
+	/// Cells per axis so that every cell is at least one smoothing radius wide.
+	pub fn grid_size(&self, simulation: &SimulationSettings) -> UVec3 {
+		let extent = simulation.bounds_max - simulation.bounds_min;
+		let cells = (extent / self.smoothing_radius).floor().as_uvec3();
+		cells.clamp(UVec3::ONE, UVec3::splat(MAX_GRID_SIZE))
+	}
+
+	pub fn uniform(&self, simulation: &SimulationSettings) -> SphUniform {
+		SphUniform {
+			grid_size: self.grid_size(simulation).to_array(),
+			smoothing_radius: self.smoothing_radius,
+			rest_density: self.rest_density,
+			stiffness: self.stiffness,
+			viscosity: self.viscosity,
+			mass: self.mass,
+		}
+	}
+}
+
+/// Buffers the SPH passes share with the basic integrator.
+pub struct SphBuffers<'a> {
+	pub particles: &'a wgpu::Buffer,
+	pub velocities: &'a wgpu::Buffer,
+	pub back_particles: &'a wgpu::Buffer,
+	pub back_velocities: &'a wgpu::Buffer,
+	pub simulation: &'a wgpu::Buffer,
+}
+
+/// Smoothed particle hydrodynamics on the GPU: density, then pressure and viscosity forces,
+/// with neighbours found through a uniform grid rebuilt every step.
+pub struct Sph {
+	pub settings: SphSettings,
+	clear_grid_pipeline: wgpu::ComputePipeline,
+	bin_pipeline: wgpu::ComputePipeline,
+	density_pipeline: wgpu::ComputePipeline,
+	forces_pipeline: wgpu::ComputePipeline,
+	group: wgpu::BindGroup,
+	uniform_buffer: wgpu::Buffer,
+	overflow_buffer: wgpu::Buffer,
+	overflow: Readback,
+	overflow_warning: Warning,
+}
+
+impl Sph {
+	pub fn new(
+		device: &wgpu::Device,
+		buffers: SphBuffers,
+		capacity: u64,
+		shaders: &Shaders,
+	) -> Result<Self, ShaderError> {
+		let shader = shaders.create_module(device, shaders::SPH)?;
+
+		let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility: wgpu::ShaderStages::COMPUTE,
+			ty: wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Storage { read_only },
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		};
+		let uniform = |binding| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility: wgpu::ShaderStages::COMPUTE,
+			ty: wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Uniform,
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		};
+
+		let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("SPH Layout Group"),
+			entries: &[
+				storage(0, true),
+				storage(1, true),
+				storage(2, false),
+				storage(3, false),
+				uniform(4),
+				uniform(5),
+				storage(6, false),
+				storage(7, false),
+				storage(8, false),
+				storage(9, false),
+			],
+		});
+
+		let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("SPH Pipeline Layout"),
+			bind_group_layouts: &[&layout],
+			push_constant_ranges: &[],
+		});
+
+		let pipeline = |label, entry_point| {
+			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+				label: Some(label),
+				layout: Some(&pipeline_layout),
+				module: &shader,
+				entry_point: Some(entry_point),
+				compilation_options: Default::default(),
+				cache: Default::default(),
+			})
+		};
+		let clear_grid_pipeline = pipeline("Compute Pipeline (SPH Clear Grid)", "cs_clear_grid");
+		let bin_pipeline = pipeline("Compute Pipeline (SPH Bin)", "cs_bin");
+		let density_pipeline = pipeline("Compute Pipeline (SPH Density)", "cs_density");
+		let forces_pipeline = pipeline("Compute Pipeline (SPH Forces)", "cs_forces");
+
+		let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("SPH Buffer"),
+			contents: bytemuck::bytes_of(&SphUniform::default()),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		});
+
+		let densities = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("SPH Density Buffer"),
+			size: capacity * std::mem::size_of::<[f32; 2]>() as u64,
+			usage: wgpu::BufferUsages::STORAGE,
+			mapped_at_creation: false,
+		});
+
+		let cells = MAX_GRID_SIZE as u64 * MAX_GRID_SIZE as u64 * MAX_GRID_SIZE as u64;
+		let cell_counts = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("SPH Cell Count Buffer"),
+			size: cells * std::mem::size_of::<u32>() as u64,
+			usage: wgpu::BufferUsages::STORAGE,
+			mapped_at_creation: false,
+		});
+		let cell_entries = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("SPH Cell Entry Buffer"),
+			size: cells * MAX_PER_CELL as u64 * std::mem::size_of::<u32>() as u64,
+			usage: wgpu::BufferUsages::STORAGE,
+			mapped_at_creation: false,
+		});
+
+		let overflow_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("SPH Overflow Buffer"),
+			size: std::mem::size_of::<u32>() as u64,
+			usage: wgpu::BufferUsages::STORAGE
+				| wgpu::BufferUsages::COPY_SRC
+				| wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("SPH Group"),
+			layout: &layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: buffers.particles.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: buffers.velocities.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 2,
+					resource: buffers.back_particles.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 3,
+					resource: buffers.back_velocities.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 4,
+					resource: buffers.simulation.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 5,
+					resource: uniform_buffer.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 6,
+					resource: densities.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 7,
+					resource: cell_counts.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 8,
+					resource: cell_entries.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 9,
+					resource: overflow_buffer.as_entire_binding(),
+				},
+			],
+		});
+
+		Ok(Self {
+			settings: Default::default(),
+			clear_grid_pipeline,
+			bin_pipeline,
+			density_pipeline,
+			forces_pipeline,
+			group,
+			uniform_buffer,
+			overflow_buffer,
+			overflow: Readback::new(device, "SPH Overflow Readback Buffer"),
+			overflow_warning: Warning::default(),
+		})
+	}
+
+	pub fn write_uniform(&self, queue: &wgpu::Queue, simulation: &SimulationSettings) {
+		let uniform = self.settings.uniform(simulation);
+		queue.write_buffer(&self.uniform_buffer, 0, uniform.bytes());
+	}
+
+	/// Warns when cells held more particles than they fit in a recent frame,
+	/// the neighbour search then misses some of them.
+	pub fn check_overflow(&mut self) {
+		if let Some(largest) = self.overflow.read() {
+			self.overflow_warning.update(largest > 0, || {
+				format!(
+					"an SPH cell holds {largest} particles but only {MAX_PER_CELL} are used, \
+					increase the smoothing radius or the bounds"
+				)
+			});
+		}
+	}
+
+	/// Resets the overflow counter, before the first step of a frame.
+	pub fn clear_overflow(&self, encoder: &mut wgpu::CommandEncoder) {
+		encoder.clear_buffer(&self.overflow_buffer, 0, None);
+	}
+
+	/// Reads the overflow counter back, after the last step of a frame.
+	pub fn copy_overflow(&self, encoder: &mut wgpu::CommandEncoder) {
+		self.overflow.copy(encoder, &self.overflow_buffer, 0);
+	}
+
+	/// Records one SPH step, leaving the result in the back buffers.
+	pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, count: u32) {
+		let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+			label: Some("SPH Pass"),
+			timestamp_writes: None,
+		});
+		let cells = MAX_GRID_SIZE * MAX_GRID_SIZE * MAX_GRID_SIZE;
+		let particle_groups = count.div_ceil(WORKGROUP_SIZE);
+		pass.set_bind_group(0, &self.group, &[]);
+
+		pass.set_pipeline(&self.clear_grid_pipeline);
+		pass.dispatch_workgroups(cells.div_ceil(WORKGROUP_SIZE), 1, 1);
+		pass.set_pipeline(&self.bin_pipeline);
+		pass.dispatch_workgroups(particle_groups, 1, 1);
+		pass.set_pipeline(&self.density_pipeline);
+		pass.dispatch_workgroups(particle_groups, 1, 1);
+		pass.set_pipeline(&self.forces_pipeline);
+		pass.dispatch_workgroups(particle_groups, 1, 1);
+	}
+}
+
+/// CPU reference of `poly6` in `sph.wgsl`.
+pub fn poly6(r2: f32, h: f32) -> f32 {
+	let h2 = h * h;
+	if r2 >= h2 {
+		return 0.0;
+	}
+	let x = h2 - r2;
+	315.0 / (64.0 * PI * h.powi(9)) * x * x * x
+}
+
+/// CPU reference of `spiky_gradient` in `sph.wgsl`.
+pub fn spiky_gradient(r: Vec3, h: f32) -> Vec3 {
+	let d = r.length();
+	if d >= h || d <= 1e-6 {
+		return Vec3::ZERO;
+	}
+	let x = h - d;
+	-45.0 / (PI * h.powi(6)) * x * x * (r / d)
+}
+
+/// CPU reference of `viscosity_laplacian` in `sph.wgsl`.
+pub fn viscosity_laplacian(d: f32, h: f32) -> f32 {
+	if d >= h {
+		return 0.0;
+	}
+	45.0 / (PI * h.powi(6)) * (h - d)
+}
+
+/// CPU reference of `cs_density`, returning `(density, pressure)` per particle.
+/// Neighbours are found by brute force, so it is only suited to small sets.
+pub fn densities(settings: &SphSettings, particles: &[Particle]) -> Vec<(f32, f32)> {
+	let h = settings.smoothing_radius;
+	particles
+		.iter()
+		.map(|a| {
+			let density: f32 = particles
+				.iter()
+				.map(|b| settings.mass * poly6((a.position() - b.position()).length_squared(), h))
+				.sum();
+			let pressure = (settings.stiffness * (density - settings.rest_density)).max(0.0);
+			(density, pressure)
+		})
+		.collect()
+}
+
+/// CPU reference of one SPH step (`cs_density` followed by `cs_forces`).
+pub fn step(
+	settings: &SphSettings,
+	simulation: &SimulationSettings,
+	dt: f32,
+	particles: &mut [Particle],
+	velocities: &mut [Vec3],
+) {
+	let h = settings.smoothing_radius;
+	let densities = densities(settings, particles);
+	let previous = particles.to_vec();
+	let previous_velocities = velocities.to_vec();
+
+	for (i, (particle, velocity)) in particles.iter_mut().zip(velocities.iter_mut()).enumerate() {
+		let (density, pressure) = densities[i];
+		let mut force = Vec3::ZERO;
+		for (j, other) in previous.iter().enumerate() {
+			if i == j {
+				continue;
+			}
+			let r = previous[i].position() - other.position();
+			let (other_density, other_pressure) = densities[j];
+			force -= settings.mass * (pressure + other_pressure) / (2.0 * other_density)
+				* spiky_gradient(r, h);
+			force +=
+				settings.viscosity
+					* settings.mass * (previous_velocities[j] - previous_velocities[i])
+					/ other_density * viscosity_laplacian(r.length(), h);
+		}
+
+		*velocity += (force / density + simulation.gravity) * dt;
+		*velocity *= (1.0 - simulation.drag * dt).max(0.0);
+		let mut position = particle.position() + *velocity * dt;
+		simulation.collide(&mut position, velocity, particle.radius());
+		particle.set_position(position);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		headless,
+		simulation::{Simulation, FIXED_STEP},
+	};
+
+	/// Integrates `f` over the ball of radius `h`, for radially symmetric kernels.
+	fn integrate(h: f32, f: impl Fn(f32) -> f32) -> f32 {
+		const STEPS: usize = 10_000;
+		let dr = h / STEPS as f32;
+		(0..STEPS)
+			.map(|i| {
+				let r = (i as f32 + 0.5) * dr;
+				4.0 * PI * r * r * f(r) * dr
+			})
+			.sum()
+	}
+
+	#[test]
+	fn poly6_is_normalised() {
+		for h in [0.1, 0.2, 1.0] {
+			let total = integrate(h, |r| poly6(r * r, h));
+			assert!((total - 1.0).abs() < 1e-3, "h = {h}: {total}");
+		}
+	}
+
+	#[test]
+	fn spiky_gradient_is_gradient_of_normalised_kernel() {
+		for h in [0.1f32, 0.2, 1.0] {
+			// The spiky kernel is 15 / (π h⁶) (h - r)³, whose gradient points towards the centre
+			let kernel = |r: f32| 15.0 / (PI * h.powi(6)) * (h - r).powi(3);
+			let total = integrate(h, kernel);
+			assert!((total - 1.0).abs() < 1e-3, "h = {h}: {total}");
+
+			let r = 0.3 * h;
+			let epsilon = 1e-3 * h;
+			let slope = (kernel(r + epsilon) - kernel(r - epsilon)) / (2.0 * epsilon);
+			let gradient = spiky_gradient(Vec3::new(0.0, r, 0.0), h);
+			assert!((gradient.y - slope).abs() < 1e-3 * slope.abs(), "h = {h}");
+			assert_eq!((gradient.x, gradient.z), (0.0, 0.0));
+		}
+	}
+
+	#[test]
+	fn density_of_cubic_lattice() {
+		let settings = SphSettings {
+			smoothing_radius: 0.15,
+			rest_density: 100.0,
+			mass: 2.0,
+			..Default::default()
+		};
+		let spacing = 0.1;
+		let mut particles = vec![];
+		for x in -2..=2 {
+			for y in -2..=2 {
+				for z in -2..=2 {
+					let position = Vec3::new(x as f32, y as f32, z as f32) * spacing;
+					particles.push(Particle::new(position, 0.01));
+				}
+			}
+		}
+		let center = particles
+			.iter()
+			.position(|particle| particle.position() == Vec3::ZERO)
+			.unwrap();
+
+		// Within 1.5 spacings: itself, 6 face and 12 edge neighbours, but not the 8 corners
+		let h = settings.smoothing_radius;
+		let w = |r2: f32| 315.0 / (64.0 * PI * h.powi(9)) * (h * h - r2).powi(3);
+		let a2 = spacing * spacing;
+		let expected = settings.mass * (w(0.0) + 6.0 * w(a2) + 12.0 * w(2.0 * a2));
+
+		let (density, pressure) = densities(&settings, &particles)[center];
+		assert!(
+			(density - expected).abs() < 1e-3 * expected,
+			"{density} != {expected}"
+		);
+		let expected_pressure = settings.stiffness * (expected - settings.rest_density);
+		assert!((pressure - expected_pressure).abs() < 1e-3 * expected_pressure);
+	}
+
+	fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<u8> {
+		let staging = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Test Readback Buffer"),
+			size: buffer.size(),
+			usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+			mapped_at_creation: false,
+		});
+		let mut encoder = device.create_command_encoder(&Default::default());
+		encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+		queue.submit([encoder.finish()]);
+		staging.slice(..).map_async(wgpu::MapMode::Read, |result| {
+			result.unwrap();
+		});
+		device.poll(wgpu::PollType::Wait).unwrap();
+		let bytes = staging.slice(..).get_mapped_range().to_vec();
+		staging.unmap();
+		bytes
+	}
+
+	#[test]
+	fn gpu_step_matches_cpu() {
+		let (device, queue) =
+			pollster::block_on(headless::request_device()).expect("no adapter to test with");
+
+		// A jittered block of particles, so that every one of them feels pressure and viscosity
+		let mut particles = vec![];
+		for i in 0..125 {
+			let cell = Vec3::new((i % 5) as f32, (i / 5 % 5) as f32, (i / 25) as f32);
+			let jitter = Vec3::new((i * 7 % 11) as f32, (i * 5 % 13) as f32, (i * 3 % 7) as f32);
+			let position = cell * 0.08 + jitter * 0.002 - 0.2;
+			particles.push(Particle::new(position, 0.02));
+		}
+		let buffer = crate::particle::create_buffer(&device, &particles);
+
+		let mut simulation = Simulation::new(
+			&device,
+			&buffer,
+			particles.len() as u32,
+			&Default::default(),
+		)
+		.unwrap();
+		simulation.settings.mode = crate::simulation::SimulationMode::Sph;
+		simulation.sph.settings.rest_density = 100.0;
+
+		let mut encoder = device.create_command_encoder(&Default::default());
+		simulation.encode(&queue, &mut encoder, &buffer, 1);
+		queue.submit([encoder.finish()]);
+		let bytes = read_buffer(&device, &queue, &buffer);
+		let gpu: &[Particle] = bytemuck::cast_slice(&bytes);
+
+		let mut cpu = particles.clone();
+		let mut velocities = vec![Vec3::ZERO; particles.len()];
+		step(
+			&simulation.sph.settings,
+			&simulation.settings,
+			FIXED_STEP,
+			&mut cpu,
+			&mut velocities,
+		);
+
+		let mut moved = 0.0f32;
+		for (i, (gpu, cpu)) in gpu.iter().zip(&cpu).enumerate() {
+			let error = (gpu.position() - cpu.position()).length();
+			assert!(
+				error < 1e-5,
+				"particle {i}: {} != {}",
+				gpu.position(),
+				cpu.position()
+			);
+			moved = moved.max((cpu.position() - particles[i].position()).length());
+		}
+		// Pressure has to push harder than gravity pulls in a single step for the test to mean anything
+		assert!(moved > 10.0 * (simulation.settings.gravity * FIXED_STEP * FIXED_STEP).length());
+	}
+}
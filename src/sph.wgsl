@@ -0,0 +1,226 @@
+struct Particle {
+    position: vec3<f32>,
+    radius: f32,
+    color: vec3<f32>,
+    material: u32,
+}
+
+struct Simulation {
+    gravity: vec3<f32>,
+    dt: f32,
+    bounds_min: vec3<f32>,
+    restitution: f32,
+    bounds_max: vec3<f32>,
+    drag: f32,
+    count: u32,
+};
+
+struct Sph {
+    grid_size: vec3<u32>,
+    smoothing_radius: f32,
+    rest_density: f32,
+    stiffness: f32,
+    viscosity: f32,
+    mass: f32,
+};
+
+const PI = 3.14159265359;
+const WORKGROUP_SIZE = 64;
+
+// Must be the same as the one in sph.rs
+const MAX_PER_CELL = 64u;
+
+@group(0) @binding(0)
+var<storage, read> particles_in: array<Particle>;
+
+@group(0) @binding(1)
+var<storage, read> velocities_in: array<vec4<f32>>;
+
+@group(0) @binding(2)
+var<storage, read_write> particles_out: array<Particle>;
+
+@group(0) @binding(3)
+var<storage, read_write> velocities_out: array<vec4<f32>>;
+
+@group(0) @binding(4)
+var<uniform> u_sim: Simulation;
+
+@group(0) @binding(5)
+var<uniform> u_sph: Sph;
+
+// (density, pressure) of every particle
+@group(0) @binding(6)
+var<storage, read_write> densities: array<vec2<f32>>;
+
+@group(0) @binding(7)
+var<storage, read_write> cell_counts: array<atomic<u32>>;
+
+@group(0) @binding(8)
+var<storage, read_write> cell_entries: array<u32>;
+
+// Largest number of particles binned into a cell that didn't fit, 0 if all of them did
+@group(0) @binding(9)
+var<storage, read_write> overflow: atomic<u32>;
+
+fn poly6(r2: f32, h: f32) -> f32 {
+    let h2 = h * h;
+    if r2 >= h2 {
+        return 0.0;
+    }
+    let x = h2 - r2;
+    return 315.0 / (64.0 * PI * pow(h, 9.0)) * x * x * x;
+}
+
+fn spiky_gradient(r: vec3<f32>, d: f32, h: f32) -> vec3<f32> {
+    if d >= h || d <= 1e-6 {
+        return vec3(0.0);
+    }
+    let x = h - d;
+    return -45.0 / (PI * pow(h, 6.0)) * x * x * (r / d);
+}
+
+fn viscosity_laplacian(d: f32, h: f32) -> f32 {
+    if d >= h {
+        return 0.0;
+    }
+    return 45.0 / (PI * pow(h, 6.0)) * (h - d);
+}
+
+fn cell_of(p: vec3<f32>) -> vec3<i32> {
+    let extent = u_sim.bounds_max - u_sim.bounds_min;
+    let norm = (p - u_sim.bounds_min) / extent;
+    let cell = vec3<i32>(floor(norm * vec3<f32>(u_sph.grid_size)));
+    return clamp(cell, vec3(0), vec3<i32>(u_sph.grid_size) - 1);
+}
+
+fn cell_index(cell: vec3<i32>) -> u32 {
+    let c = vec3<u32>(cell);
+    return c.x + u_sph.grid_size.x * (c.y + u_sph.grid_size.y * c.z);
+}
+
+fn in_grid(cell: vec3<i32>) -> bool {
+    return all(cell >= vec3(0)) && all(cell < vec3<i32>(u_sph.grid_size));
+}
+
+// Reflects the particle off the walls of the simulation box.
+fn collide(particle: ptr<function, Particle>, velocity: ptr<function, vec3<f32>>) {
+    let lo = u_sim.bounds_min + (*particle).radius;
+    let hi = u_sim.bounds_max - (*particle).radius;
+    for (var axis = 0; axis < 3; axis++) {
+        if (*particle).position[axis] < lo[axis] {
+            (*particle).position[axis] = lo[axis];
+            (*velocity)[axis] = abs((*velocity)[axis]) * u_sim.restitution;
+        } else if (*particle).position[axis] > hi[axis] {
+            (*particle).position[axis] = hi[axis];
+            (*velocity)[axis] = -abs((*velocity)[axis]) * u_sim.restitution;
+        }
+    }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_clear_grid(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= arrayLength(&cell_counts) {
+        return;
+    }
+    atomicStore(&cell_counts[id.x], 0u);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_bin(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= u_sim.count {
+        return;
+    }
+    let cell = cell_index(cell_of(particles_in[i].position));
+    let slot = atomicAdd(&cell_counts[cell], 1u);
+    // Particles past the capacity of a cell are left out of the neighbour search
+    if slot < MAX_PER_CELL {
+        cell_entries[cell * MAX_PER_CELL + slot] = i;
+    } else {
+        atomicMax(&overflow, slot + 1u);
+    }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_density(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= u_sim.count {
+        return;
+    }
+    let h = u_sph.smoothing_radius;
+    let p = particles_in[i].position;
+    let center = cell_of(p);
+
+    var density = 0.0;
+    for (var z = -1; z <= 1; z++) {
+        for (var y = -1; y <= 1; y++) {
+            for (var x = -1; x <= 1; x++) {
+                let cell = center + vec3(x, y, z);
+                if !in_grid(cell) {
+                    continue;
+                }
+                let index = cell_index(cell);
+                let count = min(atomicLoad(&cell_counts[index]), MAX_PER_CELL);
+                for (var k = 0u; k < count; k++) {
+                    let j = cell_entries[index * MAX_PER_CELL + k];
+                    let r = p - particles_in[j].position;
+                    density += u_sph.mass * poly6(dot(r, r), h);
+                }
+            }
+        }
+    }
+    // Only repulsive pressure, clamping avoids particles clumping together
+    let pressure = max(u_sph.stiffness * (density - u_sph.rest_density), 0.0);
+    densities[i] = vec2(density, pressure);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn cs_forces(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= u_sim.count {
+        return;
+    }
+    let h = u_sph.smoothing_radius;
+    var particle = particles_in[i];
+    var velocity = velocities_in[i].xyz;
+    let center = cell_of(particle.position);
+    let density = densities[i].x;
+    let pressure = densities[i].y;
+
+    var force = vec3(0.0);
+    for (var z = -1; z <= 1; z++) {
+        for (var y = -1; y <= 1; y++) {
+            for (var x = -1; x <= 1; x++) {
+                let cell = center + vec3(x, y, z);
+                if !in_grid(cell) {
+                    continue;
+                }
+                let index = cell_index(cell);
+                let count = min(atomicLoad(&cell_counts[index]), MAX_PER_CELL);
+                for (var k = 0u; k < count; k++) {
+                    let j = cell_entries[index * MAX_PER_CELL + k];
+                    if j == i {
+                        continue;
+                    }
+                    let r = particle.position - particles_in[j].position;
+                    let d = length(r);
+                    let other = densities[j];
+                    force -= u_sph.mass * (pressure + other.y) / (2.0 * other.x)
+                        * spiky_gradient(r, d, h);
+                    force += u_sph.viscosity * u_sph.mass
+                        * (velocities_in[j].xyz - velocity) / other.x
+                        * viscosity_laplacian(d, h);
+                }
+            }
+        }
+    }
+
+    // Semi-implicit Euler
+    velocity += (force / density + u_sim.gravity) * u_sim.dt;
+    velocity *= max(1.0 - u_sim.drag * u_sim.dt, 0.0);
+    particle.position += velocity * u_sim.dt;
+    collide(&particle, &velocity);
+
+    particles_out[i] = particle;
+    velocities_out[i] = vec4(velocity, 0.0);
+}
@@ -1,9 +1,17 @@
-use crate::{
-	camera::{self, Camera},
-	particle, screen, sdf, time,
+use crate::app::WindowOptions;
+use glam::{UVec2, Vec2, Vec3};
+use std::{collections::HashSet, path::PathBuf, sync::Arc, time::Instant};
+use wgpu_raymarcher::{
+	binning::SdfMethod,
+	camera::Camera,
+	hdr::HdrImage,
+	particle::Particle,
+	renderer::Renderer,
+	scene::{Scene, MAX_FROXELS},
+	shaders::{self, ShaderError, ShaderWatcher},
+	simulation::SimulationMode,
+	time,
 };
-use glam::{Vec2, Vec3};
-use std::{collections::HashSet, num::NonZero, sync::Arc, time::Instant};
 use winit::{
 	dpi::PhysicalPosition,
 	event::{ElementState, RawKeyEvent},
@@ -11,34 +19,30 @@ use winit::{
 	window::{CursorGrabMode, Window},
 };
 
+/// Pixels per froxel when switching to a grid following the window.
+const DEFAULT_FROXEL_PIXELS: u32 = 8;
+const MAX_FROXEL_PIXELS: u32 = 256;
+
+/// Title of the window while the shaders are fine.
+pub const TITLE: &str = "wgpu_raymarcher";
+
 pub struct State {
 	window: Arc<Window>,
-	device: wgpu::Device,
-	queue: wgpu::Queue,
 	size: winit::dpi::PhysicalSize<u32>,
 	surface: wgpu::Surface<'static>,
 	surface_format: wgpu::TextureFormat,
-	compute_clear_pipeline: wgpu::ComputePipeline,
-	compute_calc_pipeline: wgpu::ComputePipeline,
-	render_pipeline: wgpu::RenderPipeline,
-	compute_write_tmp_group: wgpu::BindGroup,
-	compute_write_main_group: wgpu::BindGroup,
-	render_group: wgpu::BindGroup,
-	particles_buffer: wgpu::Buffer,
-	screen_buffer: wgpu::Buffer,
-	time_buffer: wgpu::Buffer,
-	camera_buffer: wgpu::Buffer,
+	present_mode: wgpu::PresentMode,
+	renderer: Renderer,
 	start_time: std::time::Instant,
 	last_time: std::time::Instant,
 	camera: Camera,
 	input: Input,
 	locked: bool,
+	scene: Scene,
+	save_path: Option<PathBuf>,
+	shader_watcher: Option<ShaderWatcher>,
 }
 
-const T_WIDTH: u32 = 64;
-const T_HEIGHT: u32 = 64;
-const T_DEPTH: u32 = 256;
-
 #[derive(Default)]
 struct Input {
 	keys: HashSet<KeyCode>,
@@ -77,322 +81,69 @@ impl Input {
 }
 
 impl State {
-	pub async fn new(window: Arc<Window>) -> State {
+	pub async fn new(
+		window: Arc<Window>,
+		particles: &[Particle],
+		environment: Option<&HdrImage>,
+		options: &WindowOptions,
+	) -> Result<State, ShaderError> {
 		let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
 		let adapter = instance
 			.request_adapter(&wgpu::RequestAdapterOptions::default())
 			.await
 			.unwrap();
-		let (device, queue) = adapter
-			.request_device(&wgpu::DeviceDescriptor {
-				required_limits: wgpu::Limits {
-					min_storage_buffer_offset_alignment: particle::BUNDLE_SIZE_BYTES,
-					..wgpu::Limits::default()
-				},
-				..Default::default()
-			})
-			.await
-			.unwrap();
+		let (device, queue) = adapter.request_device(&Default::default()).await.unwrap();
 
 		let size = window.inner_size();
 		let surface = instance.create_surface(window.clone()).unwrap();
 		let cap = surface.get_capabilities(&adapter);
 		let surface_format = cap.formats[0];
 
-		let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-			label: Some("Raymarch Compute Shader"),
-			source: wgpu::ShaderSource::Wgsl(
-				std::fs::read_to_string("src/compute.wgsl").unwrap().into(),
-			),
-		});
-
-		let render_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-			label: Some("Raymarch Render Shader"),
-			source: wgpu::ShaderSource::Wgsl(
-				std::fs::read_to_string("src/shader.wgsl").unwrap().into(),
-			),
-		});
-
-		let compute_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-			label: Some("Compute Layout Group"),
-			entries: &[
-				wgpu::BindGroupLayoutEntry {
-					binding: 0,
-					visibility: wgpu::ShaderStages::COMPUTE,
-					ty: wgpu::BindingType::Buffer {
-						ty: wgpu::BufferBindingType::Storage { read_only: true },
-						has_dynamic_offset: true,
-						min_binding_size: None,
-					},
-					count: None,
-				},
-				wgpu::BindGroupLayoutEntry {
-					binding: 1,
-					visibility: wgpu::ShaderStages::COMPUTE,
-					ty: wgpu::BindingType::StorageTexture {
-						access: wgpu::StorageTextureAccess::WriteOnly,
-						format: wgpu::TextureFormat::Rgba16Float,
-						view_dimension: wgpu::TextureViewDimension::D3,
-					},
-					count: None,
-				},
-				wgpu::BindGroupLayoutEntry {
-					binding: 2,
-					visibility: wgpu::ShaderStages::COMPUTE,
-					ty: wgpu::BindingType::Texture {
-						sample_type: wgpu::TextureSampleType::Float { filterable: false },
-						view_dimension: wgpu::TextureViewDimension::D3,
-						multisampled: false,
-					},
-					count: None,
-				},
-				wgpu::BindGroupLayoutEntry {
-					binding: 3,
-					visibility: wgpu::ShaderStages::COMPUTE,
-					ty: wgpu::BindingType::Buffer {
-						ty: wgpu::BufferBindingType::Uniform,
-						has_dynamic_offset: false,
-						min_binding_size: None,
-					},
-					count: None,
-				},
-			],
-		});
-
-		let render_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-			label: Some("Render Layout Group"),
-			entries: &[
-				wgpu::BindGroupLayoutEntry {
-					binding: 0,
-					visibility: wgpu::ShaderStages::FRAGMENT,
-					ty: wgpu::BindingType::Buffer {
-						ty: wgpu::BufferBindingType::Uniform,
-						has_dynamic_offset: false,
-						min_binding_size: None,
-					},
-					count: None,
-				},
-				wgpu::BindGroupLayoutEntry {
-					binding: 1,
-					visibility: wgpu::ShaderStages::FRAGMENT,
-					ty: wgpu::BindingType::Buffer {
-						ty: wgpu::BufferBindingType::Uniform,
-						has_dynamic_offset: false,
-						min_binding_size: None,
-					},
-					count: None,
-				},
-				wgpu::BindGroupLayoutEntry {
-					binding: 2,
-					visibility: wgpu::ShaderStages::FRAGMENT,
-					ty: wgpu::BindingType::Buffer {
-						ty: wgpu::BufferBindingType::Uniform,
-						has_dynamic_offset: false,
-						min_binding_size: None,
-					},
-					count: None,
-				},
-				wgpu::BindGroupLayoutEntry {
-					binding: 3,
-					visibility: wgpu::ShaderStages::FRAGMENT,
-					ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-					count: None,
-				},
-				wgpu::BindGroupLayoutEntry {
-					binding: 4,
-					visibility: wgpu::ShaderStages::FRAGMENT,
-					ty: wgpu::BindingType::Texture {
-						sample_type: wgpu::TextureSampleType::Float { filterable: true },
-						view_dimension: wgpu::TextureViewDimension::D3,
-						multisampled: false,
-					},
-					count: None,
-				},
-			],
-		});
-
-		let compute_pipeline_layout =
-			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-				label: Some("Raymarch Compute Pipeline Layout"),
-				bind_group_layouts: &[&compute_layout],
-				push_constant_ranges: &[],
-			});
-
-		let render_pipeline_layout =
-			device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-				label: Some("Raymarch Render Pipeline Layout"),
-				bind_group_layouts: &[&render_layout],
-				push_constant_ranges: &[],
-			});
-
-		let compute_clear_pipeline =
-			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-				label: Some("Compute Pipeline (Clear)"),
-				layout: Some(&compute_pipeline_layout),
-				module: &compute_shader,
-				entry_point: Some("cs_clear"),
-				compilation_options: Default::default(),
-				cache: Default::default(),
-			});
-
-		let compute_calc_pipeline =
-			device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-				label: Some("Compute Pipeline (Calc SDF)"),
-				layout: Some(&compute_pipeline_layout),
-				module: &compute_shader,
-				entry_point: Some("cs_sdf"),
-				compilation_options: Default::default(),
-				cache: Default::default(),
-			});
+		let scene = options.scene.clone();
+		let mut renderer = Renderer::new(
+			&device,
+			&queue,
+			surface_format.add_srgb_suffix(),
+			particles,
+			scene.froxels((size.width, size.height)),
+			&options.shaders,
+		)?;
+		renderer.blend = scene.blend();
+		renderer.slicing = scene.render.slicing;
+		renderer.set_world(scene.world());
+		renderer.set_space(scene.render.space);
+		renderer.lighting = scene.lighting();
+		renderer.materials = scene.materials();
+		if let Some(image) = environment {
+			renderer.set_environment(image);
+		}
+		renderer.environment.intensity = scene.background.environment_intensity;
 
-		let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-			label: Some("Render Pipeline"),
-			layout: Some(&render_pipeline_layout),
-			vertex: wgpu::VertexState {
-				module: &render_shader,
-				entry_point: Some("vs_main"),
-				compilation_options: Default::default(),
-				buffers: &[],
-			},
-			fragment: Some(wgpu::FragmentState {
-				module: &render_shader,
-				entry_point: Some("fs_main"),
-				compilation_options: Default::default(),
-				targets: &[Some(wgpu::ColorTargetState {
-					format: surface_format,
-					blend: Some(wgpu::BlendState::REPLACE),
-					write_mask: wgpu::ColorWrites::ALL,
-				})],
-			}),
-			primitive: Default::default(),
-			multisample: Default::default(),
-			depth_stencil: Default::default(),
-			multiview: Default::default(),
-			cache: Default::default(),
-		});
-
-		let screen_buffer = screen::create_buffer(&device);
-		let camera_buffer = camera::create_buffer(&device);
-		let time_buffer = time::create_buffer(&device);
-		let particles_buffer = particle::create_buffer(&device);
-
-		let sdf_tmp_texture = sdf::create_texture(&device, T_WIDTH, T_HEIGHT, T_DEPTH);
-		let sdf_tmp_view = sdf::create_view(&sdf_tmp_texture);
-		let sdf_texture = sdf::create_texture(&device, T_WIDTH, T_HEIGHT, T_DEPTH);
-		let sdf_view = sdf::create_view(&sdf_texture);
-		let sdf_sampler = sdf::create_sampler(&device);
-
-		let compute_write_tmp_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-			label: Some("Compute Group (Write to Temp)"),
-			layout: &compute_layout,
-			entries: &[
-				wgpu::BindGroupEntry {
-					binding: 0,
-					resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-						buffer: &particles_buffer,
-						offset: 0,
-						size: Some(NonZero::new(particle::BUNDLE_SIZE_BYTES as u64).unwrap()),
-					}),
-				},
-				wgpu::BindGroupEntry {
-					binding: 1,
-					resource: wgpu::BindingResource::TextureView(&sdf_tmp_view),
-				},
-				wgpu::BindGroupEntry {
-					binding: 2,
-					resource: wgpu::BindingResource::TextureView(&sdf_view),
-				},
-				wgpu::BindGroupEntry {
-					binding: 3,
-					resource: camera_buffer.as_entire_binding(),
-				},
-			],
-		});
-
-		let compute_write_main_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-			label: Some("Compute Group (Write to Main)"),
-			layout: &compute_layout,
-			entries: &[
-				wgpu::BindGroupEntry {
-					binding: 0,
-					resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-						buffer: &particles_buffer,
-						offset: 0,
-						size: Some(NonZero::new(particle::BUNDLE_SIZE_BYTES as u64).unwrap()),
-					}),
-				},
-				wgpu::BindGroupEntry {
-					binding: 1,
-					resource: wgpu::BindingResource::TextureView(&sdf_view),
-				},
-				wgpu::BindGroupEntry {
-					binding: 2,
-					resource: wgpu::BindingResource::TextureView(&sdf_tmp_view),
-				},
-				wgpu::BindGroupEntry {
-					binding: 3,
-					resource: camera_buffer.as_entire_binding(),
-				},
-			],
-		});
-
-		let render_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-			label: Some("Render Group"),
-			layout: &render_layout,
-			entries: &[
-				wgpu::BindGroupEntry {
-					binding: 0,
-					resource: screen_buffer.as_entire_binding(),
-				},
-				wgpu::BindGroupEntry {
-					binding: 1,
-					resource: camera_buffer.as_entire_binding(),
-				},
-				wgpu::BindGroupEntry {
-					binding: 2,
-					resource: time_buffer.as_entire_binding(),
-				},
-				wgpu::BindGroupEntry {
-					binding: 3,
-					resource: wgpu::BindingResource::Sampler(&sdf_sampler),
-				},
-				wgpu::BindGroupEntry {
-					binding: 4,
-					resource: wgpu::BindingResource::TextureView(&sdf_view),
-				},
-			],
-		});
-
-		let camera = Camera::new();
-
-		let state = State {
+		let mut state = State {
 			window,
-			device,
-			queue,
 			size,
 			surface,
 			surface_format,
-			compute_clear_pipeline,
-			compute_calc_pipeline,
-			render_pipeline,
-			compute_write_tmp_group,
-			compute_write_main_group,
-			render_group,
-			particles_buffer,
-			screen_buffer,
-			time_buffer,
-			camera_buffer,
+			present_mode: options.present_mode,
+			renderer,
 			start_time: Instant::now(),
 			last_time: Instant::now(),
 			input: Default::default(),
-			camera,
+			camera: scene.camera(),
 			locked: false,
+			scene,
+			save_path: options.save_path.clone(),
+			shader_watcher: ShaderWatcher::new(
+				&options.shaders,
+				&[shaders::COMPUTE, shaders::RENDER],
+			),
 		};
 
 		// Configure surface for the first time
 		state.configure_surface();
+		state.store_froxels();
 
-		state
+		Ok(state)
 	}
 
 	pub fn window(&self) -> &Window {
@@ -400,18 +151,21 @@ impl State {
 	}
 
 	fn configure_surface(&self) {
+		// Surfaces can't outgrow the GPU's textures, a larger window is drawn at the largest size
+		let max = self.renderer.device().limits().max_texture_dimension_2d;
 		let surface_config = wgpu::SurfaceConfiguration {
 			usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
 			format: self.surface_format,
 			// Request compatibility with the sRGB-format texture view we‘re going to create later.
 			view_formats: vec![self.surface_format.add_srgb_suffix()],
 			alpha_mode: wgpu::CompositeAlphaMode::Auto,
-			width: self.size.width,
-			height: self.size.height,
+			width: self.size.width.min(max),
+			height: self.size.height.min(max),
 			desired_maximum_frame_latency: 3,
-			present_mode: wgpu::PresentMode::Immediate,
+			present_mode: self.present_mode,
 		};
-		self.surface.configure(&self.device, &surface_config);
+		self.surface
+			.configure(self.renderer.device(), &surface_config);
 	}
 
 	pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -419,20 +173,169 @@ impl State {
 
 		// reconfigure the surface
 		self.configure_surface();
+		self.update_froxels();
+	}
+
+	/// Rebuilds the SDF if the scene's froxel grid changed, or the window did while it follows it.
+	fn update_froxels(&mut self) {
+		let froxels = self.scene.froxels((self.size.width, self.size.height));
+		if froxels != self.renderer.froxels() {
+			self.renderer.set_froxels(froxels);
+			let used = self.renderer.froxels();
+			if used == froxels {
+				println!("Froxels: {}x{}x{}", used.x, used.y, used.z);
+			} else {
+				println!(
+					"Froxels: {}x{}x{}, reduced from {}x{}x{} to fit the GPU",
+					used.x, used.y, used.z, froxels.x, froxels.y, froxels.z
+				);
+			}
+		}
+		self.store_froxels();
+	}
+
+	/// Keeps the scene's froxel grid at the one in use, so a saved scene holds what was rendered.
+	fn store_froxels(&mut self) {
+		let used = self.renderer.froxels();
+		let render = &mut self.scene.render;
+		render.froxels = match render.froxel_pixels {
+			Some(_) => render.froxels.truncate().extend(used.z),
+			None => used,
+		};
+	}
+
+	/// Makes the froxel grid finer (`finer`) or coarser by a factor of two across the screen.
+	fn scale_froxels(&mut self, finer: bool) {
+		let render = &mut self.scene.render;
+		match &mut render.froxel_pixels {
+			Some(pixels) => {
+				*pixels = if finer { *pixels / 2 } else { *pixels * 2 }.clamp(1, MAX_FROXEL_PIXELS);
+			}
+			None => {
+				// Start from the grid in use, it may have been reduced to fit the GPU
+				let xy = self.renderer.froxels().truncate();
+				let xy = if finer { xy * 2 } else { xy / 2 };
+				let xy = xy.clamp(UVec2::ONE, UVec2::splat(MAX_FROXELS));
+				render.froxels = xy.extend(render.froxels.z);
+			}
+		}
+		self.update_froxels();
 	}
 
 	pub fn keyboard(&mut self, ev: RawKeyEvent) {
-		match ev.physical_key {
-			winit::keyboard::PhysicalKey::Code(key_code) => {
-				match ev.state {
-					ElementState::Pressed => self.input.keys.insert(key_code),
-					ElementState::Released => self.input.keys.remove(&key_code),
+		if let winit::keyboard::PhysicalKey::Code(key_code) = ev.physical_key {
+			match ev.state {
+				ElementState::Pressed => {
+					if self.input.keys.insert(key_code) {
+						self.key_down(key_code);
+					}
+				}
+				ElementState::Released => {
+					self.input.keys.remove(&key_code);
+				}
+			};
+		}
+	}
+
+	fn key_down(&mut self, key_code: KeyCode) {
+		match key_code {
+			KeyCode::KeyO => self.save_scene(),
+			KeyCode::KeyF => {
+				let render = &mut self.scene.render;
+				render.froxel_pixels = match render.froxel_pixels {
+					Some(_) => None,
+					None => Some(DEFAULT_FROXEL_PIXELS),
+				};
+				match render.froxel_pixels {
+					Some(pixels) => println!("Froxels follow the window, {pixels}px each"),
+					None => println!("Froxels fixed"),
+				}
+				self.update_froxels();
+			}
+			KeyCode::KeyL => {
+				self.renderer.slicing = self.renderer.slicing.next();
+				self.scene.render.slicing = self.renderer.slicing;
+				println!("Slicing: {:?}", self.renderer.slicing);
+			}
+			KeyCode::KeyV => {
+				let space = self.renderer.space().next();
+				self.renderer.set_space(space);
+				self.scene.render.space = space;
+				println!("SDF space: {space:?}");
+			}
+			KeyCode::KeyH => {
+				let shadows = &mut self.renderer.lighting.shadows;
+				shadows.mode = shadows.mode.next();
+				self.scene.shadows.mode = shadows.mode;
+				println!("Shadows: {:?}", shadows.mode);
+			}
+			KeyCode::KeyK => {
+				let occlusion = &mut self.renderer.lighting.occlusion;
+				occlusion.mode = occlusion.mode.next();
+				self.scene.occlusion.mode = occlusion.mode;
+				println!("Ambient occlusion: {:?}", occlusion.mode);
+			}
+			KeyCode::KeyG => {
+				let materials = &mut self.renderer.materials;
+				materials.shading = materials.shading.next();
+				self.scene.render.shading = materials.shading;
+				println!("Shading: {:?}", materials.shading);
+			}
+			KeyCode::Minus => self.scale_froxels(false),
+			KeyCode::Equal => self.scale_froxels(true),
+			KeyCode::KeyB => {
+				self.renderer.blend.mode = self.renderer.blend.mode.next();
+				println!("Blend mode: {:?}", self.renderer.blend.mode);
+			}
+			KeyCode::BracketLeft => {
+				self.renderer.blend.radius = (self.renderer.blend.radius - 0.01).max(0.0);
+				println!("Blend radius: {:.2}", self.renderer.blend.radius);
+			}
+			KeyCode::BracketRight => {
+				self.renderer.blend.radius += 0.01;
+				println!("Blend radius: {:.2}", self.renderer.blend.radius);
+			}
+			KeyCode::KeyP => {
+				self.renderer.simulation.settings.paused =
+					!self.renderer.simulation.settings.paused;
+				println!(
+					"Simulation paused: {}",
+					self.renderer.simulation.settings.paused
+				);
+			}
+			KeyCode::KeyT => {
+				self.renderer.sdf_method = match self.renderer.sdf_method {
+					SdfMethod::Bundled => SdfMethod::Binned,
+					SdfMethod::Binned => SdfMethod::Bundled,
+				};
+				println!("SDF method: {:?}", self.renderer.sdf_method);
+			}
+			KeyCode::KeyM => {
+				let settings = &mut self.renderer.simulation.settings;
+				settings.mode = match settings.mode {
+					SimulationMode::Ballistic => SimulationMode::Sph,
+					SimulationMode::Sph => SimulationMode::Ballistic,
 				};
+				println!("Simulation mode: {:?}", settings.mode);
 			}
 			_ => {}
 		}
 	}
 
+	/// Writes the scene back out with the current camera pose, blend and froxel settings.
+	fn save_scene(&mut self) {
+		let Some(path) = &self.save_path else {
+			println!("No scene path to save to, pass --save-scene");
+			return;
+		};
+		self.scene.set_camera(&self.camera);
+		self.scene.set_blend(&self.renderer.blend);
+		match self.scene.save(path) {
+			Ok(()) => println!("Saved scene to {}", path.display()),
+			Err(err) => eprintln!("Failed to save scene to {}: {err}", path.display()),
+		}
+	}
+
 	pub fn mouse(&mut self, (x, y): (f64, f64)) {
 		self.input.mouse_delta += Vec2 {
 			x: x as f32,
@@ -452,7 +355,30 @@ impl State {
 		self.locked = false;
 	}
 
+	/// Swaps in edited shaders. A broken edit is reported in the log and the
+	/// window title while the previous pipelines keep rendering.
+	fn reload_shaders(&mut self) {
+		let Some(watcher) = &mut self.shader_watcher else {
+			return;
+		};
+		if !watcher.changed() {
+			return;
+		}
+		match self.renderer.reload_shaders() {
+			Ok(()) => {
+				println!("Reloaded shaders");
+				self.window.set_title(TITLE);
+			}
+			Err(err) => {
+				eprintln!("Shader reload failed: {err}");
+				self.window
+					.set_title(&format!("{TITLE} - shader error: {err}"));
+			}
+		}
+	}
+
 	fn update(&mut self) {
+		self.reload_shaders();
 		let now_time = Instant::now();
 		let time_delta = now_time - self.last_time;
 		let mouse_delta = if self.locked {
@@ -488,75 +414,21 @@ impl State {
 				..Default::default()
 			});
 
-		let u_screen = screen::ScreenUniform::new(self.window.inner_size());
-		let u_time = time::TimeUniform::since(&self.start_time);
-		let u_camera = self.camera.uniform();
-
-		let u_screen = u_screen.bytes();
-		let u_time = u_time.bytes();
-		let u_camera = u_camera.bytes();
-
-		self.queue.write_buffer(&self.screen_buffer, 0, u_screen);
-		self.queue.write_buffer(&self.time_buffer, 0, u_time);
-		self.queue.write_buffer(&self.camera_buffer, 0, u_camera);
-
-		let mut encoder = self.device.create_command_encoder(&Default::default());
-
-		// Compute Pass
-		{
-			let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-				label: Some("Compute Pass"),
-				timestamp_writes: None,
-			});
-
-			let (wg_x, wg_y, wg_z) = (8, 4, 4);
-			let dispatch_x = (T_WIDTH + wg_x - 1) / wg_x;
-			let dispatch_y = (T_HEIGHT + wg_y - 1) / wg_y;
-			let dispatch_z = (T_DEPTH + wg_z - 1) / wg_z;
-
-			pass.set_pipeline(&self.compute_clear_pipeline);
-			pass.set_bind_group(0, &self.compute_write_main_group, &[0]);
-			pass.dispatch_workgroups(dispatch_x, dispatch_y, dispatch_z);
-
-			pass.set_pipeline(&self.compute_calc_pipeline);
-
-			let mut offset = 0;
-			let mut mode = true;
-			while offset < self.particles_buffer.size() as u32 {
-				if mode {
-					pass.set_bind_group(0, &self.compute_write_tmp_group, &[offset]);
-					pass.dispatch_workgroups(dispatch_x, dispatch_y, dispatch_z);
-				} else {
-					pass.set_bind_group(0, &self.compute_write_main_group, &[offset]);
-					pass.dispatch_workgroups(dispatch_x, dispatch_y, dispatch_z);
-				}
-				mode = !mode;
-				offset += particle::BUNDLE_SIZE_BYTES;
-			}
-		}
-
-		// Render Pass
-		{
-			let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-				label: Some("Render Pass"),
-				color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-					view: &texture_view,
-					resolve_target: None,
-					ops: wgpu::Operations {
-						load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-						store: wgpu::StoreOp::Store,
-					},
-				})],
-				timestamp_writes: None,
-				depth_stencil_attachment: None,
-				occlusion_query_set: None,
-			});
-			pass.set_bind_group(0, &self.render_group, &[]);
-			pass.set_pipeline(&self.render_pipeline);
-			pass.draw(0..6, 0..1);
-		}
-
-		self.queue.submit([encoder.finish()]);
+		let mut encoder = self
+			.renderer
+			.device()
+			.create_command_encoder(&Default::default());
+
+		let size = surface_texture.texture.size();
+		self.renderer.render(
+			&mut encoder,
+			&texture_view,
+			(size.width, size.height),
+			&self.camera,
+			time::TimeUniform::since(&self.start_time),
+		);
+
+		self.renderer.queue().submit([encoder.finish()]);
 		self.window.pre_present_notify();
 		surface_texture.present();
 
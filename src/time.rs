@@ -12,6 +12,12 @@ impl TimeUniform {
 			s: since.elapsed().as_secs_f32(),
 		}
 	}
+	pub fn from_seconds(s: f32) -> Self {
+		Self { s }
+	}
+	pub fn seconds(&self) -> f32 {
+		self.s
+	}
 	pub fn bytes(&self) -> &[u8] {
 		bytemuck::bytes_of(self)
 	}